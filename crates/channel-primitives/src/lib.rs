@@ -1,6 +1,6 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
-pub use xp_format::{XbiFormat, XbiMetadata, XbiResult};
+pub use xp_format::{VersionedXbiFormat, XbiFormat, XbiMetadata, XbiResult, XbiVersion};
 
 #[cfg(feature = "frame")]
 pub use xcm::{
@@ -9,15 +9,18 @@ pub use xcm::{
 };
 
 use codec::{Decode, Encode};
+use queue::QueueSignal;
 use scale_info::TypeInfo;
-use sp_runtime::{sp_std, Either};
+use sp_runtime::{sp_std, DispatchError, Either};
 use sp_std::prelude::*;
+use xp_format::{Status, XbiFormatV1, XbiMetadataV1, XbiTimeSheet};
 
 pub mod queue;
 pub mod traits;
 
 /// A message containing a request or a response
 #[derive(Clone, Eq, PartialEq, Encode, Decode, TypeInfo, Debug)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
 pub enum Message {
     Request(XbiFormat),
     Response(XbiResult, XbiMetadata),
@@ -50,6 +53,83 @@ impl Message {
     }
 }
 
+/// A message as it is sent to a peer, in a version of the format the peer understands
+///
+/// The first two variants are encoded exactly as the unversioned `Message` of V1, so that V1 peers understand what they
+/// are sent and what they send is understood.
+#[derive(Clone, Eq, PartialEq, Encode, Decode, TypeInfo, Debug)]
+pub enum VersionedMessage {
+    /// A request to or from a peer on V1
+    #[codec(index = 0)]
+    LegacyRequest(XbiFormatV1),
+    /// A response to or from a peer on a version before V3, its metadata cannot describe a route
+    #[codec(index = 1)]
+    LegacyResponse(XbiResult, XbiMetadataV1),
+    /// A response without the costs it reports, from V3 responses are sent as a `Result` instruction in a `Request`
+    /// instead, this is still received from peers that send it.
+    #[codec(index = 2)]
+    Response(XbiResult, XbiMetadata),
+    /// A request to or from a peer from V2, tagged with its version
+    #[codec(index = 3)]
+    Request(VersionedXbiFormat),
+}
+
+impl VersionedMessage {
+    /// The version of the request, if the message is a request rather than a response
+    pub fn request_version(&self) -> Option<XbiVersion> {
+        match self {
+            VersionedMessage::LegacyRequest(_) => Some(1),
+            VersionedMessage::Request(format) if !format.is_result() => Some(format.version()),
+            VersionedMessage::Request(_)
+            | VersionedMessage::LegacyResponse(..)
+            | VersionedMessage::Response(..) => None,
+        }
+    }
+}
+
+impl Message {
+    /// Prepare the message for a peer on the given version, failing if the peer cannot understand it
+    pub fn into_version(self, version: XbiVersion) -> Result<VersionedMessage, &'static str> {
+        match self {
+            Message::Request(format) => match version {
+                1 => Ok(VersionedMessage::LegacyRequest(XbiFormatV1::try_from(
+                    format,
+                )?)),
+                _ => Ok(VersionedMessage::Request(
+                    VersionedXbiFormat::from(format).into_version(version)?,
+                )),
+            },
+            Message::Response(result, metadata) => match version {
+                1 | 2 => Ok(VersionedMessage::LegacyResponse(
+                    result,
+                    XbiMetadataV1::try_from(metadata)?,
+                )),
+                3 => Ok(VersionedMessage::Request(
+                    XbiFormat::from_result(result, metadata).into(),
+                )),
+                _ => Err("Unsupported XBI version"),
+            },
+        }
+    }
+}
+
+impl From<VersionedMessage> for Message {
+    fn from(msg: VersionedMessage) -> Self {
+        match msg {
+            VersionedMessage::LegacyRequest(format) => Message::Request(format.into()),
+            // Results are responses, with the costs reported by the destination
+            VersionedMessage::Request(format) => match XbiFormat::from(format).into_response() {
+                Ok((result, metadata)) => Message::Response(result, metadata),
+                Err(format) => Message::Request(format),
+            },
+            VersionedMessage::LegacyResponse(result, metadata) => {
+                Message::Response(result, metadata.into())
+            }
+            VersionedMessage::Response(result, metadata) => Message::Response(result, metadata),
+        }
+    }
+}
+
 /// A trait to allow emitting events or handling of events along the step of a message's lifecycle.
 pub trait ChannelProgressionEmitter {
     /// This is emitted after sending the message over the transport protocol
@@ -60,6 +140,15 @@ pub trait ChannelProgressionEmitter {
     fn emit_instruction_handled(msg: &XbiFormat, weight: &u64);
     /// Emitted when the request is handled
     fn emit_request_handled(result: &XbiResult, metadata: &XbiMetadata, weight: &u64);
+    /// Emitted when the message could not be sent over the transport protocol
+    #[cfg(feature = "frame")]
+    fn emit_send_failed(metadata: &XbiMetadata, error: &XcmError);
+    /// Emitted when the fees reserved for the message could not be refunded
+    fn emit_refund_failed(metadata: &XbiMetadata, error: &DispatchError);
+    /// Emitted when the message breached one of its timeouts
+    fn emit_timed_out(metadata: &XbiMetadata, status: &Status);
+    /// Emitted when the message was dropped because the queue is full
+    fn emit_queue_overflow(metadata: &XbiMetadata);
 }
 
 // Noop implementation
@@ -71,6 +160,15 @@ impl ChannelProgressionEmitter for () {
     fn emit_received(_msg: Either<&XbiFormat, &XbiResult>) {}
 
     fn emit_sent(_msg: Message) {}
+
+    #[cfg(feature = "frame")]
+    fn emit_send_failed(_metadata: &XbiMetadata, _error: &XcmError) {}
+
+    fn emit_refund_failed(_metadata: &XbiMetadata, _error: &DispatchError) {}
+
+    fn emit_timed_out(_metadata: &XbiMetadata, _status: &Status) {}
+
+    fn emit_queue_overflow(_metadata: &XbiMetadata) {}
 }
 
 /// An type representing ways by which we can handle the channel
@@ -81,6 +179,25 @@ pub enum ExecutionType {
     Async,
 }
 
+/// The most messages that are returned by, or can be asked of, a single query of what a parachain knows of its messages
+pub const MAX_QUERIED_MESSAGES: u32 = 256;
+
+/// What a parachain knows of a message, from its request to its result
+#[derive(Clone, Eq, PartialEq, Debug, Default, Encode, Decode, TypeInfo)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
+pub struct MessageLifecycle {
+    /// The request, whilst it is in-flight from this parachain
+    pub request: Option<XbiFormat>,
+    /// The timesheet of the latest copy of the message this parachain holds
+    pub timesheet: Option<XbiTimeSheet<u32>>,
+    /// The signal of the message, whilst it is queued
+    pub signal: Option<QueueSignal>,
+    /// The status the message failed to be transported with, whilst it is dead-lettered
+    pub dead_letter: Option<Status>,
+    /// The result of the message, once it is in
+    pub result: Option<XbiResult>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -94,4 +211,167 @@ mod tests {
         let msg = Message::Response(Default::default(), Default::default());
         assert_eq!(msg.get_metadata(), &XbiMetadata::default());
     }
+
+    #[test]
+    fn message_can_be_versioned_for_peers() {
+        let msg = Message::Request(Default::default());
+
+        let v1 = msg.clone().into_version(1).unwrap();
+        assert!(matches!(v1, VersionedMessage::LegacyRequest(_)));
+        assert_eq!(v1.request_version(), Some(1));
+        assert_eq!(Message::from(v1), msg);
+        assert!(msg.into_version(0).is_err());
+
+        let msg = Message::Response(Default::default(), Default::default());
+        let v1 = msg.clone().into_version(1).unwrap();
+        assert!(matches!(v1, VersionedMessage::LegacyResponse(..)));
+        assert_eq!(Message::from(v1), msg);
+        let v3 = msg.clone().into_version(3).unwrap();
+        assert!(matches!(v3, VersionedMessage::Request(ref format) if format.is_result()));
+        assert_eq!(Message::from(v3), msg);
+        assert!(msg.into_version(0).is_err());
+
+        let mut metadata = XbiMetadata::default();
+        metadata.route = vec![3];
+        let routed = Message::Response(Default::default(), metadata);
+        assert!(routed.clone().into_version(2).is_err());
+        assert_eq!(
+            Message::from(routed.clone().into_version(3).unwrap()),
+            routed
+        );
+    }
+
+    #[test]
+    fn v1_peers_use_the_unversioned_encoding() {
+        use sp_runtime::AccountId32;
+        use xp_format::{Fees, Timeouts, XbiInstruction, XbiTimeSheetV1};
+
+        let id = sp_core::H256::repeat_byte(1);
+        let origin = AccountId32::new([3; 32]);
+        let timesheet = (
+            None::<u32>,
+            Some(5u32),
+            None::<u32>,
+            None::<u32>,
+            None::<u32>,
+            None::<u32>,
+        );
+        // The baseline `XbiMetadata`, field by field
+        let metadata_bytes = (
+            id,
+            2u32,
+            1u32,
+            Timeouts::default(),
+            timesheet,
+            Fees::new(None, Some(10), Some(5)),
+            Some(origin.clone()),
+        )
+            .encode();
+
+        let metadata = XbiMetadata::from(XbiMetadataV1 {
+            id,
+            dest_para_id: 2,
+            src_para_id: 1,
+            timeouts: Timeouts::default(),
+            timesheet: XbiTimeSheetV1 {
+                sent: Some(5),
+                ..Default::default()
+            },
+            fees: Fees::new(None, Some(10), Some(5)),
+            origin: Some(origin),
+        });
+
+        // The baseline `Message::Request`, with a `Transfer` of 7 to [2; 32]
+        let mut request_bytes = (0u8, 5u8, [2u8; 32], 7u128).encode();
+        request_bytes.extend(&metadata_bytes);
+        let request = Message::Request(XbiFormat {
+            instr: XbiInstruction::Transfer {
+                dest: AccountId32::new([2; 32]),
+                value: 7,
+            },
+            metadata: metadata.clone(),
+        });
+
+        let decoded = VersionedMessage::decode(&mut &request_bytes[..]).unwrap();
+        assert_eq!(decoded.request_version(), Some(1));
+        assert_eq!(Message::from(decoded), request);
+        assert_eq!(request.into_version(1).unwrap().encode(), request_bytes);
+
+        // The baseline `Message::Response`
+        let result = XbiResult {
+            status: Status::Success,
+            output: vec![1],
+            witness: vec![],
+        };
+        let mut response_bytes = (1u8, result.clone()).encode();
+        response_bytes.extend(&metadata_bytes);
+        let response = Message::Response(result, metadata);
+
+        let decoded = VersionedMessage::decode(&mut &response_bytes[..]).unwrap();
+        assert_eq!(decoded.request_version(), None);
+        assert_eq!(Message::from(decoded), response);
+        assert_eq!(response.into_version(1).unwrap().encode(), response_bytes);
+    }
+
+    #[test]
+    fn results_are_received_with_the_costs_they_report() {
+        let result = XbiResult {
+            status: Status::Success,
+            output: vec![1],
+            witness: vec![],
+        };
+        let mut metadata = XbiMetadata::default();
+        metadata.fees.push_aggregate(30);
+
+        let msg = Message::Response(result.clone(), metadata.clone());
+        assert_eq!(Message::from(msg.clone().into_version(3).unwrap()), msg);
+
+        // The costs of the instruction are those the destination reports
+        let mut format = XbiFormat::from_result(result.clone(), metadata.clone());
+        format.metadata.fees = Default::default();
+        assert_eq!(Message::from(VersionedMessage::Request(format.into())), msg);
+    }
+
+    #[test]
+    fn messages_are_keyed_by_priority_and_peer() {
+        use crate::queue::{Keyed, QueuePriority, QueueSignal, SubQueue};
+
+        let mut metadata = XbiMetadata::default();
+        metadata.src_para_id = 1;
+        metadata.dest_para_id = 2;
+        let request = Message::Request(XbiFormat {
+            metadata: metadata.clone(),
+            ..Default::default()
+        });
+        let response = Message::Response(Default::default(), metadata);
+
+        assert_eq!(
+            (request.clone(), QueueSignal::PendingRequest).key(),
+            SubQueue {
+                priority: QueuePriority::Normal,
+                para_id: 2
+            }
+        );
+        assert_eq!(
+            (request, QueueSignal::PendingExecution).key(),
+            SubQueue {
+                priority: QueuePriority::Normal,
+                para_id: 1
+            }
+        );
+        assert_eq!(
+            (response.clone(), QueueSignal::PendingResponse).key(),
+            SubQueue {
+                priority: QueuePriority::High,
+                para_id: 2
+            }
+        );
+        assert_eq!(
+            (response, QueueSignal::PendingResult).key(),
+            SubQueue {
+                priority: QueuePriority::High,
+                para_id: 1
+            }
+        );
+    }
 }
@@ -0,0 +1,227 @@
+use crate::{
+    queue::{
+        ringbuffer::{DefaultIdx, RingBufferIndex},
+        Instantiable, Keyed, Queue, QueueError,
+    },
+    traits::shims::{StorageMap, StorageValue},
+};
+use codec::{Codec, Encode, EncodeLike};
+use sp_std::{marker::PhantomData, prelude::*};
+
+/// A queue made up of sub-queues, where each item is pushed to the sub-queue of its key.
+///
+/// Items are popped from the sub-queues of the highest priority first. Within a priority, the sub-queues are popped in a round-robin,
+/// so that a flood of items in one sub-queue cannot delay the items in the others.
+///
+/// - `K` stores the keys of the sub-queues that have items, in the order they are next popped.
+/// - `B` stores the bounds of each sub-queue.
+/// - `M` stores the items of each sub-queue by their index.
+/// - `I` stores the position of each item by its id, so that an item can be found without reading the queue.
+/// - `L` stores the number of items across all sub-queues, so that the length is known without reading every sub-queue.
+///
+/// NOTE: unlike the `RingBufferTransient`, changes are written to storage on every operation.
+pub struct KeyedQueue<Item, K, B, M, I, L, Index = DefaultIdx>
+where
+    Item: Codec + EncodeLike + Keyed,
+    K: StorageValue<Vec<Item::Key>, Query = Vec<Item::Key>>,
+    B: StorageMap<Item::Key, (Index, Index), Query = (Index, Index)>,
+    M: StorageMap<(Item::Key, Index), Item, Query = Item>,
+    I: StorageMap<Item::Id, (Item::Key, Index), Query = Option<(Item::Key, Index)>>,
+    L: StorageValue<Index, Query = Index>,
+    Index: RingBufferIndex,
+{
+    _phantom: PhantomData<(Item, K, B, M, I, L, Index)>,
+}
+
+impl<Item, K, B, M, I, L, Index> Default for KeyedQueue<Item, K, B, M, I, L, Index>
+where
+    Item: Codec + EncodeLike + Keyed,
+    K: StorageValue<Vec<Item::Key>, Query = Vec<Item::Key>>,
+    B: StorageMap<Item::Key, (Index, Index), Query = (Index, Index)>,
+    M: StorageMap<(Item::Key, Index), Item, Query = Item>,
+    I: StorageMap<Item::Id, (Item::Key, Index), Query = Option<(Item::Key, Index)>>,
+    L: StorageValue<Index, Query = Index>,
+    Index: RingBufferIndex,
+{
+    fn default() -> Self {
+        KeyedQueue {
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<Item, K, B, M, I, L, Index> Instantiable for KeyedQueue<Item, K, B, M, I, L, Index>
+where
+    Item: Codec + EncodeLike + Keyed,
+    K: StorageValue<Vec<Item::Key>, Query = Vec<Item::Key>>,
+    B: StorageMap<Item::Key, (Index, Index), Query = (Index, Index)>,
+    M: StorageMap<(Item::Key, Index), Item, Query = Item>,
+    I: StorageMap<Item::Id, (Item::Key, Index), Query = Option<(Item::Key, Index)>>,
+    L: StorageValue<Index, Query = Index>,
+    Index: RingBufferIndex,
+{
+    type Args = ();
+
+    fn new(_args: Self::Args) -> Self {
+        KeyedQueue::default()
+    }
+}
+
+impl<Item, K, B, M, I, L, Index> KeyedQueue<Item, K, B, M, I, L, Index>
+where
+    Item: Codec + EncodeLike + Keyed,
+    K: StorageValue<Vec<Item::Key>, Query = Vec<Item::Key>>,
+    B: StorageMap<Item::Key, (Index, Index), Query = (Index, Index)>,
+    M: StorageMap<(Item::Key, Index), Item, Query = Item>,
+    I: StorageMap<Item::Id, (Item::Key, Index), Query = Option<(Item::Key, Index)>>,
+    L: StorageValue<Index, Query = Index>,
+    Index: RingBufferIndex,
+{
+    /// The key of the sub-queue that is popped next, this is the least recently popped sub-queue of the highest priority.
+    pub fn next_key(&self) -> Option<Item::Key> {
+        let keys = K::get();
+        let priority = keys.iter().map(Item::priority).max()?;
+        keys.into_iter().find(|key| Item::priority(key) == priority)
+    }
+
+    /// Return the number of items in the sub-queue of the given key.
+    pub fn len_of(&self, key: &Item::Key) -> usize {
+        let (start, end) = B::get(key);
+        end.wrapping_sub(start).unique_saturated_into()
+    }
+
+    /// Return the item with the given id without removing it, if it is queued.
+    ///
+    /// If more than one item with the id is queued, this is the one pushed last.
+    pub fn get_by_id(&self, id: &Item::Id) -> Option<Item> {
+        I::get(id).map(|position| M::get(position))
+    }
+
+    /// Return every item without removing them, sub-queues of a higher priority first and each sub-queue from its start.
+    ///
+    /// Within a priority, the sub-queues are in the order they are next popped, but their items are not interleaved as they are popped.
+    pub fn items(&self) -> Vec<Item> {
+        self.page(0, usize::MAX)
+    }
+
+    /// Return up to `count` items without removing them, skipping the first `start` items in the order of `items`.
+    ///
+    /// Only the items that are returned are read, the skipped sub-queues are passed over by their bounds.
+    pub fn page(&self, start: usize, count: usize) -> Vec<Item> {
+        let mut keys = K::get();
+        // The sort is stable, so the round-robin order is kept within a priority
+        keys.sort_by(|a, b| Item::priority(b).cmp(&Item::priority(a)));
+
+        let mut skip = start;
+        let mut items = Vec::with_capacity(self.len().saturating_sub(start).min(count));
+        for key in keys {
+            if items.len() >= count {
+                break;
+            }
+            let (first, end) = B::get(&key);
+            let len: usize = end.wrapping_sub(first).unique_saturated_into();
+            if skip >= len {
+                skip -= len;
+                continue;
+            }
+
+            let mut index = first.wrapping_add(Index::unique_saturated_from(skip));
+            skip = 0;
+            while index != end && items.len() < count {
+                items.push(M::get((&key, index)));
+                index = index.wrapping_add(1.into());
+            }
+        }
+        items
+    }
+}
+
+impl<Item, K, B, M, I, L, Index> Queue<Item> for KeyedQueue<Item, K, B, M, I, L, Index>
+where
+    Item: Codec + EncodeLike + Keyed,
+    K: StorageValue<Vec<Item::Key>, Query = Vec<Item::Key>>,
+    B: StorageMap<Item::Key, (Index, Index), Query = (Index, Index)>,
+    M: StorageMap<(Item::Key, Index), Item, Query = Item>,
+    I: StorageMap<Item::Id, (Item::Key, Index), Query = Option<(Item::Key, Index)>>,
+    L: StorageValue<Index, Query = Index>,
+    Index: RingBufferIndex,
+{
+    /// Push an item onto the end of its sub-queue, the item is rejected if the queue is full.
+    ///
+    /// A sub-queue that was empty is popped after every other sub-queue of the same priority.
+    fn push(&mut self, item: Item) -> Result<(), QueueError> {
+        if self.is_full() {
+            log::warn!(target: "xp-channel", "queue is full, rejecting item: {:?}", item.encode());
+            return Err(QueueError::Full);
+        }
+
+        let key = item.key();
+        let (start, end) = B::get(&key);
+
+        log::debug!(
+            target: "xp-channel",
+            "pushing item: {:?} at index {:?} of sub-queue {:?}",
+            item.encode(),
+            end.encode(),
+            key.encode()
+        );
+        I::insert(item.id(), (&key, end));
+        M::insert((&key, end), item);
+        B::insert(&key, (start, end.wrapping_add(1.into())));
+        L::mutate(|len| *len = len.wrapping_add(1.into()));
+
+        if start == end {
+            K::mutate(|keys| keys.push(key));
+        }
+        Ok(())
+    }
+
+    /// Pop an item from the start of the next sub-queue, which is then moved to the back of the round-robin.
+    fn pop(&mut self) -> Option<Item> {
+        let key = self.next_key()?;
+        let (start, end) = B::get(&key);
+        let item = M::take((&key, start));
+        // An item pushed later with the same id keeps its position
+        I::mutate_exists(item.id(), |position| {
+            if position.as_ref() == Some(&(key.clone(), start)) {
+                *position = None;
+            }
+        });
+        let start = start.wrapping_add(1.into());
+        L::mutate(|len| *len = len.wrapping_sub(1.into()));
+
+        K::mutate(|keys| {
+            keys.retain(|k| k != &key);
+            if start == end {
+                B::remove(&key);
+            } else {
+                B::insert(&key, (start, end));
+                keys.push(key);
+            }
+        });
+
+        Some(item)
+    }
+
+    /// Return the item that would be popped next without removing it.
+    fn peek(&self) -> Option<Item> {
+        let key = self.next_key()?;
+        let (start, _) = B::get(&key);
+        Some(M::get((&key, start)))
+    }
+
+    /// Return whether every sub-queue is empty.
+    fn is_empty(&self) -> bool {
+        K::get().is_empty()
+    }
+
+    /// Return the number of items across all sub-queues.
+    fn len(&self) -> usize {
+        L::get().unique_saturated_into()
+    }
+
+    /// Return the number of items the queue can hold across all sub-queues.
+    fn capacity(&self) -> usize {
+        Index::max_value().unique_saturated_into()
+    }
+}
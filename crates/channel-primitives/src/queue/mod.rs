@@ -1,20 +1,47 @@
-use crate::TypeInfo;
-use codec::{Codec, Decode, Encode, EncodeLike};
+use crate::{Message, TypeInfo};
+use codec::{Codec, Decode, Encode, EncodeLike, FullCodec};
+use sp_core::H256;
 use sp_std::prelude::*;
 use xp_format::Status;
 
+pub mod keyed;
 pub mod ringbuffer;
 
+/// An error when interacting with a queue
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum QueueError {
+    /// The queue is at capacity, no more items can be pushed until some are popped
+    Full,
+}
+
+impl From<QueueError> for sp_runtime::DispatchError {
+    fn from(e: QueueError) -> Self {
+        match e {
+            QueueError::Full => sp_runtime::DispatchError::Other("XBI queue is full"),
+        }
+    }
+}
+
 pub trait Queue<Item>
 where
     Item: Codec + EncodeLike,
 {
-    /// Push an item onto the end of the queue.
-    fn push(&mut self, i: Item);
+    /// Push an item onto the end of the queue, the item is rejected if the queue is full.
+    fn push(&mut self, i: Item) -> Result<(), QueueError>;
     /// Pop an item from the start of the queue.
     fn pop(&mut self) -> Option<Item>;
+    /// Return the item at the start of the queue without removing it.
+    fn peek(&self) -> Option<Item>;
     /// Return whether the queue is empty.
     fn is_empty(&self) -> bool;
+    /// Return the number of items in the queue.
+    fn len(&self) -> usize;
+    /// Return the maximum number of items the queue can hold.
+    fn capacity(&self) -> usize;
+    /// Return whether the queue is at capacity.
+    fn is_full(&self) -> bool {
+        self.len() >= self.capacity()
+    }
 }
 
 pub trait Instantiable {
@@ -23,6 +50,7 @@ pub trait Instantiable {
 }
 
 #[derive(Clone, Eq, PartialEq, Default, Encode, Decode, TypeInfo, Debug)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
 pub enum QueueSignal {
     #[default]
     PendingRequest, // Request needs to be sent over the protocol
@@ -32,16 +60,96 @@ pub enum QueueSignal {
     ProtocolError(Status),
 }
 
+impl QueueSignal {
+    /// Responses and results are owed to other parachains and users, so they are prioritised over new requests.
+    pub fn priority(&self) -> QueuePriority {
+        match self {
+            QueueSignal::PendingRequest | QueueSignal::PendingExecution => QueuePriority::Normal,
+            QueueSignal::PendingResponse
+            | QueueSignal::PendingResult
+            | QueueSignal::ProtocolError(_) => QueuePriority::High,
+        }
+    }
+}
+
+/// The priority of a sub-queue, sub-queues with a higher priority are always popped first.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Encode, Decode, TypeInfo, Debug)]
+pub enum QueuePriority {
+    Normal,
+    High,
+}
+
+/// An item that belongs to a sub-queue of a keyed queue.
+pub trait Keyed {
+    /// The key of a sub-queue
+    type Key: FullCodec + Clone + PartialEq;
+
+    /// The id of an item, by which it can be found in the queue
+    type Id: FullCodec;
+
+    /// The key of the sub-queue this item belongs to
+    fn key(&self) -> Self::Key;
+
+    /// The id of this item
+    fn id(&self) -> Self::Id;
+
+    /// The priority of the sub-queue with the given key
+    fn priority(key: &Self::Key) -> QueuePriority;
+}
+
+/// The key of a sub-queue of XBI messages, messages are queued by their priority and the parachain on the other side of the channel.
+#[derive(Clone, Copy, Eq, PartialEq, Encode, Decode, TypeInfo, Debug)]
+pub struct SubQueue {
+    pub priority: QueuePriority,
+    pub para_id: u32,
+}
+
+impl Keyed for (Message, QueueSignal) {
+    type Key = SubQueue;
+    type Id = H256;
+
+    fn key(&self) -> Self::Key {
+        let (msg, signal) = self;
+        let metadata = msg.get_metadata();
+        let para_id = match signal {
+            // These were received from the other side of the channel
+            QueueSignal::PendingExecution | QueueSignal::PendingResult => metadata.src_para_id,
+            _ => metadata.dest_para_id,
+        };
+
+        SubQueue {
+            priority: signal.priority(),
+            para_id,
+        }
+    }
+
+    fn id(&self) -> Self::Id {
+        self.0.get_metadata().get_id()
+    }
+
+    fn priority(key: &Self::Key) -> QueuePriority {
+        key.priority
+    }
+}
+
 pub trait Queueable<Item>
 where
     Item: Codec + EncodeLike,
 {
-    /// Push an item onto the end of the queue.
-    fn push(i: Item);
+    /// Push an item onto the end of the queue, the item is rejected if the queue is full.
+    fn push(i: Item) -> Result<(), QueueError>;
     /// Pop an item from the start of the queue.
     fn pop() -> Option<Item>;
+    /// Return the item at the start of the queue without removing it.
+    fn peek() -> Option<Item>;
     /// Return whether the queue is empty.
     fn is_empty() -> bool;
+    /// Return the number of items in the queue.
+    fn len() -> usize;
+    /// Return the maximum number of items the queue can hold.
+    fn capacity() -> usize;
+    /// Return whether the queue is at capacity.
+    fn is_full() -> bool;
 }
 
 /// This has some interesting functionality, since the ringbuffer is inherently based of drop & new.
@@ -50,9 +158,9 @@ where
     Item: Codec + EncodeLike,
     R: Instantiable<Args = ()> + Queue<Item>,
 {
-    fn push(i: Item) {
+    fn push(i: Item) -> Result<(), QueueError> {
         let mut r = R::new(());
-        r.push(i);
+        r.push(i)
     }
 
     fn pop() -> Option<Item> {
@@ -60,8 +168,28 @@ where
         r.pop()
     }
 
+    fn peek() -> Option<Item> {
+        let r = R::new(());
+        r.peek()
+    }
+
     fn is_empty() -> bool {
         let r = R::new(());
         r.is_empty()
     }
+
+    fn len() -> usize {
+        let r = R::new(());
+        r.len()
+    }
+
+    fn capacity() -> usize {
+        let r = R::new(());
+        r.capacity()
+    }
+
+    fn is_full() -> bool {
+        let r = R::new(());
+        r.is_full()
+    }
 }
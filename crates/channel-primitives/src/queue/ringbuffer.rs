@@ -1,8 +1,9 @@
 use crate::{
-    queue::{Instantiable, Queue},
+    queue::{Instantiable, Queue, QueueError},
     traits::shims::{StorageMap, StorageValue},
 };
 use codec::{Codec, EncodeLike};
+use sp_runtime::traits::{Bounded, UniqueSaturatedFrom, UniqueSaturatedInto};
 use sp_std::{marker::PhantomData, prelude::*};
 
 pub trait RingBuffer<Item>: Queue<Item>
@@ -38,6 +39,33 @@ impl_wrapping_ops!(u16);
 impl_wrapping_ops!(u32);
 impl_wrapping_ops!(u64);
 
+/// The requirements of an index into the ringbuffer.
+pub trait RingBufferIndex:
+    Codec
+    + EncodeLike
+    + Eq
+    + WrappingOps
+    + From<u8>
+    + Copy
+    + Bounded
+    + UniqueSaturatedInto<usize>
+    + UniqueSaturatedFrom<usize>
+{
+}
+
+impl<T> RingBufferIndex for T where
+    T: Codec
+        + EncodeLike
+        + Eq
+        + WrappingOps
+        + From<u8>
+        + Copy
+        + Bounded
+        + UniqueSaturatedInto<usize>
+        + UniqueSaturatedFrom<usize>
+{
+}
+
 pub type DefaultIdx = u16;
 /// Transient backing data that is the backbone of the trait object.
 pub struct RingBufferTransient<Item, B, M, Index = DefaultIdx>
@@ -45,7 +73,7 @@ where
     Item: Codec + EncodeLike,
     B: StorageValue<(Index, Index), Query = (Index, Index)>,
     M: StorageMap<Index, Item, Query = Item>,
-    Index: Codec + EncodeLike + Eq + WrappingOps + From<u8> + Copy,
+    Index: RingBufferIndex,
 {
     start: Index,
     end: Index,
@@ -57,7 +85,7 @@ where
     Item: Codec + EncodeLike,
     B: StorageValue<(Index, Index), Query = (Index, Index)>,
     M: StorageMap<Index, Item, Query = Item>,
-    Index: Codec + EncodeLike + Eq + WrappingOps + From<u8> + Copy,
+    Index: RingBufferIndex,
 {
     type Args = ();
 
@@ -71,7 +99,7 @@ where
     Item: Codec + EncodeLike,
     B: StorageValue<(Index, Index), Query = (Index, Index)>,
     M: StorageMap<Index, Item, Query = Item>,
-    Index: Codec + EncodeLike + Eq + WrappingOps + From<u8> + Copy,
+    Index: RingBufferIndex,
 {
     /// Create a new `RingBufferTransient` that backs the ringbuffer implementation.
     ///
@@ -91,7 +119,7 @@ where
     Item: Codec + EncodeLike,
     B: StorageValue<(Index, Index), Query = (Index, Index)>,
     M: StorageMap<Index, Item, Query = Item>,
-    Index: Codec + EncodeLike + Eq + WrappingOps + From<u8> + Copy,
+    Index: RingBufferIndex,
 {
     fn default() -> Self {
         let (start, end) = B::get();
@@ -108,7 +136,7 @@ where
     Item: Codec + EncodeLike,
     B: StorageValue<(Index, Index), Query = (Index, Index)>,
     M: StorageMap<Index, Item, Query = Item>,
-    Index: Codec + EncodeLike + Eq + WrappingOps + From<u8> + Copy,
+    Index: RingBufferIndex,
 {
     /// Commit on `drop`.
     fn drop(&mut self) {
@@ -121,12 +149,22 @@ where
     Item: Codec + EncodeLike,
     B: StorageValue<(Index, Index), Query = (Index, Index)>,
     M: StorageMap<Index, Item, Query = Item>,
-    Index: Codec + Copy + EncodeLike + Eq + From<u8> + WrappingOps,
+    Index: RingBufferIndex,
 {
     /// Push an item onto the end of the queue.
     ///
     /// Will insert the new item, but will not update the bounds in storage.
-    fn push(&mut self, item: Item) {
+    /// The item is rejected if the queue is full, rather than overwriting the oldest item.
+    fn push(&mut self, item: Item) -> Result<(), QueueError> {
+        // this will intentionally overflow and wrap around when bonds_end
+        // reaches `Index::max_value` because we want a ringbuffer.
+        let next_index = self.end.wrapping_add(1.into());
+        if next_index == self.start {
+            // the queue would present as empty but is not
+            log::warn!(target: "xp-channel", "queue is full, rejecting item: {:?}", item.encode());
+            return Err(QueueError::Full);
+        }
+
         log::debug!(
             target: "xp-channel",
             "pushing item: {:?} at index {:?}",
@@ -134,15 +172,8 @@ where
             self.end.encode()
         );
         M::insert(self.end, item);
-        // this will intentionally overflow and wrap around when bonds_end
-        // reaches `Index::max_value` because we want a ringbuffer.
-        let next_index = self.end.wrapping_add(1.into());
-        if next_index == self.start {
-            // queue presents as empty but is not
-            // --> overwrite the oldest item in the FIFO ringbuffer
-            self.start = self.start.wrapping_add(1.into());
-        }
         self.end = next_index;
+        Ok(())
     }
 
     /// Pop an item from the start of the queue.
@@ -158,10 +189,28 @@ where
         item.into()
     }
 
+    /// Return the item at the start of the queue without removing it.
+    fn peek(&self) -> Option<Item> {
+        if self.is_empty() {
+            return None;
+        }
+        M::get(self.start).into()
+    }
+
     /// Return whether to consider the queue empty.
     fn is_empty(&self) -> bool {
         self.start == self.end
     }
+
+    /// Return the number of items between the start and end of the queue.
+    fn len(&self) -> usize {
+        self.end.wrapping_sub(self.start).unique_saturated_into()
+    }
+
+    /// Return the number of items the queue can hold, one index is always left free so that a full queue is not considered empty.
+    fn capacity(&self) -> usize {
+        Index::max_value().unique_saturated_into()
+    }
 }
 
 /// Ringbuffer implementation based on `RingBufferTransient`
@@ -170,7 +219,7 @@ where
     Item: Codec + EncodeLike,
     B: StorageValue<(Index, Index), Query = (Index, Index)>,
     M: StorageMap<Index, Item, Query = Item>,
-    Index: Codec + EncodeLike + Eq + WrappingOps + From<u8> + Copy,
+    Index: RingBufferIndex,
 {
     /// Commit the (potentially) changed bounds to storage.
     fn commit(&self) {
@@ -67,6 +67,49 @@ pub trait XbiInstructionHandler<Origin> {
     >;
 }
 
+/// A handler for a subset of XBI instructions, which can be composed with other handlers into a router.
+///
+/// Routes are implemented for tuples, where an instruction is handled by the first handler in the tuple that accepts its identifier.
+/// This allows a parachain to mix and match the instructions it supports, override the semantics of an instruction by placing its own handler first,
+/// and register handlers for custom `Unknown { identifier }` instructions.
+#[cfg(feature = "frame")]
+pub trait XbiInstructionRoute<Origin> {
+    /// Whether the handler accepts instructions with the given identifier, see `XbiInstruction::identifier`
+    fn accepts(identifier: u8) -> bool;
+
+    /// Handle an instruction, this is only called if the handler accepts its identifier
+    fn handle(
+        origin: &Origin,
+        xbi: &mut xp_format::XbiFormat,
+    ) -> Result<
+        HandlerInfo<frame_support::weights::Weight>,
+        frame_support::dispatch::DispatchErrorWithPostInfo,
+    >;
+}
+
+#[cfg(feature = "frame")]
+#[impl_trait_for_tuples::impl_for_tuples(30)]
+impl<Origin> XbiInstructionRoute<Origin> for Tuple {
+    fn accepts(identifier: u8) -> bool {
+        for_tuples!( #( if Tuple::accepts(identifier) { return true; } )* );
+        false
+    }
+
+    fn handle(
+        origin: &Origin,
+        xbi: &mut xp_format::XbiFormat,
+    ) -> Result<
+        HandlerInfo<frame_support::weights::Weight>,
+        frame_support::dispatch::DispatchErrorWithPostInfo,
+    > {
+        let identifier = xbi.instr.identifier();
+        for_tuples!( #( if Tuple::accepts(identifier) { return Tuple::handle(origin, xbi); } )* );
+
+        log::debug!(target: "xp-channel", "No route accepts XBI instruction {:?}", identifier);
+        Err(sp_runtime::DispatchError::Other("No route accepts the XBI instruction").into())
+    }
+}
+
 /// A trait providing generic write access, its intention is so that a pallet may provide a way to write channel elements to storage.
 pub trait Writable<T: FullCodec> {
     fn write(t: T) -> DispatchResult;
@@ -20,3 +20,21 @@ pub fn xbi_origin<T: codec::Decode>(
         });
     x
 }
+
+/// The local account that acts for the origin of a message from another parachain.
+///
+/// The origin of a message is chosen by its sender, so it is never dispatched as is. It is hashed with the parachain the
+/// message came from, so that a peer can only ever act as the accounts derived for it and never as a local account.
+pub fn remote_origin<T: codec::Decode>(
+    m: &xp_channel::XbiMetadata,
+) -> Result<T, sp_runtime::DispatchError> {
+    use codec::Encode;
+
+    let origin = m.get_origin().ok_or("XBI message has no origin")?;
+    let entropy =
+        (b"xbi/remote-origin", m.src_para_id, origin).using_encoded(sp_io::hashing::blake2_256);
+    codec::Decode::decode(&mut sp_runtime::traits::TrailingZeroInput::new(
+        &entropy[..],
+    ))
+    .map_err(|_| "XBI message origin is not valid".into())
+}
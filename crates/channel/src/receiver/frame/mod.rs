@@ -7,7 +7,7 @@ use xp_format::{Status, XbiFormat, XbiMetadata, XbiResult};
 pub mod queue_backed;
 pub mod sync;
 
-/// The receiver needs to invert the message src/dest so that it can respond accordingly
+/// The receiver needs to invert the message src/dest so that it can respond accordingly, the response is relayed back along the reverse of the route
 pub fn invert_destination_from_message(metadata: &mut XbiMetadata) {
     let reply_para = metadata.src_para_id;
     let my_para = metadata.dest_para_id;
@@ -15,6 +15,7 @@ pub fn invert_destination_from_message(metadata: &mut XbiMetadata) {
     // TODO: receiver may want to customise the response metadata at some point
     metadata.dest_para_id = reply_para;
     metadata.src_para_id = my_para;
+    metadata.route.reverse();
 }
 
 pub fn handle_instruction_result<E: ChannelProgressionEmitter>(
@@ -89,6 +90,23 @@ mod tests {
         assert_eq!(metadata.dest_para_id, 1);
     }
 
+    #[test]
+    fn inverting_destination_reverses_the_route() {
+        let mut metadata = XbiMetadata::new(
+            1,
+            4,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+        metadata.route = vec![2, 3];
+        invert_destination_from_message(&mut metadata);
+        assert_eq!(metadata.route, vec![3, 2]);
+        assert_eq!(metadata.next_hop(metadata.src_para_id), 3);
+    }
+
     #[test]
     fn xbi_handler_maps_to_result_correctly_when_exceeded_gas() {
         let info = HandlerInfo {
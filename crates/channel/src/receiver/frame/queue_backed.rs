@@ -37,7 +37,7 @@ where
         Queue::push((
             Message::Request(format.to_owned()),
             QueueSignal::PendingExecution,
-        ));
+        ))?;
 
         // TODO: cost of queueing message, explore this
         Ok(Default::default())
@@ -60,7 +60,7 @@ where
         Queue::push((
             Message::Response(msg.clone(), meta.clone()),
             QueueSignal::PendingResult,
-        ));
+        ))?;
 
         // TODO: add the cost of handling this response here, explore this
         Ok(Default::default())
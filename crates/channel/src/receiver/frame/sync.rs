@@ -97,9 +97,8 @@ where
         ));
         Emitter::emit_received(Either::Left(msg));
 
-        invert_destination_from_message(&mut msg.metadata);
-
         let instruction_result = InstructionHandler::handle(origin, msg);
+        invert_destination_from_message(&mut msg.metadata);
         log::debug!(target: "xbi", "Instruction result: {:?}", instruction_result);
 
         let xbi_result = handle_instruction_result::<Emitter>(&instruction_result, msg);
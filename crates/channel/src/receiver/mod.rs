@@ -18,7 +18,11 @@ pub trait Receiver {
     /// Receive an XBI message
     fn receive(origin: Self::Origin, msg: Message) -> Self::Outcome {
         match msg {
-            Message::Request(mut msg) => Self::handle_request(&origin, &mut msg),
+            Message::Request(msg) => match msg.into_response() {
+                // Peers may respond with the standard's `Result` instruction rather than a response
+                Ok((result, metadata)) => Self::handle_response(&origin, &result, &metadata),
+                Err(mut msg) => Self::handle_request(&origin, &mut msg),
+            },
             Message::Response(msg, metadata) => Self::handle_response(&origin, &msg, &metadata),
         }
     }
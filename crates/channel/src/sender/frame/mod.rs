@@ -1,5 +1,13 @@
+use crate::sender::Sender as SenderExt;
+use codec::{Decode, DecodeAll, Encode};
+use scale_info::TypeInfo;
+use sp_core::H256;
+use sp_runtime::{
+    traits::Dispatchable, AccountId32, DispatchError, DispatchResult, DispatchResultWithInfo,
+};
 use sp_std::prelude::Vec;
-use xp_channel::Message;
+use xp_channel::{traits::Writable, Message};
+use xp_format::XbiResult;
 
 pub mod queue_backed;
 pub mod sync;
@@ -9,6 +17,9 @@ pub mod sync;
 /// The implementer should take note that this can be very different depending on who implements the channel.
 /// Such as the indexes for the receiver, perhaps it should be indexed.
 ///
+/// The implementer is also responsible for encoding the message in a version the destination understands,
+/// see [`xp_channel::VersionedMessage`], and should fail if it cannot be represented in that version.
+///
 /// The implementer should implement this trait since it cannot be known by this crate, e.g:
 ///
 /// ```
@@ -16,12 +27,104 @@ pub mod sync;
 /// # pub fn test<T: frame_system::Config>(format: xp_channel::XbiFormat) {
 ///     //let pallet_index_in_runtime = 200;
 ///     //let mut xbi_call: VecDeque<u8> =
-///     //   crate::pallet::Call::receive::<T> { msg: format.into() }
+///     //   crate::pallet::Call::receive::<T> { msg: Message::from(format).into_version(peer_version)? }
 ///     //       .encode()
 ///     //       .into();
 ///     //xbi_call.push_front(pallet_index_in_runtime); // Pallet index is not known by the crate for every channel and can be changed anytime
 /// # }
 /// ```
 pub trait ReceiveCallProvider {
-    fn provide<T: Into<Message>>(t: T) -> Vec<u8>;
+    fn provide<T: Into<Message>>(t: T) -> Result<Vec<u8>, DispatchError>;
+}
+
+/// The continuation of a promise, stored until the results of the requests it waits for are in.
+///
+/// Promises cannot be stored themselves, so the continuation keeps the encoded call of the promise without its result,
+/// which completes the call once it is in. The result must therefore be the last argument of the call,
+/// a `XbiResult` for `then` and a `Vec<XbiResult>` for `join`.
+#[derive(Clone, Eq, PartialEq, Encode, Decode, TypeInfo, Debug)]
+pub struct Continuation {
+    /// The origin the call is dispatched with, this is the origin of the requests
+    pub origin: Option<AccountId32>,
+    /// The ids of the requests, a joined call is given their results in this order
+    pub ids: Vec<H256>,
+    /// The encoded call, without its result
+    pub call: Vec<u8>,
+    /// Whether the call is given the results of every request, rather than the result of one
+    pub joined: bool,
+}
+
+impl Continuation {
+    /// Prepare the continuation of a promise, this fails if the promise does not take its result as the last argument of its call
+    pub fn new<Resolved: Default + Encode, Call: Encode>(
+        origin: Option<AccountId32>,
+        ids: Vec<H256>,
+        promise: fn(Resolved) -> Call,
+        joined: bool,
+    ) -> Result<Self, DispatchError> {
+        let call = promise(Resolved::default()).encode();
+        let placeholder = Resolved::default().encode();
+        let len = call
+            .len()
+            .checked_sub(placeholder.len())
+            .filter(|len| call[*len..] == placeholder[..])
+            .ok_or(DispatchError::Other(
+                "The promise must take its result as the last argument of its call",
+            ))?;
+
+        Ok(Continuation {
+            origin,
+            ids,
+            call: call[..len].to_vec(),
+            joined,
+        })
+    }
+
+    /// Complete the call with the results of the requests, in the order of their ids
+    pub fn resolve<Call: Decode>(&self, results: Vec<XbiResult>) -> Result<Call, DispatchError> {
+        let mut call = self.call.clone();
+        if self.joined {
+            results.encode_to(&mut call);
+        } else {
+            results
+                .first()
+                .ok_or(DispatchError::Other(
+                    "A promise has no result to resolve with",
+                ))?
+                .encode_to(&mut call);
+        }
+
+        Call::decode_all(&mut &call[..])
+            .map_err(|_| DispatchError::Other("Failed to decode the call of a promise"))
+    }
+}
+
+/// Send the requests of a promise, storing its continuation until the results of all of them are in
+pub(crate) fn send_with_promise<Sender, Promises, Resolved, Call>(
+    reqs: Vec<Message>,
+    promise: fn(Resolved) -> Call,
+    joined: bool,
+) -> DispatchResultWithInfo<Call::PostInfo>
+where
+    Sender: SenderExt<Message, Outcome = DispatchResult>,
+    Promises: Writable<Continuation>,
+    Resolved: Default + Encode,
+    Call: Dispatchable + Encode,
+    Call::PostInfo: Default,
+{
+    if reqs.iter().any(|req| matches!(req, Message::Response(..))) {
+        return Err(DispatchError::Other("Only requests can be sent with a promise").into());
+    }
+
+    let origin = reqs
+        .first()
+        .and_then(|req| req.get_metadata().get_origin().cloned());
+    let ids = reqs.iter().map(|req| req.get_metadata().get_id()).collect();
+    let continuation = Continuation::new(origin, ids, promise, joined)?;
+
+    for req in reqs {
+        Sender::send(req)?;
+    }
+    Promises::write(continuation)?;
+    Ok(Default::default())
 }
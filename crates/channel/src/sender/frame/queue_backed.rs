@@ -1,17 +1,31 @@
-use crate::sender::Sender as SenderExt;
+use super::{send_with_promise, Continuation};
+use crate::sender::{CallPromise, PromiseDelegate, Sender as SenderExt};
+use codec::Encode;
 use frame_support::traits::{fungibles::Mutate, Get, ReservableCurrency};
 use frame_system::Config;
-use sp_runtime::{traits::UniqueSaturatedInto, DispatchResult};
+use sp_runtime::{
+    traits::{Dispatchable, UniqueSaturatedInto},
+    DispatchResult, DispatchResultWithInfo,
+};
 use sp_std::borrow::ToOwned;
-use sp_std::marker::PhantomData;
+use sp_std::{marker::PhantomData, prelude::*, vec};
 use xp_channel::{
-    queue::{QueueSignal, Queueable},
+    queue::{QueueError, QueueSignal, Queueable},
+    traits::Writable,
     Message,
 };
-use xp_format::Timestamp::*;
+use xp_format::{Timestamp::*, XbiResult};
 
 /// An asynchronous frame-based channel sender part. The resolving messages are handled by some Queue implementation.
-pub struct Sender<T, Queue, Currency, Assets, ChargeForMessage, AssetReserveCustodian> {
+pub struct Sender<
+    T,
+    Queue,
+    Currency,
+    Assets,
+    ChargeForMessage,
+    AssetReserveCustodian,
+    Promises = (),
+> {
     #[allow(clippy::all)]
     phantom: PhantomData<(
         T,
@@ -20,11 +34,13 @@ pub struct Sender<T, Queue, Currency, Assets, ChargeForMessage, AssetReserveCust
         Assets,
         ChargeForMessage,
         AssetReserveCustodian,
+        Promises,
     )>,
 }
 
-impl<T, Queue, Currency, Assets, ChargeForMessage, AssetReserveCustodian> SenderExt<Message>
-    for Sender<T, Queue, Currency, Assets, ChargeForMessage, AssetReserveCustodian>
+impl<T, Queue, Currency, Assets, ChargeForMessage, AssetReserveCustodian, Promises>
+    SenderExt<Message>
+    for Sender<T, Queue, Currency, Assets, ChargeForMessage, AssetReserveCustodian, Promises>
 where
     T: Config,
     Queue: Queueable<(Message, QueueSignal)>,
@@ -51,6 +67,12 @@ where
 
                 format.metadata.progress(Submitted(current_block));
 
+                // Reject the request before charging for it if it cannot be queued
+                if Queue::is_full() {
+                    log::warn!(target: "xs-channel", "Queue is full, rejecting message: {:?}", format);
+                    return Err(QueueError::Full.into());
+                }
+
                 ChargeForMessage::charge(&o, &format.metadata.fees)?;
 
                 log::debug!(target: "xs-channel", "Pushing message: {:?} on block {} to queue", format, current_block);
@@ -58,7 +80,7 @@ where
                 Queue::push((
                     Message::Request(format.to_owned()),
                     QueueSignal::PendingRequest,
-                ));
+                ))?;
             }
             Message::Response(result, metadata) => {
                 let o: T::AccountId = crate::xbi_origin(metadata)?;
@@ -72,9 +94,53 @@ where
                 Queue::push((
                     Message::Response(result.to_owned(), metadata.to_owned()),
                     QueueSignal::PendingResponse,
-                ));
+                ))?;
             }
         }
         Ok(())
     }
 }
+
+impl<T, Queue, Currency, Assets, ChargeForMessage, AssetReserveCustodian, Promises, Call>
+    PromiseDelegate<Message, Call>
+    for Sender<T, Queue, Currency, Assets, ChargeForMessage, AssetReserveCustodian, Promises>
+where
+    T: Config,
+    Queue: Queueable<(Message, QueueSignal)>,
+    Currency: ReservableCurrency<T::AccountId>,
+    Assets: Mutate<T::AccountId>,
+    ChargeForMessage: xp_channel::traits::MonetaryForMessage<
+        T::AccountId,
+        Currency,
+        Assets,
+        AssetReserveCustodian,
+    >,
+    AssetReserveCustodian: Get<T::AccountId>,
+    Promises: Writable<Continuation>,
+    Call: Dispatchable + Encode,
+    Call::PostInfo: Default,
+{
+    type Resolved = XbiResult;
+
+    fn then(
+        req: Message,
+        promise: CallPromise<Self::Resolved, Call>,
+    ) -> DispatchResultWithInfo<Call::PostInfo> {
+        send_with_promise::<Self, Promises, _, _>(vec![req], promise.0, false)
+    }
+
+    fn join(
+        req: Vec<Message>,
+        promise: CallPromise<Vec<Self::Resolved>, Call>,
+    ) -> DispatchResultWithInfo<Call::PostInfo> {
+        send_with_promise::<Self, Promises, _, _>(req, promise.0, true)
+    }
+
+    fn chain(
+        _result: Call::PostInfo,
+        req: Message,
+        promise: CallPromise<Self::Resolved, Call>,
+    ) -> DispatchResultWithInfo<Call::PostInfo> {
+        Self::then(req, promise)
+    }
+}
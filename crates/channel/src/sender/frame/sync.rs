@@ -1,16 +1,22 @@
-use super::ReceiveCallProvider;
-use crate::sender::Sender as SenderExt;
+use super::{send_with_promise, Continuation, ReceiveCallProvider};
+use crate::sender::{CallPromise, PromiseDelegate, Sender as SenderExt};
 use codec::{Decode, Encode};
-use frame_support::traits::{
-    fungibles::{Inspect, Mutate},
-    Get, ReservableCurrency,
+use frame_support::{
+    traits::{
+        fungibles::{Inspect, Mutate},
+        Get, ReservableCurrency,
+    },
+    weights::Weight,
 };
 use frame_system::Config;
-use sp_runtime::{traits::UniqueSaturatedInto, DispatchError, DispatchResult};
-use sp_std::marker::PhantomData;
+use sp_runtime::{
+    traits::{Dispatchable, UniqueSaturatedInto},
+    DispatchError, DispatchResult, DispatchResultWithInfo,
+};
+use sp_std::{marker::PhantomData, prelude::*, vec};
 use xp_channel::SendXcm;
-use xp_channel::{ChannelProgressionEmitter, Message};
-use xp_format::Timestamp::*;
+use xp_channel::{traits::Writable, ChannelProgressionEmitter, Message};
+use xp_format::{Timestamp::*, XbiResult};
 use xp_xcm::xcm::prelude::*;
 use xp_xcm::{MultiLocationBuilder, XcmBuilder};
 
@@ -26,6 +32,8 @@ pub struct Sender<
     AssetRegistry,
     ChargeForMessage,
     AssetReserveCustodian,
+    ResponseWeightLimit,
+    Promises = (),
 > {
     #[allow(clippy::all)]
     phantom: PhantomData<(
@@ -38,6 +46,8 @@ pub struct Sender<
         AssetRegistry,
         ChargeForMessage,
         AssetReserveCustodian,
+        ResponseWeightLimit,
+        Promises,
     )>,
 }
 
@@ -53,6 +63,8 @@ impl<
         AssetLookup,
         ChargeForMessage,
         AssetReserveCustodian,
+        ResponseWeightLimit,
+        Promises,
     > SenderExt<Message>
     for Sender<
         T,
@@ -64,6 +76,8 @@ impl<
         AssetLookup,
         ChargeForMessage,
         AssetReserveCustodian,
+        ResponseWeightLimit,
+        Promises,
     >
 where
     T: Config,
@@ -80,6 +94,7 @@ where
         AssetReserveCustodian,
     >,
     AssetReserveCustodian: Get<T::AccountId>,
+    ResponseWeightLimit: Get<Weight>,
 {
     type Outcome = DispatchResult;
 
@@ -88,7 +103,12 @@ where
 
         let metadata = msg.get_metadata().clone();
 
-        let dest = MultiLocationBuilder::new_parachain(metadata.dest_para_id)
+        // Messages sent from here go to the first hop of their route
+        let dest = MultiLocationBuilder::new_parachain(metadata.next_hop(metadata.src_para_id))
+            .with_parents(1)
+            .build();
+        // Whatever is left of the fees once the message is executed is deposited back to our sovereign account
+        let surplus_beneficiary = MultiLocationBuilder::new_parachain(metadata.src_para_id)
             .with_parents(1)
             .build();
 
@@ -99,6 +119,7 @@ where
                 format.metadata.progress(Sent(current_block));
 
                 let o: T::AccountId = crate::xbi_origin(&format.metadata)?;
+                let call = CallProvider::provide(format.clone())?;
                 ChargeForMessage::charge(&o, &format.metadata.fees)?;
 
                 let payment_asset = match format.metadata.fees.asset {
@@ -120,16 +141,9 @@ where
                         format.metadata.fees.notification_cost_limit,
                         None,
                     )
-                    .with_transact(
-                        Some(OriginKind::SovereignAccount),
-                        None,
-                        CallProvider::provide(format.clone()),
-                    )
-                    // TODO deposit whatever is left over in the reserve
-                    // .with_deposit_asset(
-                    //     MultiLocationBuilder::new_parachain(format.metadata.src_para_id).build(),
-                    //     50,
-                    // )
+                    .with_transact(Some(OriginKind::SovereignAccount), None, call)
+                    .with_refund_surplus()
+                    .with_deposit_asset(surplus_beneficiary, 1)
                     .build();
 
                 Xcm::send_xcm(dest, xbi_format_msg)
@@ -141,9 +155,11 @@ where
                         // TODO: ensure happens on queue
                         if let Err(e) = ChargeForMessage::refund(&o, &metadata.fees) {
                             log::error!(target: "xs-channel", "Failed to refund fees: {:?}", e);
+                            Emitter::emit_refund_failed(&metadata, &e);
                         }
 
                         log::error!(target: "xs-channel", "Failed to send xcm request: {:?}", e);
+                        Emitter::emit_send_failed(&metadata, &e.into());
                         DispatchError::Other("Failed to send xcm request")
                     })
             }
@@ -151,13 +167,8 @@ where
                 // Progress the delivered timestamp
                 metadata.progress(Responded(current_block));
 
-                // Sovereign will pay for this on behalf of the user, this is tricky to get
-                // TODO: Set this and get it from config
-                let require_weight_at_most = 1_000_000_000;
-
-                // NOTE: do we want to allow the user to control what asset we pay for in response?
-                // I think that should be configured by the channel implementation, not the user
-                let _payment_asset = match metadata.fees.asset {
+                // The response is paid for from the notification budget of the user, in the asset they paid the request in
+                let payment_asset = match metadata.fees.asset {
                     Some(id) => {
                         let id: AssetIdOf<T, Assets> = Decode::decode(&mut &id.encode()[..])
                             .map_err(|_| DispatchError::CannotLookup)?;
@@ -167,23 +178,100 @@ where
                 };
 
                 let xbi_format_msg = XcmBuilder::<()>::default()
-                    // TODO: reenable based on above conversations`
-                    // .with_withdraw_concrete_asset(payment_asset.clone(), 1_000_000_000_000) // TODO: take amount from new costs field
-                    // .with_buy_execution(payment_asset, 1_000_000_000, None) // TODO: same as above
+                    .with_withdraw_concrete_asset(
+                        payment_asset.clone(),
+                        metadata.fees.notification_cost_limit,
+                    )
+                    .with_buy_execution(payment_asset, metadata.fees.notification_cost_limit, None)
                     .with_transact(
                         Some(OriginKind::SovereignAccount),
-                        Some(require_weight_at_most),
-                        CallProvider::provide((result.clone(), metadata.clone())),
+                        Some(ResponseWeightLimit::get()),
+                        CallProvider::provide((result.clone(), metadata.clone()))?,
                     )
+                    .with_refund_surplus()
+                    .with_deposit_asset(surplus_beneficiary, 1)
                     .build();
 
                 Xcm::send_xcm(dest, xbi_format_msg)
                     .map(|_| Emitter::emit_sent(msg.clone()))
                     .map_err(|e| {
                         log::error!(target: "xs-channel", "Failed to send xcm request: {:?}", e);
+                        Emitter::emit_send_failed(msg.get_metadata(), &e.into());
                         DispatchError::Other("Failed to send xcm request")
                     })
             }
         }
     }
 }
+
+impl<
+        T,
+        Emitter,
+        CallProvider,
+        Xcm,
+        Currency,
+        Assets,
+        AssetLookup,
+        ChargeForMessage,
+        AssetReserveCustodian,
+        ResponseWeightLimit,
+        Promises,
+        Call,
+    > PromiseDelegate<Message, Call>
+    for Sender<
+        T,
+        Emitter,
+        CallProvider,
+        Xcm,
+        Currency,
+        Assets,
+        AssetLookup,
+        ChargeForMessage,
+        AssetReserveCustodian,
+        ResponseWeightLimit,
+        Promises,
+    >
+where
+    T: Config,
+    Emitter: ChannelProgressionEmitter,
+    CallProvider: ReceiveCallProvider,
+    Xcm: SendXcm,
+    Currency: ReservableCurrency<T::AccountId>,
+    Assets: Mutate<T::AccountId>,
+    AssetLookup: xp_xcm::frame_traits::AssetLookup<<Assets as Inspect<T::AccountId>>::AssetId>,
+    ChargeForMessage: xp_channel::traits::MonetaryForMessage<
+        T::AccountId,
+        Currency,
+        Assets,
+        AssetReserveCustodian,
+    >,
+    AssetReserveCustodian: Get<T::AccountId>,
+    ResponseWeightLimit: Get<Weight>,
+    Promises: Writable<Continuation>,
+    Call: Dispatchable + Encode,
+    Call::PostInfo: Default,
+{
+    type Resolved = XbiResult;
+
+    fn then(
+        req: Message,
+        promise: CallPromise<Self::Resolved, Call>,
+    ) -> DispatchResultWithInfo<Call::PostInfo> {
+        send_with_promise::<Self, Promises, _, _>(vec![req], promise.0, false)
+    }
+
+    fn join(
+        req: Vec<Message>,
+        promise: CallPromise<Vec<Self::Resolved>, Call>,
+    ) -> DispatchResultWithInfo<Call::PostInfo> {
+        send_with_promise::<Self, Promises, _, _>(req, promise.0, true)
+    }
+
+    fn chain(
+        _result: Call::PostInfo,
+        req: Message,
+        promise: CallPromise<Self::Resolved, Call>,
+    ) -> DispatchResultWithInfo<Call::PostInfo> {
+        Self::then(req, promise)
+    }
+}
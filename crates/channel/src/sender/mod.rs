@@ -33,19 +33,27 @@ pub struct CallPromise<Result, Call: Dispatchable>(pub fn(Result) -> Call);
 // #[cfg(feature = "promises")]
 // Xbi promise delegates are defined as components that may handle the result of a sent message
 // this would allow for chaining of middleware, I think
-// TODO: it's possible we might not implement promises for this, and it would be in the receiver
 pub trait PromiseDelegate<T, Call: Dispatchable>: Sender<T> {
+    /// The result a promise is resolved with
+    type Resolved;
+
+    /// Send the request, dispatching the call of the promise with its result
     fn then(
         req: T,
-        promise: CallPromise<Self::Outcome, Call>,
+        promise: CallPromise<Self::Resolved, Call>,
     ) -> DispatchResultWithInfo<Call::PostInfo>;
 
-    fn join(req: Vec<T>, promise: CallPromise<Vec<Self::Outcome>, Call>);
+    /// Send all of the requests, dispatching the call of the promise once all of their results are in
+    fn join(
+        req: Vec<T>,
+        promise: CallPromise<Vec<Self::Resolved>, Call>,
+    ) -> DispatchResultWithInfo<Call::PostInfo>;
 
+    /// Send the request after a previous promise, dispatching the call of the promise with its result
     fn chain(
         result: Call::PostInfo,
         req: T,
-        promise: CallPromise<Self::Outcome, Call>,
+        promise: CallPromise<Self::Resolved, Call>,
     ) -> DispatchResultWithInfo<Call::PostInfo>;
 }
 
@@ -113,6 +121,8 @@ mod tests {
     const DUMMY_ORIGIN: u64 = 50;
 
     impl PromiseDelegate<u32, DummyDispatch> for DummySender {
+        type Resolved = Self::Outcome;
+
         fn then(
             req: u32,
             promise: CallPromise<Self::Outcome, DummyDispatch>,
@@ -120,10 +130,12 @@ mod tests {
             promise.0(DummySender::send(req)).dispatch(DUMMY_ORIGIN)
         }
 
-        fn join(req: Vec<u32>, promise: CallPromise<Vec<Self::Outcome>, DummyDispatch>) {
+        fn join(
+            req: Vec<u32>,
+            promise: CallPromise<Vec<Self::Outcome>, DummyDispatch>,
+        ) -> DispatchResultWithInfo<<DummyDispatch as Dispatchable>::PostInfo> {
             promise.0(req.iter().map(|req| DummySender::send(*req)).collect())
                 .dispatch(DUMMY_ORIGIN)
-                .unwrap();
         }
 
         fn chain(
@@ -292,7 +304,7 @@ mod tests {
             .map(|(index, _)| (index + 1) as u32)
             .collect();
 
-        DummySender::join(requests, CallPromise(check_result_is_ok));
+        DummySender::join(requests, CallPromise(check_result_is_ok)).unwrap();
 
         let guard = QUEUE.lock().unwrap();
         assert_eq!(*guard.get(&1_u8).unwrap(), 500);
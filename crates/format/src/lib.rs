@@ -9,13 +9,16 @@ use sp_runtime::AccountId32;
 use sp_std::prelude::*;
 use sp_std::vec;
 
+pub mod versioned;
 pub mod xbi_codec;
 
 use sabi::*;
+pub use versioned::*;
 pub use xbi_codec::*;
 
 /// A representation of the status of an XBI execution
 #[derive(Clone, Eq, PartialEq, Default, Encode, Decode, Debug, TypeInfo)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
 pub enum Status {
     #[default]
     /// The XBI message was successful
@@ -81,6 +84,7 @@ pub struct XbiCheckIn<BlockNumber> {
 
 /// An XBI message
 #[derive(Clone, Eq, PartialEq, Debug, Default, Encode, Decode, TypeInfo)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
 pub struct XbiFormat {
     /// The instruction to execute on the target
     pub instr: XbiInstruction,
@@ -88,9 +92,53 @@ pub struct XbiFormat {
     pub metadata: XbiMetadata,
 }
 
+impl XbiFormat {
+    /// Respond to a request with a `Result` instruction
+    pub fn from_result(result: XbiResult, metadata: XbiMetadata) -> Self {
+        XbiFormat {
+            instr: XbiInstruction::Result {
+                outcome: result.status,
+                output: result.output,
+                witness: result.witness,
+                actual_aggregated_costs: metadata.fees.get_aggregated_cost(),
+            },
+            metadata,
+        }
+    }
+
+    /// The response carried by the message if it is a `Result` instruction, otherwise the message is given back.
+    ///
+    /// The costs reported by the instruction are what the destination aggregated, so they replace those of the metadata.
+    // Justification: the message is given back as it was, boxing it would allocate for every request that is received
+    #[allow(clippy::result_large_err)]
+    pub fn into_response(self) -> Result<(XbiResult, XbiMetadata), Self> {
+        match self.instr {
+            XbiInstruction::Result {
+                outcome,
+                output,
+                witness,
+                actual_aggregated_costs,
+            } => {
+                let mut metadata = self.metadata;
+                metadata.fees.aggregated_cost = actual_aggregated_costs;
+                Ok((
+                    XbiResult {
+                        status: outcome,
+                        output,
+                        witness,
+                    },
+                    metadata,
+                ))
+            }
+            _ => Err(self),
+        }
+    }
+}
+
 // TODO: implement into<usize> to specify custom, versioned byte representations. E.g Result = 255
 /// The instruction to execute on the target
 #[derive(Clone, Eq, PartialEq, Debug, TypeInfo)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
 pub enum XbiInstruction {
     /// An opaque message providing the instruction identifier and some bytes
     Unknown { identifier: u8, params: Vec<u8> },
@@ -160,6 +208,16 @@ pub enum XbiInstruction {
         asset_b: AssetId,
         amount: Value,
     },
+    /// A batch of instructions sharing the metadata of the message, these are executed atomically on the target
+    /// and resolve with the output of each instruction. Batches cannot be nested.
+    Batch { instructions: Vec<XbiInstruction> },
+    /// The outcome of an instruction, sent back to the source of the request
+    Result {
+        outcome: Status,
+        output: Data,
+        witness: Data,
+        actual_aggregated_costs: Value,
+    },
 }
 
 impl Default for XbiInstruction {
@@ -170,6 +228,7 @@ impl Default for XbiInstruction {
 
 /// A result containing the status of the call
 #[derive(Debug, Clone, Eq, Default, PartialEq, Encode, Decode, TypeInfo)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
 pub struct XbiResult {
     pub status: Status,
     pub output: Data,
@@ -190,6 +249,7 @@ pub type Timeout = u32;
 /// to when the message should be notified
 // TODO: be specific on the unit of time, allow it to be specified
 #[derive(Clone, Eq, PartialEq, Debug, Encode, Decode, TypeInfo)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
 pub struct ActionNotificationTimeouts {
     pub action: Timeout,
     pub notification: Timeout,
@@ -207,8 +267,17 @@ impl Default for ActionNotificationTimeouts {
     }
 }
 
+impl ActionNotificationTimeouts {
+    /// The action timeout, in milliseconds, converted to a number of blocks. Always rounds up so a partial block still counts.
+    pub fn action_in_blocks(&self, block_time_ms: u32) -> u32 {
+        let block_time_ms = block_time_ms.max(1);
+        self.action / block_time_ms + (self.action % block_time_ms != 0) as u32
+    }
+}
+
 // TODO: add requested here so we can time when the message was sent
 #[derive(Clone, Eq, PartialEq, Debug, Default, Encode, Decode, TypeInfo)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
 pub struct Timeouts {
     /// Timeouts in relation to when the message should be sent
     pub sent: ActionNotificationTimeouts,
@@ -234,9 +303,19 @@ impl Timeouts {
             responded: responded.unwrap_or_default(),
         }
     }
+
+    /// The most blocks a message may take to be resolved by its source, from being submitted to being responded to.
+    pub fn lifetime_in_blocks(&self, block_time_ms: u32) -> u32 {
+        self.sent
+            .action_in_blocks(block_time_ms)
+            .saturating_add(self.delivered.action_in_blocks(block_time_ms))
+            .saturating_add(self.executed.action_in_blocks(block_time_ms))
+            .saturating_add(self.responded.action_in_blocks(block_time_ms))
+    }
 }
 
 #[derive(Clone, Eq, PartialEq, Debug, Default, Encode, Decode, TypeInfo)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
 pub struct Fees {
     /// The asset to pay the fees in, otherwise native
     pub asset: Option<AssetId>,
@@ -296,6 +375,7 @@ impl Fees {
 ///
 /// Utilised by the queue to determine when to stop progressing an item
 #[derive(Debug, Clone, PartialEq, Default, Eq, Encode, Decode, TypeInfo)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
 pub struct XbiTimeSheet<BlockNumber: FullCodec + TypeInfo> {
     /// When a message was submitted
     pub submitted: Option<BlockNumber>,
@@ -309,6 +389,8 @@ pub struct XbiTimeSheet<BlockNumber: FullCodec + TypeInfo> {
     pub responded: Option<BlockNumber>,
     /// When a response was received by the initiater
     pub received: Option<BlockNumber>,
+    /// Each parachain that forwarded the message along its route, and when it was forwarded
+    pub hops: Vec<(u32, BlockNumber)>,
 }
 
 pub enum Timestamp<BlockNumber: FullCodec + TypeInfo> {
@@ -324,6 +406,8 @@ pub enum Timestamp<BlockNumber: FullCodec + TypeInfo> {
     Responded(BlockNumber),
     /// When a response was received by the initiater
     Received(BlockNumber),
+    /// When a message was forwarded by the parachain with the given id
+    Forwarded(u32, BlockNumber),
 }
 
 impl<BlockNumber: FullCodec + TypeInfo> XbiTimeSheet<BlockNumber> {
@@ -335,6 +419,7 @@ impl<BlockNumber: FullCodec + TypeInfo> XbiTimeSheet<BlockNumber> {
             executed: None,
             responded: None,
             received: None,
+            hops: vec![],
         }
     }
 
@@ -346,6 +431,7 @@ impl<BlockNumber: FullCodec + TypeInfo> XbiTimeSheet<BlockNumber> {
             Timestamp::Executed(block) => self.executed = Some(block),
             Timestamp::Responded(block) => self.responded = Some(block),
             Timestamp::Received(block) => self.received = Some(block),
+            Timestamp::Forwarded(para_id, block) => self.hops.push((para_id, block)),
         }
         self
     }
@@ -353,6 +439,7 @@ impl<BlockNumber: FullCodec + TypeInfo> XbiTimeSheet<BlockNumber> {
 
 /// Additional information about the target, costs and any user defined timeouts relating to the message
 #[derive(Clone, Eq, PartialEq, Debug, Default, Encode, Decode, TypeInfo)]
+#[cfg_attr(feature = "std", derive(serde::Serialize, serde::Deserialize))]
 pub struct XbiMetadata {
     /// The XBI identifier
     id: sp_core::H256,
@@ -360,6 +447,8 @@ pub struct XbiMetadata {
     pub dest_para_id: u32,
     /// The src parachain
     pub src_para_id: u32,
+    /// The parachains the message is forwarded through, in order, before it reaches the destination
+    pub route: Vec<u32>,
     /// User provided timeouts
     pub timeouts: Timeouts,
     /// The time sheet providing timestamps to each of the xbi progression
@@ -407,24 +496,39 @@ impl XbiMetadata {
         self.sane_fields().concat()
     }
 
+    /// The id of the metadata for a nonce and an optional seed, this is the id `enrich_id` assigns
+    pub fn derive_id<Hashing: Hasher<Out = sp_core::H256>>(
+        &self,
+        nonce: u32,
+        seed: Option<&[u8]>,
+    ) -> sp_core::H256 {
+        let mut hash_contents = vec![nonce.encode(), self.sane_hashable_fields()];
+        if let Some(seed) = seed {
+            hash_contents.push(seed.to_vec());
+        }
+
+        Hashing::hash(&hash_contents.concat()[..])
+    }
+
     pub fn enrich_id<Hashing: Hasher<Out = sp_core::H256>>(
         &mut self,
         nonce: u32,
         seed: Option<&[u8]>,
     ) -> &mut Self {
         if self.id == sp_core::H256::default() {
-            let mut hash_contents = vec![nonce.encode(), self.sane_hashable_fields()];
-            if let Some(seed) = seed {
-                hash_contents.push(seed.to_vec());
-            }
-
-            self.id = Hashing::hash(&hash_contents.concat()[..]);
+            self.id = self.derive_id::<Hashing>(nonce, seed);
         } else {
             log::warn!("Can only enrich the id if it has not been enriched already");
         }
         self
     }
 
+    /// Assign the id of the metadata, replacing any id it was enriched with
+    pub fn set_id(&mut self, id: sp_core::H256) -> &mut Self {
+        self.id = id;
+        self
+    }
+
     pub fn enrich_origin(&mut self, origin: &AccountId32) -> &mut Self {
         if self.origin.is_none() {
             self.origin = Some(origin.clone());
@@ -460,6 +564,7 @@ impl XbiMetadata {
             id: Default::default(),
             dest_para_id,
             src_para_id,
+            route: vec![],
             timeouts,
             timesheet: Default::default(),
             fees,
@@ -472,6 +577,85 @@ impl XbiMetadata {
         self.timesheet.progress(timestamp);
         self
     }
+
+    /// The parachain the message should be sent to from `here`. This is the hop after `here` on the route,
+    /// or the first hop when sending from the source, and the destination once the route is exhausted.
+    pub fn next_hop(&self, here: u32) -> u32 {
+        let next = match self.route.iter().position(|hop| *hop == here) {
+            Some(position) => self.route.get(position + 1),
+            None => self.route.first(),
+        };
+        next.copied().unwrap_or(self.dest_para_id)
+    }
+
+    /// The parachain the message was sent to `here` from, the inverse of `next_hop`. This is the hop before `here` on the route,
+    /// or the source when `here` is the first hop, and the last hop when `here` is the destination.
+    pub fn previous_hop(&self, here: u32) -> u32 {
+        let previous = match self.route.iter().position(|hop| *hop == here) {
+            Some(position) => position
+                .checked_sub(1)
+                .and_then(|previous| self.route.get(previous)),
+            None => self.route.last(),
+        };
+        previous.copied().unwrap_or(self.src_para_id)
+    }
+
+    /// Whether the route can be followed, it must not visit any parachain twice, including the source and the destination.
+    pub fn has_valid_route(&self) -> bool {
+        let mut visited = vec![self.src_para_id, self.dest_para_id];
+        for hop in &self.route {
+            if visited.contains(hop) {
+                return false;
+            }
+            visited.push(*hop);
+        }
+        true
+    }
+
+    /// Validate the timesheet against the user provided timeouts, returning the timeout status of the stage that breached
+    /// its deadline at block `now`, if any.
+    ///
+    /// Deadlines are only ever measured against timestamps recorded by the same chain:
+    /// - on the target, execution must happen within `executed` of the message being delivered.
+    /// - on the source, a message must be sent within `sent` of being submitted.
+    /// - on the source, a sent message must be responded to within `delivered + executed + responded` of being sent. Since
+    ///   the source cannot observe the target, this is resolved as a `DeliveryTimeout`.
+    pub fn timeout_status(&self, now: u32, block_time_ms: u32) -> Option<Status> {
+        let timesheet = &self.timesheet;
+        if timesheet.received.is_some() {
+            return None;
+        }
+
+        let deadline_breached = |since: u32, blocks: u32| now > since.saturating_add(blocks);
+
+        match (
+            timesheet.submitted,
+            timesheet.sent,
+            timesheet.delivered,
+            timesheet.executed,
+        ) {
+            (_, _, Some(delivered), None) => deadline_breached(
+                delivered,
+                self.timeouts.executed.action_in_blocks(block_time_ms),
+            )
+            .then_some(Status::ExecutionTimeout),
+            (_, Some(sent), None, None) => deadline_breached(
+                sent,
+                self.timeouts
+                    .delivered
+                    .action_in_blocks(block_time_ms)
+                    .saturating_add(self.timeouts.executed.action_in_blocks(block_time_ms))
+                    .saturating_add(self.timeouts.responded.action_in_blocks(block_time_ms)),
+            )
+            .then_some(Status::DeliveryTimeout),
+            (Some(submitted), None, None, None) => deadline_breached(
+                submitted,
+                self.timeouts.sent.action_in_blocks(block_time_ms),
+            )
+            .then_some(Status::SendTimeout),
+            _ => None,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -560,6 +744,114 @@ mod tests {
         );
     }
 
+    #[test]
+    fn ids_are_derived_from_the_nonce_and_seed() {
+        let mut meta = XbiMetadata::default();
+        let derive = |meta: &XbiMetadata, nonce, seed| {
+            meta.derive_id::<sp_runtime::traits::BlakeTwo256>(nonce, seed)
+        };
+
+        let seeded = derive(&meta, 1, Some(&b"seed"[..]));
+        assert_ne!(seeded, derive(&meta, 1, None));
+        assert_ne!(seeded, derive(&meta, 2, Some(&b"seed"[..])));
+        assert_eq!(meta.id, sp_core::H256::default());
+
+        meta.enrich_id::<sp_runtime::traits::BlakeTwo256>(1, Some(&b"seed"[..]));
+        assert_eq!(meta.get_id(), seeded);
+
+        // Setting the id replaces the enriched id
+        meta.set_id(sp_core::H256::repeat_byte(1));
+        assert_eq!(meta.get_id(), sp_core::H256::repeat_byte(1));
+    }
+
+    #[test]
+    fn action_timeouts_round_up_to_blocks() {
+        let timeouts = ActionNotificationTimeouts {
+            action: 6001,
+            notification: 0,
+        };
+        assert_eq!(timeouts.action_in_blocks(6000), 2);
+        assert_eq!(
+            ActionNotificationTimeouts::default().action_in_blocks(6000),
+            16
+        );
+        assert_eq!(timeouts.action_in_blocks(0), 6001);
+    }
+
+    #[test]
+    fn lifetime_covers_every_timeout() {
+        assert_eq!(Timeouts::default().lifetime_in_blocks(6000), 64);
+
+        let timeouts = Timeouts::new(
+            Some(ActionNotificationTimeouts {
+                action: 6001,
+                notification: 0,
+            }),
+            None,
+            None,
+            Some(ActionNotificationTimeouts {
+                action: u32::MAX,
+                notification: 0,
+            }),
+        );
+        assert_eq!(timeouts.lifetime_in_blocks(6000), 2 + 16 + 16 + 715_828);
+        assert_eq!(timeouts.lifetime_in_blocks(0), u32::MAX);
+    }
+
+    #[test]
+    fn unsent_message_times_out_on_send() {
+        let mut meta = XbiMetadata {
+            timeouts: Timeouts::new(
+                Some(ActionNotificationTimeouts {
+                    action: 12000,
+                    notification: 0,
+                }),
+                None,
+                None,
+                None,
+            ),
+            ..Default::default()
+        };
+        assert_eq!(meta.timeout_status(100, 6000), None);
+
+        meta.progress(Submitted(1));
+        assert_eq!(meta.timeout_status(3, 6000), None);
+        assert_eq!(meta.timeout_status(4, 6000), Some(Status::SendTimeout));
+
+        meta.progress(Sent(3));
+        assert_eq!(meta.timeout_status(4, 6000), None);
+    }
+
+    #[test]
+    fn sent_message_times_out_on_delivery() {
+        let mut meta = XbiMetadata::default();
+        meta.progress(Submitted(1)).progress(Sent(1));
+
+        // 16 blocks for each of delivered, executed and responded
+        assert_eq!(meta.timeout_status(49, 6000), None);
+        assert_eq!(meta.timeout_status(50, 6000), Some(Status::DeliveryTimeout));
+
+        meta.progress(Received(50));
+        assert_eq!(meta.timeout_status(50, 6000), None);
+    }
+
+    #[test]
+    fn delivered_message_times_out_on_execution() {
+        let mut meta = XbiMetadata::default();
+        // Source timestamps are never compared with the target ones
+        meta.progress(Submitted(1000)).progress(Sent(1000));
+        meta.progress(Delivered(10));
+
+        assert_eq!(meta.timeout_status(26, 6000), None);
+        assert_eq!(
+            meta.timeout_status(27, 6000),
+            Some(Status::ExecutionTimeout)
+        );
+
+        meta.progress(Executed(26));
+        assert_eq!(meta.timeout_status(27, 6000), None);
+    }
+
     // test that the sane_hashable fields do not contain the insane fields
     #[test]
     fn test_sane_hashable_fields() {
@@ -568,4 +860,86 @@ mod tests {
         assert!(!sane_fields.contains(&meta.id.encode()));
         assert!(!sane_fields.contains(&meta.timesheet.encode()));
     }
+
+    #[test]
+    fn messages_follow_their_route() {
+        let mut meta = XbiMetadata {
+            src_para_id: 1,
+            dest_para_id: 4,
+            ..Default::default()
+        };
+        assert_eq!(meta.next_hop(1), 4);
+        assert_eq!(meta.previous_hop(4), 1);
+
+        meta.route = vec![2, 3];
+        assert_eq!(meta.next_hop(1), 2);
+        assert_eq!(meta.next_hop(2), 3);
+        assert_eq!(meta.next_hop(3), 4);
+        assert_eq!(meta.previous_hop(2), 1);
+        assert_eq!(meta.previous_hop(3), 2);
+        assert_eq!(meta.previous_hop(4), 3);
+        assert!(meta.has_valid_route());
+
+        meta.progress(Forwarded(2, 10)).progress(Forwarded(3, 20));
+        assert_eq!(meta.get_timesheet().hops, vec![(2, 10), (3, 20)]);
+    }
+
+    #[test]
+    fn routes_cannot_revisit_a_parachain() {
+        let mut meta = XbiMetadata {
+            src_para_id: 1,
+            dest_para_id: 4,
+            route: vec![2, 2],
+            ..Default::default()
+        };
+        assert!(!meta.has_valid_route());
+
+        meta.route = vec![2, 4];
+        assert!(!meta.has_valid_route());
+
+        meta.route = vec![1];
+        assert!(!meta.has_valid_route());
+    }
+
+    #[test]
+    fn result_instruction_carries_the_response() {
+        let mut metadata = XbiMetadata::default();
+        metadata.fees.push_aggregate(42);
+        let result = XbiResult {
+            status: Status::FailedExecution,
+            output: vec![1, 2],
+            witness: vec![3],
+        };
+
+        let format = XbiFormat::from_result(result.clone(), metadata.clone());
+        assert!(matches!(
+            format.instr,
+            XbiInstruction::Result {
+                actual_aggregated_costs: 42,
+                ..
+            }
+        ));
+        assert_eq!(format.into_response(), Ok((result, metadata)));
+
+        assert_eq!(
+            XbiFormat::default().into_response(),
+            Err(XbiFormat::default())
+        );
+    }
+
+    #[test]
+    fn result_instruction_reports_the_aggregated_costs() {
+        let mut format = XbiFormat::from_result(XbiResult::default(), XbiMetadata::default());
+        if let XbiInstruction::Result {
+            ref mut actual_aggregated_costs,
+            ..
+        } = format.instr
+        {
+            *actual_aggregated_costs = 7;
+        }
+        format.metadata.fees.push_aggregate(42);
+
+        let (_, metadata) = format.into_response().unwrap();
+        assert_eq!(metadata.fees.get_aggregated_cost(), 7);
+    }
 }
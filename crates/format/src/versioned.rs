@@ -0,0 +1,198 @@
+use codec::{Decode, Encode};
+use scale_info::TypeInfo;
+
+use crate::{Fees, XbiFormat, XbiFormatV1, XbiFormatV2, XbiInstruction};
+
+/// A version of the XBI standard
+pub type XbiVersion = u32;
+
+/// The version of the standard this crate speaks natively
+pub const XBI_VERSION: XbiVersion = 3;
+
+/// An XBI message tagged with the version of the standard it is encoded with, so that peers can
+/// upgrade independently.
+#[derive(Clone, Eq, PartialEq, Debug, Encode, Decode, TypeInfo)]
+pub enum VersionedXbiFormat {
+    /// Variable sized fields are prefixed with a `u8` length
+    #[codec(index = 1)]
+    V1(XbiFormatV1),
+    /// Variable sized fields are prefixed with a compact length
+    #[codec(index = 2)]
+    V2(XbiFormatV2),
+    /// The metadata can route the message through other parachains
+    #[codec(index = 3)]
+    V3(XbiFormat),
+}
+
+impl VersionedXbiFormat {
+    pub fn version(&self) -> XbiVersion {
+        match self {
+            VersionedXbiFormat::V1(_) => 1,
+            VersionedXbiFormat::V2(_) => 2,
+            VersionedXbiFormat::V3(_) => 3,
+        }
+    }
+
+    /// The fees of the message, these are the same in every version
+    pub fn fees(&self) -> &Fees {
+        match self {
+            VersionedXbiFormat::V1(format) => &format.metadata.fees,
+            VersionedXbiFormat::V2(format) => &format.metadata.fees,
+            VersionedXbiFormat::V3(format) => &format.metadata.fees,
+        }
+    }
+
+    /// Whether the message is a `Result` instruction, responding to a request rather than making one
+    pub fn is_result(&self) -> bool {
+        let instr = match self {
+            VersionedXbiFormat::V1(format) => format.instr.as_inner(),
+            VersionedXbiFormat::V2(format) => &format.instr,
+            VersionedXbiFormat::V3(format) => &format.instr,
+        };
+        matches!(instr, XbiInstruction::Result { .. })
+    }
+
+    /// Convert the message to the given version, failing if the version is unknown or the message
+    /// cannot be represented in it.
+    pub fn into_version(self, version: XbiVersion) -> Result<Self, &'static str> {
+        match version {
+            1 => Ok(VersionedXbiFormat::V1(XbiFormatV1::try_from(
+                XbiFormat::from(self),
+            )?)),
+            2 => Ok(VersionedXbiFormat::V2(XbiFormatV2::try_from(
+                XbiFormat::from(self),
+            )?)),
+            3 => Ok(VersionedXbiFormat::V3(self.into())),
+            _ => Err("Unsupported XBI version"),
+        }
+    }
+}
+
+impl From<XbiFormat> for VersionedXbiFormat {
+    fn from(format: XbiFormat) -> Self {
+        VersionedXbiFormat::V3(format)
+    }
+}
+
+impl From<VersionedXbiFormat> for XbiFormat {
+    fn from(format: VersionedXbiFormat) -> Self {
+        match format {
+            VersionedXbiFormat::V1(format) => format.into(),
+            VersionedXbiFormat::V2(format) => format.into(),
+            VersionedXbiFormat::V3(format) => format,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{XbiMetadata, XbiMetadataV1};
+    use sp_runtime::AccountId32;
+
+    fn wasm_call(data: Vec<u8>) -> XbiFormat {
+        XbiFormat {
+            instr: XbiInstruction::CallWasm {
+                dest: AccountId32::new([2; 32]),
+                value: 1,
+                gas_limit: 2,
+                storage_deposit_limit: None,
+                data,
+            },
+            metadata: XbiMetadata::default(),
+        }
+    }
+
+    #[test]
+    fn versioned_format_round_trips_in_every_version() {
+        let format = wasm_call(vec![8, 9]);
+
+        for version in 1..=XBI_VERSION {
+            let versioned = VersionedXbiFormat::from(format.clone())
+                .into_version(version)
+                .unwrap();
+            assert_eq!(versioned.version(), version);
+
+            let decoded: VersionedXbiFormat = Decode::decode(&mut &versioned.encode()[..]).unwrap();
+            assert_eq!(decoded, versioned);
+            assert_eq!(XbiFormat::from(decoded), format);
+        }
+    }
+
+    #[test]
+    fn cannot_downgrade_message_that_does_not_fit() {
+        let versioned = VersionedXbiFormat::from(wasm_call(vec![8; 300]));
+
+        assert_eq!(
+            versioned.clone().into_version(1),
+            Err("XBI instruction field is too long for the V1 format")
+        );
+        assert_eq!(
+            versioned.clone().into_version(2).map(XbiFormat::from),
+            Ok(versioned.clone().into())
+        );
+        assert_eq!(versioned.clone().into_version(3), Ok(versioned));
+    }
+
+    #[test]
+    fn cannot_downgrade_routed_message() {
+        let mut format = wasm_call(vec![]);
+        format.metadata.route = vec![3];
+        let versioned = VersionedXbiFormat::from(format);
+
+        for version in 1..XBI_VERSION {
+            assert_eq!(
+                versioned.clone().into_version(version),
+                Err("XBI routes are not supported before the V3 format")
+            );
+        }
+        assert_eq!(versioned.clone().into_version(3), Ok(versioned));
+    }
+
+    #[test]
+    fn legacy_versions_decode_the_metadata_before_routing() {
+        let format = wasm_call(vec![8, 9]);
+        let legacy = XbiMetadataV1::try_from(format.metadata.clone()).unwrap();
+
+        // A V2 message as encoded by a peer that predates routing
+        let mut encoded = vec![2u8];
+        encoded.extend(format.instr.encode());
+        encoded.extend(legacy.encode());
+
+        let decoded: VersionedXbiFormat = Decode::decode(&mut &encoded[..]).unwrap();
+        assert_eq!(decoded.version(), 2);
+        assert_eq!(XbiFormat::from(decoded), format);
+    }
+
+    #[test]
+    fn results_are_recognised_in_every_version_that_has_them() {
+        let result = XbiFormat::from_result(Default::default(), XbiMetadata::default());
+
+        // The V1 format predates the `Result` instruction
+        for version in 2..=XBI_VERSION {
+            let versioned = VersionedXbiFormat::from(result.clone())
+                .into_version(version)
+                .unwrap();
+            assert!(versioned.is_result());
+            assert!(!VersionedXbiFormat::from(wasm_call(vec![]))
+                .into_version(version)
+                .unwrap()
+                .is_result());
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let versioned = VersionedXbiFormat::from(wasm_call(vec![]));
+
+        assert_eq!(
+            versioned.clone().into_version(0),
+            Err("Unsupported XBI version")
+        );
+        assert_eq!(
+            versioned.into_version(XBI_VERSION + 1),
+            Err("Unsupported XBI version")
+        );
+        assert!(<VersionedXbiFormat as Decode>::decode(&mut &[4u8][..]).is_err());
+    }
+}
@@ -0,0 +1,675 @@
+use codec::{Compact, Decode, Encode, Input, Output};
+
+pub use crate::*;
+
+pub mod v1;
+pub mod v2;
+
+pub use v1::{XbiFormatV1, XbiInstructionV1, XbiMetadataV1, XbiTimeSheetV1};
+pub use v2::{XbiFormatV2, XbiMetadataV2};
+
+/// The largest length accepted for any variable sized field of an instruction when decoding.
+///
+/// This stops a malicious length prefix from making us allocate more than a message could ever carry.
+pub const MAX_XBI_FIELD_LEN: u32 = 1024 * 1024;
+
+/// Decode the compact length prefix of a SCALE vector, checking it against the bounds of the input
+/// before anything is allocated.
+fn decode_bounded_len<I: Input>(input: &mut I) -> Result<u32, codec::Error> {
+    let len = <Compact<u32>>::decode(input)?.0;
+
+    if len > MAX_XBI_FIELD_LEN {
+        return Err("XBI field length exceeds the maximum".into());
+    }
+
+    // Every element takes at least a byte, so we can reject lengths the input could never fill
+    if let Some(remaining_len) = input.remaining_len()? {
+        if len as usize > remaining_len {
+            return Err("XBI field length exceeds the remaining input".into());
+        }
+    }
+
+    Ok(len)
+}
+
+/// Decode a SCALE vector with a bounded length prefix
+fn decode_bounded_vec<T: Decode, I: Input>(input: &mut I) -> Result<Vec<T>, codec::Error> {
+    let len = decode_bounded_len(input)?;
+    (0..len).map(|_| T::decode(input)).collect()
+}
+
+impl XbiInstruction {
+    /// The byte identifying the instruction on the wire
+    pub fn identifier(&self) -> u8 {
+        match self {
+            XbiInstruction::Unknown { identifier, .. } => *identifier,
+            XbiInstruction::CallNative { .. } => 1,
+            XbiInstruction::CallEvm { .. } => 2,
+            XbiInstruction::CallWasm { .. } => 3,
+            XbiInstruction::CallCustom { .. } => 4,
+            XbiInstruction::Transfer { .. } => 5,
+            XbiInstruction::TransferAssets { .. } => 6,
+            XbiInstruction::Swap { .. } => 7,
+            XbiInstruction::AddLiquidity { .. } => 8,
+            XbiInstruction::RemoveLiquidity { .. } => 9,
+            XbiInstruction::GetPrice { .. } => 10,
+            XbiInstruction::Batch { .. } => 11,
+            XbiInstruction::Result { .. } => 255,
+        }
+    }
+}
+
+/// Decode the instructions of a batch, rejecting any nested batches before they are decoded.
+fn decode_batch<I: Input>(input: &mut I) -> Result<Vec<XbiInstruction>, codec::Error> {
+    let len = decode_bounded_len(input)?;
+    (0..len)
+        .map(|_| match input.read_byte()? {
+            11 => Err("XBI batches cannot be nested".into()),
+            identifier => XbiInstruction::decode_with_identifier(identifier, input),
+        })
+        .collect()
+}
+
+impl XbiInstruction {
+    /// Decode the params of an instruction whose identifier has already been read
+    fn decode_with_identifier<I: Input>(
+        identifier: u8,
+        input: &mut I,
+    ) -> Result<Self, codec::Error> {
+        match identifier {
+            0 => Err("Unknown XBI Order".into()),
+            1 => Ok(XbiInstruction::CallNative {
+                payload: decode_bounded_vec(input)?,
+            }),
+            2 => Ok(XbiInstruction::CallEvm {
+                source: Decode::decode(input)?,
+                target: Decode::decode(input)?,
+                value: Decode::decode(input)?,
+                input: decode_bounded_vec(input)?,
+                gas_limit: Decode::decode(input)?,
+                max_fee_per_gas: Decode::decode(input)?,
+                max_priority_fee_per_gas: Decode::decode(input)?,
+                nonce: Decode::decode(input)?,
+                access_list: decode_bounded_vec::<(AccountId20, Vec<sp_core::H256>), _>(input)?,
+            }),
+            3 => Ok(XbiInstruction::CallWasm {
+                dest: Decode::decode(input)?,
+                value: Decode::decode(input)?,
+                gas_limit: Decode::decode(input)?,
+                storage_deposit_limit: Decode::decode(input)?,
+                data: decode_bounded_vec(input)?,
+            }),
+            4 => Ok(XbiInstruction::CallCustom {
+                caller: Decode::decode(input)?,
+                dest: Decode::decode(input)?,
+                value: Decode::decode(input)?,
+                input: decode_bounded_vec(input)?,
+                limit: Decode::decode(input)?,
+                additional_params: decode_bounded_vec(input)?,
+            }),
+            5 => Ok(XbiInstruction::Transfer {
+                dest: Decode::decode(input)?,
+                value: Decode::decode(input)?,
+            }),
+            6 => Ok(XbiInstruction::TransferAssets {
+                currency_id: Decode::decode(input)?,
+                dest: Decode::decode(input)?,
+                value: Decode::decode(input)?,
+            }),
+            7 => Ok(XbiInstruction::Swap {
+                asset_out: Decode::decode(input)?,
+                asset_in: Decode::decode(input)?,
+                amount: Decode::decode(input)?,
+                max_limit: Decode::decode(input)?,
+                discount: Decode::decode(input)?,
+            }),
+            8 => Ok(XbiInstruction::AddLiquidity {
+                asset_a: Decode::decode(input)?,
+                asset_b: Decode::decode(input)?,
+                amount_a: Decode::decode(input)?,
+                amount_b_max_limit: Decode::decode(input)?,
+            }),
+            9 => Ok(XbiInstruction::RemoveLiquidity {
+                asset_a: Decode::decode(input)?,
+                asset_b: Decode::decode(input)?,
+                liquidity_amount: Decode::decode(input)?,
+            }),
+            10 => Ok(XbiInstruction::GetPrice {
+                asset_a: Decode::decode(input)?,
+                asset_b: Decode::decode(input)?,
+                amount: Decode::decode(input)?,
+            }),
+            11 => Ok(XbiInstruction::Batch {
+                instructions: decode_batch(input)?,
+            }),
+            255 => Ok(XbiInstruction::Result {
+                outcome: Decode::decode(input)?,
+                output: decode_bounded_vec(input)?,
+                witness: decode_bounded_vec(input)?,
+                actual_aggregated_costs: Decode::decode(input)?,
+            }),
+            identifier => Ok(XbiInstruction::Unknown {
+                identifier,
+                params: decode_bounded_vec(input)?,
+            }),
+        }
+    }
+}
+
+impl Decode for XbiInstruction {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, codec::Error> {
+        let identifier = input.read_byte()?;
+        XbiInstruction::decode_with_identifier(identifier, input)
+    }
+}
+
+/*
+ * Encoding of XBI instruction is defined as follows:
+ *  identifier / u8
+ *  scale-encoded params, in the order they are declared // bytes
+ *
+ * Every variable sized field is prefixed with its SCALE compact length.
+ */
+impl Encode for XbiInstruction {
+    fn encode_to<T: Output + ?Sized>(&self, dest_bytes: &mut T) {
+        dest_bytes.push_byte(self.identifier());
+        match self {
+            XbiInstruction::Unknown { params, .. } => params.encode_to(dest_bytes),
+            XbiInstruction::CallNative { payload } => payload.encode_to(dest_bytes),
+            XbiInstruction::CallEvm {
+                source,
+                target,
+                value,
+                input,
+                gas_limit,
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+                nonce,
+                access_list,
+            } => {
+                source.encode_to(dest_bytes);
+                target.encode_to(dest_bytes);
+                value.encode_to(dest_bytes);
+                input.encode_to(dest_bytes);
+                gas_limit.encode_to(dest_bytes);
+                max_fee_per_gas.encode_to(dest_bytes);
+                max_priority_fee_per_gas.encode_to(dest_bytes);
+                nonce.encode_to(dest_bytes);
+                access_list.encode_to(dest_bytes);
+            }
+            XbiInstruction::CallWasm {
+                dest,
+                value,
+                gas_limit,
+                storage_deposit_limit,
+                data,
+            } => {
+                dest.encode_to(dest_bytes);
+                value.encode_to(dest_bytes);
+                gas_limit.encode_to(dest_bytes);
+                storage_deposit_limit.encode_to(dest_bytes);
+                data.encode_to(dest_bytes);
+            }
+            XbiInstruction::CallCustom {
+                caller,
+                dest,
+                value,
+                input,
+                limit,
+                additional_params,
+            } => {
+                caller.encode_to(dest_bytes);
+                dest.encode_to(dest_bytes);
+                value.encode_to(dest_bytes);
+                input.encode_to(dest_bytes);
+                limit.encode_to(dest_bytes);
+                additional_params.encode_to(dest_bytes);
+            }
+            XbiInstruction::Transfer { dest, value } => {
+                dest.encode_to(dest_bytes);
+                value.encode_to(dest_bytes);
+            }
+            XbiInstruction::TransferAssets {
+                currency_id,
+                dest,
+                value,
+            } => {
+                currency_id.encode_to(dest_bytes);
+                dest.encode_to(dest_bytes);
+                value.encode_to(dest_bytes);
+            }
+            XbiInstruction::Swap {
+                asset_out,
+                asset_in,
+                amount,
+                max_limit,
+                discount,
+            } => {
+                asset_out.encode_to(dest_bytes);
+                asset_in.encode_to(dest_bytes);
+                amount.encode_to(dest_bytes);
+                max_limit.encode_to(dest_bytes);
+                discount.encode_to(dest_bytes);
+            }
+            XbiInstruction::AddLiquidity {
+                asset_a,
+                asset_b,
+                amount_a,
+                amount_b_max_limit,
+            } => {
+                asset_a.encode_to(dest_bytes);
+                asset_b.encode_to(dest_bytes);
+                amount_a.encode_to(dest_bytes);
+                amount_b_max_limit.encode_to(dest_bytes);
+            }
+            XbiInstruction::RemoveLiquidity {
+                asset_a,
+                asset_b,
+                liquidity_amount,
+            } => {
+                asset_a.encode_to(dest_bytes);
+                asset_b.encode_to(dest_bytes);
+                liquidity_amount.encode_to(dest_bytes);
+            }
+            XbiInstruction::GetPrice {
+                asset_a,
+                asset_b,
+                amount,
+            } => {
+                asset_a.encode_to(dest_bytes);
+                asset_b.encode_to(dest_bytes);
+                amount.encode_to(dest_bytes);
+            }
+            XbiInstruction::Batch { instructions } => instructions.encode_to(dest_bytes),
+            XbiInstruction::Result {
+                outcome,
+                output,
+                witness,
+                actual_aggregated_costs,
+            } => {
+                outcome.encode_to(dest_bytes);
+                output.encode_to(dest_bytes);
+                witness.encode_to(dest_bytes);
+                actual_aggregated_costs.encode_to(dest_bytes);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::XbiInstruction;
+    use crate::{ActionNotificationTimeouts, XbiFormat, XbiMetadata};
+    use codec::{Decode, Encode};
+
+    #[test]
+    fn custom_encodes_decodes_xbi_evm() {
+        let xbi_evm = XbiInstruction::CallEvm {
+            source: AccountId20::repeat_byte(3),
+            target: AccountId20::repeat_byte(2),
+            value: sp_core::U256([1, 0, 0, 0]),
+            input: vec![8, 9],
+            gas_limit: 2,
+            max_fee_per_gas: sp_core::U256([4, 5, 6, 7]),
+            max_priority_fee_per_gas: None,
+            nonce: Some(sp_core::U256([3, 4, 6, 7])),
+            access_list: vec![],
+        };
+
+        let decoded_xbi_evm: XbiInstruction = Decode::decode(&mut &xbi_evm.encode()[..]).unwrap();
+        assert_eq!(decoded_xbi_evm.encode(), xbi_evm.encode());
+        assert_eq!(xbi_evm, decoded_xbi_evm);
+    }
+
+    #[test]
+    fn custom_encodes_decodes_xbi_evm_and_metadata() {
+        let xbi_evm_format = XbiFormat {
+            instr: XbiInstruction::CallEvm {
+                source: AccountId20::repeat_byte(3),
+                target: AccountId20::repeat_byte(2),
+                value: sp_core::U256([1, 0, 0, 0]),
+                input: vec![8, 9],
+                gas_limit: 2,
+                max_fee_per_gas: sp_core::U256([4, 5, 6, 7]),
+                max_priority_fee_per_gas: None,
+                nonce: Some(sp_core::U256([3, 4, 6, 7])),
+                access_list: vec![],
+            },
+            metadata: XbiMetadata {
+                id: sp_core::H256::repeat_byte(2),
+                dest_para_id: 3u32,
+                src_para_id: 4u32,
+                route: vec![5u32],
+                timeouts: Timeouts::new(
+                    Some(ActionNotificationTimeouts {
+                        action: 1u32,
+                        notification: 2u32,
+                    }),
+                    Some(ActionNotificationTimeouts {
+                        action: 3u32,
+                        notification: 4u32,
+                    }),
+                    Some(ActionNotificationTimeouts {
+                        action: 4u32,
+                        notification: 5u32,
+                    }),
+                    Some(ActionNotificationTimeouts {
+                        action: 6u32,
+                        notification: 7u32,
+                    }),
+                ),
+                timesheet: XbiTimeSheet {
+                    submitted: Some(8),
+                    sent: Some(9),
+                    delivered: Some(10),
+                    executed: Some(11),
+                    responded: Some(12),
+                    received: None,
+                    hops: vec![(5, 13)],
+                },
+                fees: Fees::new(Some(13), Some(14), Some(15)),
+                origin: None,
+            },
+        };
+
+        let decoded_xbi_evm: XbiFormat = Decode::decode(&mut &xbi_evm_format.encode()[..]).unwrap();
+        assert_eq!(decoded_xbi_evm.encode(), xbi_evm_format.encode());
+        assert_eq!(xbi_evm_format, decoded_xbi_evm);
+    }
+
+    #[test]
+    fn custom_encodes_decodes_empty_xbi_evm() {
+        let xbi_evm = XbiInstruction::CallEvm {
+            source: AccountId20::repeat_byte(3),
+            target: AccountId20::repeat_byte(2),
+            value: sp_core::U256([1, 0, 0, 0]),
+            input: vec![],
+            gas_limit: 2,
+            max_fee_per_gas: sp_core::U256([4, 5, 6, 7]),
+            max_priority_fee_per_gas: None,
+            nonce: None,
+            access_list: vec![],
+        };
+
+        let decoded_xbi_evm: XbiInstruction = Decode::decode(&mut &xbi_evm.encode()[..]).unwrap();
+        assert_eq!(decoded_xbi_evm.encode(), xbi_evm.encode());
+        assert_eq!(xbi_evm, decoded_xbi_evm);
+        assert_eq!(xbi_evm.encode().len(), 117);
+    }
+
+    #[test]
+    fn custom_encodes_decodes_xbi_wasm() {
+        let xbi_wasm = XbiInstruction::CallWasm {
+            dest: AccountId32::new([
+                2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+                2, 2, 2, 2,
+            ]),
+            value: 1,
+            gas_limit: 2,
+            storage_deposit_limit: Some(6),
+            data: vec![8, 9],
+        };
+
+        let decoded_xbi_wasm: XbiInstruction = Decode::decode(&mut &xbi_wasm.encode()[..]).unwrap();
+        assert_eq!(decoded_xbi_wasm.encode(), xbi_wasm.encode());
+        assert_eq!(xbi_wasm, decoded_xbi_wasm);
+    }
+
+    #[test]
+    fn custom_encodes_decodes_empty_xbi_wasm() {
+        let xbi_wasm = XbiInstruction::CallWasm {
+            dest: AccountId32::new([
+                2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+                2, 2, 2, 2,
+            ]),
+            value: 1,
+            gas_limit: 2,
+            storage_deposit_limit: None,
+            data: vec![],
+        };
+
+        let decoded_xbi_wasm: XbiInstruction = Decode::decode(&mut &xbi_wasm.encode()[..]).unwrap();
+        assert_eq!(decoded_xbi_wasm.encode(), xbi_wasm.encode());
+        assert_eq!(xbi_wasm, decoded_xbi_wasm);
+
+        // Minimum length of XBI::CallWasm with empty / none values
+        assert_eq!(xbi_wasm.encode().len(), 59);
+    }
+
+    #[test]
+    fn custom_encodes_decodes_xbi_call_custom() {
+        let xbi_call_custom = XbiInstruction::CallCustom {
+            caller: AccountId32::new([
+                2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+                2, 2, 2, 2,
+            ]),
+            dest: AccountId32::new([
+                2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+                2, 2, 2, 2,
+            ]),
+            value: 1,
+            input: vec![8, 9],
+            limit: 1,
+            additional_params: vec![10u8, 11u8],
+        };
+
+        let decoded_xbi_call_custom: XbiInstruction =
+            Decode::decode(&mut &xbi_call_custom.encode()[..]).unwrap();
+        assert_eq!(decoded_xbi_call_custom.encode(), xbi_call_custom.encode());
+        assert_eq!(xbi_call_custom, decoded_xbi_call_custom);
+    }
+
+    #[test]
+    fn custom_encodes_decodes_empty_xbi_call_custom() {
+        let xbi_call_custom = XbiInstruction::CallCustom {
+            caller: AccountId32::new([
+                2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+                2, 2, 2, 2,
+            ]),
+            dest: AccountId32::new([
+                2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+                2, 2, 2, 2,
+            ]),
+            value: 1,
+            input: vec![],
+            limit: 1,
+            additional_params: vec![],
+        };
+
+        let decoded_xbi_call_custom: XbiInstruction =
+            Decode::decode(&mut &xbi_call_custom.encode()[..]).unwrap();
+        assert_eq!(decoded_xbi_call_custom.encode(), xbi_call_custom.encode());
+        assert_eq!(xbi_call_custom, decoded_xbi_call_custom);
+        assert_eq!(xbi_call_custom.encode().len(), 91);
+    }
+
+    #[test]
+    fn custom_encodes_decodes_xbi_transfer() {
+        let xbi_transfer = XbiInstruction::Transfer {
+            dest: AccountId32::new([
+                2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+                2, 2, 2, 2,
+            ]),
+            value: 1,
+        };
+
+        let decoded_xbi_transfer: XbiInstruction =
+            Decode::decode(&mut &xbi_transfer.encode()[..]).unwrap();
+        assert_eq!(decoded_xbi_transfer.encode(), xbi_transfer.encode());
+        assert_eq!(xbi_transfer, decoded_xbi_transfer);
+        assert_eq!(xbi_transfer.encode().len(), 49);
+    }
+
+    #[test]
+    fn custom_encodes_decodes_xbi_transfer_assets() {
+        let xbi_transfer_assets = XbiInstruction::TransferAssets {
+            currency_id: 1u32,
+            dest: AccountId32::new([
+                2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+                2, 2, 2, 2,
+            ]),
+            value: 1,
+        };
+
+        let decoded_xbi_transfer_assets: XbiInstruction =
+            Decode::decode(&mut &xbi_transfer_assets.encode()[..]).unwrap();
+        assert_eq!(
+            decoded_xbi_transfer_assets.encode(),
+            xbi_transfer_assets.encode()
+        );
+        assert_eq!(xbi_transfer_assets, decoded_xbi_transfer_assets);
+        assert_eq!(xbi_transfer_assets.encode().len(), 53);
+    }
+
+    #[test]
+    fn encodes_decodes_fields_longer_than_u8() {
+        let xbi_evm = XbiInstruction::CallEvm {
+            source: AccountId20::repeat_byte(3),
+            target: AccountId20::repeat_byte(2),
+            value: sp_core::U256([1, 0, 0, 0]),
+            input: vec![7; 1024],
+            gas_limit: 2,
+            max_fee_per_gas: sp_core::U256([4, 5, 6, 7]),
+            max_priority_fee_per_gas: Some(sp_core::U256([1, 2, 3, 4])),
+            nonce: None,
+            access_list: vec![(
+                AccountId20::repeat_byte(1),
+                vec![sp_core::H256::repeat_byte(9); 10],
+            )],
+        };
+
+        let decoded_xbi_evm: XbiInstruction = Decode::decode(&mut &xbi_evm.encode()[..]).unwrap();
+        assert_eq!(xbi_evm, decoded_xbi_evm);
+
+        let xbi_call_custom = XbiInstruction::CallCustom {
+            caller: AccountId32::new([1; 32]),
+            dest: AccountId32::new([2; 32]),
+            value: 1,
+            input: vec![8; 300],
+            limit: 1,
+            additional_params: vec![10; 500],
+        };
+
+        let decoded_xbi_call_custom: XbiInstruction =
+            Decode::decode(&mut &xbi_call_custom.encode()[..]).unwrap();
+        assert_eq!(xbi_call_custom, decoded_xbi_call_custom);
+    }
+
+    #[test]
+    fn native_payload_does_not_consume_metadata() {
+        let xbi_format = XbiFormat {
+            instr: XbiInstruction::CallNative {
+                payload: vec![4; 512],
+            },
+            metadata: XbiMetadata::default(),
+        };
+
+        let decoded_xbi_format: XbiFormat = Decode::decode(&mut &xbi_format.encode()[..]).unwrap();
+        assert_eq!(xbi_format, decoded_xbi_format);
+    }
+
+    #[test]
+    fn encodes_decodes_unknown_instruction() {
+        let xbi_unknown = XbiInstruction::Unknown {
+            identifier: 42,
+            params: vec![1, 2, 3],
+        };
+
+        let decoded_xbi_unknown: XbiInstruction =
+            Decode::decode(&mut &xbi_unknown.encode()[..]).unwrap();
+        assert_eq!(xbi_unknown, decoded_xbi_unknown);
+    }
+
+    #[test]
+    fn rejects_short_input() {
+        let xbi_wasm = XbiInstruction::CallWasm {
+            dest: AccountId32::new([2; 32]),
+            value: 1,
+            gas_limit: 2,
+            storage_deposit_limit: None,
+            data: vec![8; 300],
+        };
+        let encoded = xbi_wasm.encode();
+
+        let err =
+            <XbiInstruction as Decode>::decode(&mut &encoded[..encoded.len() - 1]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "XBI field length exceeds the remaining input"
+        );
+        assert!(<XbiInstruction as Decode>::decode(&mut &encoded[..20]).is_err());
+    }
+
+    #[test]
+    fn rejects_lengths_over_the_maximum() {
+        let mut encoded = vec![1u8];
+        Compact(MAX_XBI_FIELD_LEN + 1).encode_to(&mut encoded);
+        encoded.extend(vec![0u8; 16]);
+
+        let err = <XbiInstruction as Decode>::decode(&mut &encoded[..]).unwrap_err();
+        assert_eq!(err.to_string(), "XBI field length exceeds the maximum");
+    }
+
+    #[test]
+    fn encodes_decodes_xbi_result() {
+        let xbi_result = XbiInstruction::Result {
+            outcome: Status::ExecutionLimitExceeded,
+            output: vec![1; 300],
+            witness: vec![2, 3],
+            actual_aggregated_costs: 1_000,
+        };
+
+        let decoded_xbi_result: XbiInstruction =
+            Decode::decode(&mut &xbi_result.encode()[..]).unwrap();
+        assert_eq!(xbi_result, decoded_xbi_result);
+    }
+
+    #[test]
+    fn encodes_decodes_xbi_batch() {
+        let xbi_batch = XbiInstruction::Batch {
+            instructions: vec![
+                XbiInstruction::Transfer {
+                    dest: AccountId32::new([2; 32]),
+                    value: 1,
+                },
+                XbiInstruction::CallNative {
+                    payload: vec![4; 300],
+                },
+            ],
+        };
+
+        let decoded_xbi_batch: XbiInstruction =
+            Decode::decode(&mut &xbi_batch.encode()[..]).unwrap();
+        assert_eq!(xbi_batch, decoded_xbi_batch);
+        assert_eq!(xbi_batch.encode()[..2], [11, 8]);
+    }
+
+    #[test]
+    fn rejects_nested_batches() {
+        let xbi_batch = XbiInstruction::Batch {
+            instructions: vec![XbiInstruction::Batch {
+                instructions: vec![],
+            }],
+        };
+
+        let err = <XbiInstruction as Decode>::decode(&mut &xbi_batch.encode()[..]).unwrap_err();
+        assert_eq!(err.to_string(), "XBI batches cannot be nested");
+    }
+
+    #[test]
+    fn xbi_result_matches_the_standard_layout() {
+        let xbi_result = XbiInstruction::Result {
+            outcome: Status::DeliveryTimeout,
+            output: vec![7, 8],
+            witness: vec![],
+            actual_aggregated_costs: 258,
+        };
+
+        // identifier, outcome, compact output, compact witness, little endian u128 costs
+        let mut expected = vec![255u8, 6, 8, 7, 8, 0, 2, 1];
+        expected.extend([0u8; 14]);
+        assert_eq!(xbi_result.encode(), expected);
+    }
+}
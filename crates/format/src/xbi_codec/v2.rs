@@ -0,0 +1,99 @@
+//! The second XBI wire format, where the variable sized fields of an instruction are prefixed with a
+//! compact length but the metadata cannot describe a route.
+//!
+//! This is kept so that peers which have not upgraded can still be understood, new messages should
+//! use [`XbiFormat`].
+use codec::{Decode, Encode};
+
+use crate::*;
+
+/// The V2 format did not change the metadata of V1
+pub type XbiMetadataV2 = XbiMetadataV1;
+
+/// An [`XbiFormat`] as understood by peers on the second version of the standard.
+#[derive(Clone, Eq, PartialEq, Debug, Encode, Decode, TypeInfo)]
+pub struct XbiFormatV2 {
+    pub instr: XbiInstruction,
+    pub metadata: XbiMetadataV2,
+}
+
+impl TryFrom<XbiFormat> for XbiFormatV2 {
+    type Error = &'static str;
+
+    fn try_from(format: XbiFormat) -> Result<Self, Self::Error> {
+        Ok(XbiFormatV2 {
+            instr: format.instr,
+            metadata: XbiMetadataV2::try_from(format.metadata)?,
+        })
+    }
+}
+
+impl From<XbiFormatV2> for XbiFormat {
+    fn from(format: XbiFormatV2) -> Self {
+        XbiFormat {
+            instr: format.instr,
+            metadata: format.metadata.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_like_the_format_before_routing() {
+        let mut metadata =
+            XbiMetadata::new(1, 2, Default::default(), Default::default(), None, 1, None);
+        metadata.progress(Timestamp::Submitted(3));
+        let format = XbiFormat {
+            instr: XbiInstruction::Transfer {
+                dest: AccountId32::new([2; 32]),
+                value: 1,
+            },
+            metadata: metadata.clone(),
+        };
+
+        let v2 = XbiFormatV2::try_from(format.clone()).unwrap();
+        // The metadata of V2 is the routed metadata without its route and hops
+        let mut expected = format.instr.encode();
+        expected.extend(metadata.get_id().encode());
+        expected.extend(2u32.encode());
+        expected.extend(1u32.encode());
+        expected.extend(metadata.timeouts.encode());
+        expected.extend(
+            (
+                Some(3u32),
+                None::<u32>,
+                None::<u32>,
+                None::<u32>,
+                None::<u32>,
+                None::<u32>,
+            )
+                .encode(),
+        );
+        expected.extend(metadata.fees.encode());
+        expected.extend(None::<AccountId32>.encode());
+        assert_eq!(v2.encode(), expected);
+
+        assert_eq!(XbiFormat::from(v2), format);
+    }
+
+    #[test]
+    fn rejects_routed_messages() {
+        let mut format = XbiFormat::default();
+        format.metadata.route = vec![3];
+
+        assert_eq!(
+            XbiFormatV2::try_from(format.clone()),
+            Err("XBI routes are not supported before the V3 format")
+        );
+
+        format.metadata.route = vec![];
+        format.metadata.progress(Timestamp::Forwarded(3, 1));
+        assert_eq!(
+            XbiFormatV2::try_from(format),
+            Err("XBI routes are not supported before the V3 format")
+        );
+    }
+}
@@ -10,7 +10,7 @@ use frame_system::EnsureRoot;
 use pallet_xcm::XcmPassthrough;
 use polkadot_parachain::primitives::Sibling;
 use sp_runtime::{
-    traits::{ConstU32, ConstU64},
+    traits::{ConstBool, ConstU128, ConstU32, ConstU64, ConstU8},
     AccountId32,
 };
 use xcm_builder::{
@@ -212,6 +212,8 @@ where
 parameter_types! {
     pub const XbiSovereign: AccountId = AccountId32::new([104u8; 32]);
     pub ReserveBalanceCustodian: AccountId = AccountId::new([64u8; 32]);
+    pub const DefaultPortalIndex: pallet_xbi_portal::PortalIndex =
+        pallet_xbi_portal::PortalIndex { pallet: 200, call: 1 };
 }
 
 impl pallet_xbi_portal::Config for Runtime {
@@ -222,17 +224,32 @@ impl pallet_xbi_portal::Config for Runtime {
     type CheckInLimit = ConstU32<100>;
     type CheckInterval = ConstU64<3>;
     type CheckOutLimit = ConstU32<100>;
+    type QueueWeightLimit = ConstU64<250_000_000_000>;
+    type MaxRetries = ConstU8<3>;
+    type RetryBackoff = ConstU64<3>;
     type Contracts = Contracts;
     type Currency = Balances;
+    type CustomVm = ();
+    type InstructionHandlers = pallet_xbi_portal::handlers::DefaultHandlers<Runtime>;
     type DeFi = ();
     type Event = Event;
     type Evm = Evm;
     type ExpectedBlockTimeMs = ConstU32<6000>;
     type ParachainId = ConstU32<3333>;
+    type DefaultPortalIndex = DefaultPortalIndex;
+    type PeerOrigin = pallet_xbi_portal::origins::EnsureSibling<Runtime, LocationToAccountId>;
+    type AllowLocalReceive = ConstBool<false>;
+    type MaxExecutedRetention = ConstU32<14_400>;
+    type ExecutedPruneLimit = ConstU32<1_000>;
+    type ForwardingFee = ConstU128<0>;
+    type LocationToAccountId = LocationToAccountId;
     type TimeoutChecksLimit = ConstU32<3000>;
     type Xcm = XcmRouter;
     type XcmSovereignOrigin = XbiSovereign;
     type FeeConversion = IdentityFee<Balance>;
+    type NativeCallFilter = frame_support::traits::Nothing;
     type ReserveBalanceCustodian = ReserveBalanceCustodian;
-    type NotificationWeight = ConstU64<100_000_000>;
+    type NotificationWeight = pallet_xbi_portal::impls::MeasuredNotificationWeight<Runtime>;
+    type WeightInfo = pallet_xbi_portal::weights::SubstrateWeight<Runtime>;
+    type ResponseWeightLimit = ConstU64<1_000_000_000>;
 }
@@ -13,7 +13,7 @@ use polkadot_primitives::v2::Moment;
 use sp_core::H256;
 use sp_runtime::{
     testing::Header,
-    traits::{ConstU32, ConstU64, Convert, IdentityLookup},
+    traits::{ConstBool, ConstU128, ConstU32, ConstU64, ConstU8, Convert, IdentityLookup},
     AccountId32,
 };
 use xcm::latest::prelude::*;
@@ -274,6 +274,8 @@ where
 parameter_types! {
     pub const XbiSovereign: AccountId = AccountId32::new([100u8; 32]);
     pub ReserveBalanceCustodian: AccountId = AccountId::new([64u8; 32]);
+    pub const DefaultPortalIndex: pallet_xbi_portal::PortalIndex =
+        pallet_xbi_portal::PortalIndex { pallet: 200, call: 1 };
 }
 
 impl pallet_xbi_portal::Config for Runtime {
@@ -284,19 +286,34 @@ impl pallet_xbi_portal::Config for Runtime {
     type CheckInLimit = ConstU32<100>;
     type CheckInterval = ConstU64<3>;
     type CheckOutLimit = ConstU32<100>;
+    type QueueWeightLimit = ConstU64<250_000_000_000>;
+    type MaxRetries = ConstU8<3>;
+    type RetryBackoff = ConstU64<3>;
     type Contracts = ();
     type Currency = Balances;
+    type CustomVm = ();
+    type InstructionHandlers = pallet_xbi_portal::handlers::DefaultHandlers<Runtime>;
     type DeFi = ();
     type Event = Event;
     type Evm = Evm;
     type ExpectedBlockTimeMs = ConstU32<6000>;
     type ParachainId = ConstU32<3333>;
+    type DefaultPortalIndex = DefaultPortalIndex;
+    type PeerOrigin = pallet_xbi_portal::origins::EnsureSibling<Runtime, LocationToAccountId>;
+    type AllowLocalReceive = ConstBool<false>;
+    type MaxExecutedRetention = ConstU32<14_400>;
+    type ExecutedPruneLimit = ConstU32<1_000>;
+    type ForwardingFee = ConstU128<0>;
+    type LocationToAccountId = LocationToAccountId;
     type TimeoutChecksLimit = ConstU32<3000>;
     type Xcm = XcmRouter;
     type XcmSovereignOrigin = XbiSovereign;
     type FeeConversion = IdentityFee<Balance>;
+    type NativeCallFilter = frame_support::traits::Nothing;
     type ReserveBalanceCustodian = ReserveBalanceCustodian;
-    type NotificationWeight = ConstU64<100_000_000>;
+    type NotificationWeight = pallet_xbi_portal::impls::MeasuredNotificationWeight<Runtime>;
+    type WeightInfo = pallet_xbi_portal::weights::SubstrateWeight<Runtime>;
+    type ResponseWeightLimit = ConstU64<1_000_000_000>;
 }
 
 pub type AssetId = u32;
@@ -67,7 +67,9 @@ mod tests {
     use substrate_abi::{SubstrateAbiConverter, TryConvert};
     use xcm::{latest::prelude::*, VersionedMultiLocation, VersionedXcm};
     use xcm_emulator::TestExt;
-    use xp_format::{Fees, Status, XbiFormat, XbiInstruction, XbiMetadata};
+    use xp_format::{
+        ActionNotificationTimeouts, Fees, Status, Timeouts, XbiFormat, XbiInstruction, XbiMetadata,
+    };
 
     const ASSET_ID: u32 = 1;
     const EXEC_COST: u128 = 90_000_000_000;
@@ -558,7 +560,8 @@ mod tests {
                         Default::default(),
                         Default::default(),
                     ),
-                }
+                },
+                None
             ));
 
             assert_xcmp_sent!(large);
@@ -622,7 +625,8 @@ mod tests {
                         Default::default(),
                         Default::default(),
                     ),
-                }
+                },
+                None
             ));
 
             crate::slim::log_all_events("Slim");
@@ -690,7 +694,8 @@ mod tests {
                         Default::default(),
                         Default::default(),
                     ),
-                }
+                },
+                None
             ));
             crate::slim::log_all_events("Slim");
             assert_xcmp_sent!(slim);
@@ -777,7 +782,8 @@ mod tests {
                         Default::default(),
                         Default::default(),
                     ),
-                }
+                },
+                None
             ));
 
             crate::slim::log_all_events("Slim");
@@ -837,7 +843,8 @@ mod tests {
                         Default::default(),
                         Default::default(),
                     ),
-                }
+                },
+                None
             ));
 
             crate::slim::log_all_events("Slim");
@@ -904,7 +911,8 @@ mod tests {
                         Default::default(),
                         Default::default(),
                     ),
-                }
+                },
+                None
             ));
             crate::slim::log_all_events("Slim");
             // Assert owner paid for the execution fees
@@ -980,7 +988,8 @@ mod tests {
                         Default::default(),
                         Default::default(),
                     ),
-                }
+                },
+                None
             ));
 
             crate::slim::log_all_events("Slim");
@@ -1046,7 +1055,8 @@ mod tests {
                         Default::default(),
                         Default::default(),
                     ),
-                }
+                },
+                None
             ));
 
             assert_ok!(slim::XbiPortal::process_queue(slim::Origin::root()));
@@ -1097,10 +1107,128 @@ mod tests {
         });
     }
 
-    // TODO:
     #[test]
     fn xbi_call_can_timeout() {
-        // most of the call is refunded minus transport fees
+        setup();
+        setup_default_assets();
+
+        println!(">>> [Slim] Queueing xbi message");
+        Slim::execute_with(|| {
+            assert_ok!(slim::XbiPortal::send(
+                slim::Origin::signed(ALICE),
+                xp_channel::ExecutionType::Async,
+                XbiFormat {
+                    instr: XbiInstruction::Transfer {
+                        dest: CONTRACT_CALLER,
+                        value: 1
+                    },
+                    metadata: XbiMetadata::new(
+                        SLIM_PARA_ID,
+                        LARGE_PARA_ID,
+                        // Must be sent within a single block
+                        Timeouts::new(
+                            Some(ActionNotificationTimeouts {
+                                action: 6000,
+                                notification: 6000,
+                            }),
+                            None,
+                            None,
+                            None,
+                        ),
+                        Fees::new(Some(ASSET_ID), Some(EXEC_COST), Some(NOTIFICATION_COST)),
+                        None,
+                        Default::default(),
+                        Default::default(),
+                    ),
+                },
+                None
+            ));
+            assert_asset_burned!(slim, ASSET_ID, ALICE, EXEC_COST + NOTIFICATION_COST);
+            slim::System::reset_events();
+        });
+
+        println!(">>> [Slim] Processing queue after the send timeout");
+        Slim::execute_with(|| {
+            slim::System::set_block_number(slim::System::block_number() + 2);
+            assert_ok!(slim::XbiPortal::process_queue(slim::Origin::root()));
+            crate::slim::log_all_events("Slim");
+
+            assert!(!slim::System::events().iter().any(|r| matches!(
+                &r.event,
+                slim::Event::XbiPortal(pallet_xbi_portal::Event::XbiMessageSent { .. })
+            )));
+            assert_response_stored!(slim, Status::SendTimeout);
+            // Nothing was spent, so the whole reserve is refunded
+            assert_asset_issued!(slim, ASSET_ID, ALICE, EXEC_COST + NOTIFICATION_COST);
+            slim::System::reset_events();
+        });
+    }
+
+    #[test]
+    fn senders_can_choose_the_id_of_their_messages() {
+        use pallet_xbi_portal::{Error, MessageIdSource, XbiRequests};
+        use sp_runtime::{testing::H256, traits::BlakeTwo256};
+
+        setup();
+        setup_default_assets();
+
+        let format = || XbiFormat {
+            instr: XbiInstruction::Transfer {
+                dest: CONTRACT_CALLER,
+                value: 1,
+            },
+            metadata: XbiMetadata::new(
+                SLIM_PARA_ID,
+                LARGE_PARA_ID,
+                Default::default(),
+                Fees::new(Some(ASSET_ID), Some(EXEC_COST), Some(NOTIFICATION_COST)),
+                None,
+                Default::default(),
+                Default::default(),
+            ),
+        };
+        let send = |id| {
+            slim::XbiPortal::send(
+                slim::Origin::signed(ALICE),
+                xp_channel::ExecutionType::Async,
+                format(),
+                id,
+            )
+        };
+
+        Slim::execute_with(|| {
+            let mut metadata = format().metadata;
+            metadata.enrich_origin(&ALICE);
+
+            // Ids are derived from the nonce of the sender by default
+            assert_ok!(send(None));
+            assert_eq!(slim::XbiPortal::message_nonce(ALICE), 1);
+            assert!(XbiRequests::<slim::Runtime>::contains_key(
+                metadata.derive_id::<BlakeTwo256>(1, None)
+            ));
+
+            // Or from a seed, without using the nonce
+            assert_ok!(send(Some(MessageIdSource::Seed(b"seed".to_vec()))));
+            assert_eq!(slim::XbiPortal::message_nonce(ALICE), 1);
+            assert!(XbiRequests::<slim::Runtime>::contains_key(
+                metadata.derive_id::<BlakeTwo256>(0, Some(&b"seed"[..]))
+            ));
+
+            // Or chosen explicitly, bound to the sender, ids must be unique
+            let id = H256::repeat_byte(1);
+            assert_ok!(send(Some(MessageIdSource::Explicit(id))));
+            assert!(XbiRequests::<slim::Runtime>::contains_key(
+                slim::XbiPortal::explicit_message_id(&ALICE, id)
+            ));
+            frame_support::assert_err!(
+                send(Some(MessageIdSource::Explicit(id))),
+                Error::<slim::Runtime>::MessageIdInUse
+            );
+            frame_support::assert_err!(
+                send(Some(MessageIdSource::Seed(b"seed".to_vec()))),
+                Error::<slim::Runtime>::MessageIdInUse
+            );
+        });
     }
 
     // TODO:
@@ -0,0 +1,23 @@
+//! Runtime API of the XBI portal, so that off-chain consumers can query what happened to a message without scraping events or storage.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use sp_core::H256;
+use sp_std::prelude::*;
+pub use xp_channel::{
+    queue::QueueSignal, Message, MessageLifecycle, XbiResult, MAX_QUERIED_MESSAGES,
+};
+
+sp_api::decl_runtime_apis! {
+    pub trait XbiPortalApi {
+        /// What the portal knows of the message with the given id, from its request to its result
+        fn message_lifecycle(id: H256) -> Option<MessageLifecycle>;
+
+        /// A page of up to `count` messages in the queue of the portal from the `start`th, with their signals.
+        ///
+        /// No more than `MAX_QUERIED_MESSAGES` are returned.
+        fn pending_queue(start: u32, count: u32) -> Vec<(Message, QueueSignal)>;
+
+        /// The result of each of the messages with the given ids, if it is in, only the first `MAX_QUERIED_MESSAGES` ids are looked up
+        fn results(ids: Vec<H256>) -> Vec<Option<XbiResult>>;
+    }
+}
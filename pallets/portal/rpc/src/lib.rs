@@ -0,0 +1,148 @@
+//! JSON-RPC of the XBI portal, backed by the `XbiPortalApi` runtime API.
+use jsonrpsee::{
+    core::{async_trait, RpcResult},
+    proc_macros::rpc,
+    types::error::{CallError, ErrorObject},
+};
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::HeaderBackend;
+use sp_core::H256;
+use sp_runtime::{generic::BlockId, traits::Block as BlockT};
+use std::sync::Arc;
+
+pub use pallet_xbi_portal_rpc_runtime_api::{
+    Message, MessageLifecycle, QueueSignal, XbiPortalApi as XbiPortalRuntimeApi, XbiResult,
+    MAX_QUERIED_MESSAGES,
+};
+
+#[rpc(server)]
+pub trait XbiPortalApi<BlockHash> {
+    /// The `MessageLifecycle` of the message with the given id, if the portal knows of it
+    #[method(name = "xbi_messageLifecycle")]
+    fn message_lifecycle(
+        &self,
+        id: H256,
+        at: Option<BlockHash>,
+    ) -> RpcResult<Option<MessageLifecycle>>;
+
+    /// A page of up to `count` messages in the queue of the portal from the `start`th, with their signals.
+    ///
+    /// No more than `MAX_QUERIED_MESSAGES` are returned.
+    #[method(name = "xbi_pendingQueue")]
+    fn pending_queue(
+        &self,
+        start: u32,
+        count: u32,
+        at: Option<BlockHash>,
+    ) -> RpcResult<Vec<(Message, QueueSignal)>>;
+
+    /// The `XbiResult` of each of the messages with the given ids, if it is in.
+    ///
+    /// No more than `MAX_QUERIED_MESSAGES` ids can be given.
+    #[method(name = "xbi_results")]
+    fn results(&self, ids: Vec<H256>, at: Option<BlockHash>) -> RpcResult<Vec<Option<XbiResult>>>;
+}
+
+/// Provides the RPC methods to query the messages of the portal
+pub struct XbiPortal<C, Block> {
+    client: Arc<C>,
+    _marker: std::marker::PhantomData<Block>,
+}
+
+impl<C, Block> XbiPortal<C, Block> {
+    pub fn new(client: Arc<C>) -> Self {
+        Self {
+            client,
+            _marker: Default::default(),
+        }
+    }
+}
+
+/// Error codes of the RPC
+pub enum Error {
+    /// The call to the runtime failed
+    RuntimeError,
+    /// More messages were asked for than can be queried at once
+    TooManyMessages,
+}
+
+impl From<Error> for i32 {
+    fn from(e: Error) -> i32 {
+        match e {
+            Error::RuntimeError => 1,
+            Error::TooManyMessages => 2,
+        }
+    }
+}
+
+fn runtime_error(e: impl std::fmt::Debug) -> jsonrpsee::core::Error {
+    CallError::Custom(ErrorObject::owned(
+        Error::RuntimeError.into(),
+        "Unable to query the XBI portal",
+        Some(format!("{:?}", e)),
+    ))
+    .into()
+}
+
+fn too_many_messages(count: usize) -> jsonrpsee::core::Error {
+    CallError::Custom(ErrorObject::owned(
+        Error::TooManyMessages.into(),
+        "Too many messages to query at once",
+        Some(format!(
+            "{} were asked for, at most {} can be",
+            count, MAX_QUERIED_MESSAGES
+        )),
+    ))
+    .into()
+}
+
+#[async_trait]
+impl<C, Block> XbiPortalApiServer<<Block as BlockT>::Hash> for XbiPortal<C, Block>
+where
+    Block: BlockT,
+    C: ProvideRuntimeApi<Block> + HeaderBackend<Block> + Send + Sync + 'static,
+    C::Api: XbiPortalRuntimeApi<Block>,
+{
+    fn message_lifecycle(
+        &self,
+        id: H256,
+        at: Option<<Block as BlockT>::Hash>,
+    ) -> RpcResult<Option<MessageLifecycle>> {
+        let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+        self.client
+            .runtime_api()
+            .message_lifecycle(&at, id)
+            .map_err(runtime_error)
+    }
+
+    fn pending_queue(
+        &self,
+        start: u32,
+        count: u32,
+        at: Option<<Block as BlockT>::Hash>,
+    ) -> RpcResult<Vec<(Message, QueueSignal)>> {
+        if count > MAX_QUERIED_MESSAGES {
+            return Err(too_many_messages(count as usize));
+        }
+        let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+        self.client
+            .runtime_api()
+            .pending_queue(&at, start, count)
+            .map_err(runtime_error)
+    }
+
+    fn results(
+        &self,
+        ids: Vec<H256>,
+        at: Option<<Block as BlockT>::Hash>,
+    ) -> RpcResult<Vec<Option<XbiResult>>> {
+        if ids.len() > MAX_QUERIED_MESSAGES as usize {
+            return Err(too_many_messages(ids.len()));
+        }
+        let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+        self.client
+            .runtime_api()
+            .results(&at, ids)
+            .map_err(runtime_error)
+    }
+}
@@ -0,0 +1,164 @@
+//! Benchmarks for the portal, from which runtimes generate the weights that `weights` has placeholders for.
+//!
+//! Messages are sent to `PEER`, which the runtime must have an XCM route to for `send_sync` to succeed. Messages sent from the queue
+//! may fail to be transported, in which case the comparable cost of failing is measured. Requests carry an instruction that no
+//! handler accepts, so that only the portal is measured.
+use super::*;
+use crate::{
+    impls::{account32_from_account, cast_hash},
+    Pallet as XbiPortal,
+};
+use frame_benchmarking::{benchmarks, whitelisted_caller, BenchmarkError};
+use frame_support::traits::{Currency, EnsureOrigin};
+use frame_system::RawOrigin;
+use xp_channel::{
+    queue::Queue as QueueExt,
+    traits::{ChargeForMessage, Writable},
+    ExecutionType,
+};
+use xp_format::{Fees, XbiInstruction};
+use xs_channel::receiver::frame::invert_destination_from_message;
+
+const PEER: u32 = 2;
+/// An instruction identifier that is not assigned to any instruction, so that no handler accepts it
+const UNASSIGNED_INSTRUCTION: u8 = 200;
+
+fn funded_caller<T: Config>() -> T::AccountId {
+    let caller: T::AccountId = whitelisted_caller();
+    T::Currency::make_free_balance_be(
+        &caller,
+        T::Currency::minimum_balance().saturating_add(u32::MAX.into()),
+    );
+    caller
+}
+
+fn request<T: Config>(caller: &T::AccountId, src_para_id: u32, dest_para_id: u32) -> XbiFormat {
+    XbiFormat {
+        instr: XbiInstruction::Unknown {
+            identifier: UNASSIGNED_INSTRUCTION,
+            params: vec![],
+        },
+        metadata: XbiMetadata::new(
+            src_para_id,
+            dest_para_id,
+            Default::default(),
+            Fees::new(None, Some(1_000), Some(1_000)),
+            account32_from_account::<T>(caller).ok(),
+            0,
+            None,
+        ),
+    }
+}
+
+/// The origin of a peer, and its para id
+fn peer<T: Config>() -> Result<(T::Origin, u32), BenchmarkError> {
+    let origin = T::PeerOrigin::successful_origin();
+    let para_id = T::PeerOrigin::try_origin(origin.clone())
+        .map_err(|_| BenchmarkError::Stop("The origin of a peer cannot be resolved"))?;
+    Ok((origin, para_id))
+}
+
+/// A request sent from here by the caller, with its fees charged and tracked until it is resolved
+fn sent_request<T: Config>(caller: &T::AccountId, dest_para_id: u32) -> XbiFormat {
+    let format = request::<T>(caller, T::ParachainId::get(), dest_para_id);
+    <() as ChargeForMessage<T::AccountId, T::Currency, T::Assets, T::ReserveBalanceCustodian>>::charge(
+        caller,
+        &format.metadata.fees,
+    )
+    .unwrap();
+    XbiRequests::<T>::insert(
+        cast_hash::<T>(&format.metadata.get_id()).unwrap(),
+        format.clone(),
+    );
+    format
+}
+
+fn queue<T: Config>(item: (Message, QueueSignal)) {
+    <Queue<XbiPortal<T>>>::default().push(item).unwrap();
+}
+
+benchmarks! {
+    send_sync {
+        let caller = funded_caller::<T>();
+        let msg = request::<T>(&caller, T::ParachainId::get(), PEER);
+    }: send(RawOrigin::Signed(caller), ExecutionType::Sync, msg, None)
+    verify {
+        assert_eq!(XbiRequests::<T>::iter().count(), 1);
+    }
+
+    send_async {
+        let caller = funded_caller::<T>();
+        let msg = request::<T>(&caller, T::ParachainId::get(), PEER);
+    }: send(RawOrigin::Signed(caller), ExecutionType::Async, msg, None)
+    verify {
+        assert_eq!(<Queue<XbiPortal<T>>>::default().len(), 1);
+    }
+
+    receive_request {
+        let (origin, peer) = peer::<T>()?;
+        let caller: T::AccountId = whitelisted_caller();
+        let format = request::<T>(&caller, peer, T::ParachainId::get());
+        // The request was executed before, and its record expired without being pruned
+        frame_system::Pallet::<T>::set_block_number(1u32.into());
+        ExecutedRequests::<T>::insert(peer, cast_hash::<T>(&format.metadata.get_id()).unwrap(), 0);
+        let msg = VersionedMessage::Request(format.into());
+    }: receive(origin, msg)
+
+    receive_response {
+        let (origin, peer) = peer::<T>()?;
+        let caller = funded_caller::<T>();
+        let mut metadata = sent_request::<T>(&caller, peer).metadata;
+        invert_destination_from_message(&mut metadata);
+        let id = cast_hash::<T>(&metadata.get_id()).unwrap();
+    }: receive(origin, VersionedMessage::Request(XbiFormat::from_result(Default::default(), metadata).into()))
+    verify {
+        assert!(XbiResponses::<T>::contains_key(id));
+    }
+
+    process_queue_request {
+        let caller = funded_caller::<T>();
+        queue::<T>((Message::Request(sent_request::<T>(&caller, PEER)), QueueSignal::PendingRequest));
+    }: process_queue(RawOrigin::Root)
+
+    process_queue_execution {
+        let caller: T::AccountId = whitelisted_caller();
+        let format = request::<T>(&caller, PEER, T::ParachainId::get());
+        queue::<T>((Message::Request(format), QueueSignal::PendingExecution));
+    }: process_queue(RawOrigin::Root)
+
+    process_queue_response {
+        let caller: T::AccountId = whitelisted_caller();
+        let metadata = request::<T>(&caller, T::ParachainId::get(), PEER).metadata;
+        queue::<T>((Message::Response(Default::default(), metadata), QueueSignal::PendingResponse));
+    }: process_queue(RawOrigin::Root)
+
+    process_queue_result {
+        let caller = funded_caller::<T>();
+        let mut metadata = sent_request::<T>(&caller, PEER).metadata;
+        invert_destination_from_message(&mut metadata);
+        let id = cast_hash::<T>(&metadata.get_id()).unwrap();
+        queue::<T>((Message::Response(Default::default(), metadata), QueueSignal::PendingResult));
+    }: process_queue(RawOrigin::Root)
+    verify {
+        assert!(XbiResponses::<T>::contains_key(id));
+    }
+
+    process_queue_protocol_error {
+        let caller = funded_caller::<T>();
+        let format = sent_request::<T>(&caller, PEER);
+        queue::<T>((Message::Request(format), QueueSignal::ProtocolError(Status::DispatchFailed)));
+    }: process_queue(RawOrigin::Root)
+
+    write_result {
+        let caller = funded_caller::<T>();
+        let metadata = sent_request::<T>(&caller, PEER).metadata;
+        let id = cast_hash::<T>(&metadata.get_id()).unwrap();
+    }: {
+        <XbiPortal<T> as Writable<_>>::write((metadata.get_id(), Default::default()))?;
+    }
+    verify {
+        assert!(XbiResponses::<T>::contains_key(id));
+    }
+
+    impl_benchmark_test_suite!(XbiPortal, crate::mock::new_bench_ext(), crate::mock::Test);
+}
@@ -0,0 +1,362 @@
+//! The instruction handlers provided by the portal.
+//!
+//! Each handler accepts a subset of XBI instructions and can be composed into `Config::InstructionHandlers` as a tuple.
+//! Runtimes that want to support every instruction the portal knows about can use `DefaultHandlers`.
+//!
+//! Every handler acts as the account `xs_channel::remote_origin` derives for the XBI origin of the message, never as the origin it is
+//! handled with, so that a peer can only ever act as the accounts derived for it.
+use crate::{
+    impls::account_from_account32,
+    primitives::{custom_vm::CustomVm, defi::DeFi},
+    xbi_abi::AccountId20,
+    Config, Error,
+};
+use codec::{Decode, DecodeLimit, Encode};
+use contracts_primitives::traits::Contracts;
+use evm_primitives::traits::Evm;
+use frame_support::{
+    dispatch::DispatchErrorWithPostInfo,
+    ensure,
+    traits::{fungibles::Transfer, Contains, Currency, ExistenceRequirement},
+    weights::{GetDispatchInfo, PostDispatchInfo, Weight},
+};
+use frame_system::RawOrigin;
+use sp_runtime::{
+    traits::{Dispatchable, UniqueSaturatedInto},
+    DispatchError,
+};
+use sp_std::{marker::PhantomData, prelude::*};
+use xp_channel::traits::{HandlerInfo, XbiInstructionRoute};
+use xp_format::{XbiFormat, XbiInstruction};
+
+/// The maximum depth of nested calls that can be decoded from a `CallNative` payload
+const MAX_NATIVE_CALL_DEPTH: u32 = 256;
+
+type HandlerResult = Result<HandlerInfo<Weight>, DispatchErrorWithPostInfo<PostDispatchInfo>>;
+
+/// The account the instruction of a message is executed as
+fn remote_origin<T: Config>(xbi: &XbiFormat) -> Result<T::AccountId, DispatchError> {
+    xs_channel::remote_origin(&xbi.metadata)
+}
+
+/// The EVM address of an account, its leading 20 bytes as `pallet_evm::EnsureAddressTruncated` expects
+fn evm_address<T: Config>(account: &T::AccountId) -> AccountId20 {
+    let mut address = [0u8; 20];
+    account.using_encoded(|bytes| {
+        let len = bytes.len().min(address.len());
+        address[..len].copy_from_slice(&bytes[..len]);
+    });
+    AccountId20::from(address)
+}
+
+/// All of the handlers provided by the portal
+pub type DefaultHandlers<T> = (
+    NativeCallHandler<T>,
+    EvmHandler<T>,
+    WasmHandler<T>,
+    CustomVmHandler<T>,
+    TransferHandler<T>,
+    TransferAssetsHandler<T>,
+    DeFiHandler<T>,
+);
+
+/// Dispatches `CallNative` payloads that pass `Config::NativeCallFilter` as the account `xs_channel::remote_origin` derives for the XBI origin
+pub struct NativeCallHandler<T>(PhantomData<T>);
+
+impl<T: Config> XbiInstructionRoute<T::Origin> for NativeCallHandler<T> {
+    fn accepts(identifier: u8) -> bool {
+        identifier == 1
+    }
+
+    fn handle(_origin: &T::Origin, xbi: &mut XbiFormat) -> HandlerResult {
+        let payload = match xbi.instr {
+            XbiInstruction::CallNative { ref payload } => payload,
+            _ => return Err(Error::<T>::UnknownInstruction.into()),
+        };
+
+        let call = <T as Config>::Call::decode_all_with_depth_limit(
+            MAX_NATIVE_CALL_DEPTH,
+            &mut &payload[..],
+        )
+        .map_err(|_| Error::<T>::FailedToDecodeCall)?;
+        ensure!(
+            T::NativeCallFilter::contains(&call),
+            Error::<T>::CallFiltered
+        );
+
+        let weight = call.get_dispatch_info().weight;
+
+        call.dispatch(RawOrigin::Signed(remote_origin::<T>(xbi)?).into())
+            .map(|info| HandlerInfo {
+                output: vec![],
+                weight: info.actual_weight.unwrap_or(weight),
+            })
+            .map_err(|e| DispatchErrorWithPostInfo {
+                post_info: PostDispatchInfo {
+                    actual_weight: Some(e.post_info.actual_weight.unwrap_or(weight)),
+                    pays_fee: e.post_info.pays_fee,
+                },
+                error: e.error,
+            })
+    }
+}
+
+/// Routes `CallEvm` instructions to `Config::Evm`, calling from the address of the remote origin whatever `source` the instruction names
+pub struct EvmHandler<T>(PhantomData<T>);
+
+impl<T: Config> XbiInstructionRoute<T::Origin> for EvmHandler<T> {
+    fn accepts(identifier: u8) -> bool {
+        identifier == 2
+    }
+
+    fn handle(_origin: &T::Origin, xbi: &mut XbiFormat) -> HandlerResult {
+        let caller = remote_origin::<T>(xbi)?;
+
+        match xbi.instr {
+            XbiInstruction::CallEvm {
+                source: _,
+                target,
+                value,
+                ref input,
+                gas_limit,
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+                nonce,
+                ref access_list,
+            } => {
+                let evm_result = T::Evm::call(
+                    RawOrigin::Signed(caller.clone()).into(),
+                    evm_address::<T>(&caller),
+                    target,
+                    input.clone(),
+                    value,
+                    gas_limit,
+                    max_fee_per_gas,
+                    max_priority_fee_per_gas,
+                    nonce,
+                    access_list.clone(),
+                );
+                let weight = evm_result.clone().map(|(_, weight)| weight);
+
+                evm_result
+                    .map(|(x, weight)| HandlerInfo {
+                        output: x.value,
+                        weight,
+                    })
+                    .map_err(|e| DispatchErrorWithPostInfo {
+                        post_info: PostDispatchInfo {
+                            actual_weight: weight.ok(),
+                            pays_fee: Default::default(),
+                        },
+                        error: e,
+                    })
+            }
+            _ => Err(Error::<T>::UnknownInstruction.into()),
+        }
+    }
+}
+
+/// Routes `CallWasm` instructions to `Config::Contracts`
+pub struct WasmHandler<T>(PhantomData<T>);
+
+impl<T: Config> XbiInstructionRoute<T::Origin> for WasmHandler<T> {
+    fn accepts(identifier: u8) -> bool {
+        identifier == 3
+    }
+
+    fn handle(_origin: &T::Origin, xbi: &mut XbiFormat) -> HandlerResult {
+        let caller = remote_origin::<T>(xbi)?;
+
+        match xbi.instr {
+            XbiInstruction::CallWasm {
+                ref dest,
+                value,
+                gas_limit,
+                storage_deposit_limit,
+                ref data,
+            } => {
+                let contract_result = T::Contracts::call(
+                    caller,
+                    account_from_account32::<T>(dest)?,
+                    value.unique_saturated_into(),
+                    gas_limit,
+                    storage_deposit_limit.map(UniqueSaturatedInto::unique_saturated_into),
+                    data.clone(),
+                    false, // ALWAYS FALSE, could panic the runtime unless over rpc
+                );
+                contract_result
+                    .result
+                    .map(|r| HandlerInfo {
+                        output: r.data.0,
+                        weight: contract_result.gas_consumed,
+                    })
+                    .map_err(|e| DispatchErrorWithPostInfo {
+                        post_info: PostDispatchInfo {
+                            actual_weight: Some(contract_result.gas_consumed),
+                            pays_fee: Default::default(),
+                        },
+                        error: e,
+                    })
+            }
+            _ => Err(Error::<T>::UnknownInstruction.into()),
+        }
+    }
+}
+
+/// Routes `CallCustom` instructions to `Config::CustomVm`
+pub struct CustomVmHandler<T>(PhantomData<T>);
+
+impl<T: Config> XbiInstructionRoute<T::Origin> for CustomVmHandler<T> {
+    fn accepts(identifier: u8) -> bool {
+        identifier == 4
+    }
+
+    fn handle(_origin: &T::Origin, xbi: &mut XbiFormat) -> HandlerResult {
+        let origin: T::Origin = RawOrigin::Signed(remote_origin::<T>(xbi)?).into();
+
+        match xbi.instr {
+            XbiInstruction::CallCustom {
+                ref caller,
+                ref dest,
+                value,
+                ref input,
+                limit,
+                ref additional_params,
+            } => T::CustomVm::call(
+                origin,
+                caller.clone(),
+                dest.clone(),
+                value.unique_saturated_into(),
+                input.clone(),
+                limit,
+                additional_params.clone(),
+            ),
+            _ => Err(Error::<T>::UnknownInstruction.into()),
+        }
+    }
+}
+
+/// Transfers `Config::Currency` from the remote origin for `Transfer` instructions
+pub struct TransferHandler<T>(PhantomData<T>);
+
+impl<T: Config> XbiInstructionRoute<T::Origin> for TransferHandler<T> {
+    fn accepts(identifier: u8) -> bool {
+        identifier == 5
+    }
+
+    fn handle(_origin: &T::Origin, xbi: &mut XbiFormat) -> HandlerResult {
+        let caller = remote_origin::<T>(xbi)?;
+
+        match xbi.instr {
+            XbiInstruction::Transfer { ref dest, value } => T::Currency::transfer(
+                &caller,
+                &account_from_account32::<T>(dest)?,
+                value.unique_saturated_into(),
+                ExistenceRequirement::AllowDeath,
+            )
+            .map(|_| Default::default())
+            .map_err(|_| Error::<T>::TransferFailed.into()),
+            _ => Err(Error::<T>::UnknownInstruction.into()),
+        }
+    }
+}
+
+/// Transfers `Config::Assets` from the remote origin for `TransferAssets` instructions
+pub struct TransferAssetsHandler<T>(PhantomData<T>);
+
+impl<T: Config> XbiInstructionRoute<T::Origin> for TransferAssetsHandler<T> {
+    fn accepts(identifier: u8) -> bool {
+        identifier == 6
+    }
+
+    fn handle(_origin: &T::Origin, xbi: &mut XbiFormat) -> HandlerResult {
+        let caller = remote_origin::<T>(xbi)?;
+
+        match xbi.instr {
+            XbiInstruction::TransferAssets {
+                currency_id,
+                ref dest,
+                value,
+            } => {
+                let keep_alive = true;
+
+                let currency_id = <T::Assets as frame_support::traits::fungibles::Inspect<
+                    T::AccountId,
+                >>::AssetId::decode(
+                    &mut &currency_id.encode()[..]
+                )
+                .map_err(|_| Error::<T>::FailedToCastValue)?;
+
+                // TODO: have an assertion that the destination actually was updated
+                T::Assets::transfer(
+                    currency_id,
+                    &caller,
+                    &account_from_account32::<T>(dest)?,
+                    value.unique_saturated_into(),
+                    keep_alive,
+                )
+                .map(|_| Default::default())
+                .map_err(|_| Error::<T>::TransferFailed.into())
+            }
+            _ => Err(Error::<T>::UnknownInstruction.into()),
+        }
+    }
+}
+
+/// Routes `Swap`, `AddLiquidity`, `RemoveLiquidity` and `GetPrice` instructions to `Config::DeFi`
+pub struct DeFiHandler<T>(PhantomData<T>);
+
+impl<T: Config> XbiInstructionRoute<T::Origin> for DeFiHandler<T> {
+    fn accepts(identifier: u8) -> bool {
+        (7..=10).contains(&identifier)
+    }
+
+    fn handle(_origin: &T::Origin, xbi: &mut XbiFormat) -> HandlerResult {
+        let origin: T::Origin = RawOrigin::Signed(remote_origin::<T>(xbi)?).into();
+
+        match xbi.instr {
+            XbiInstruction::Swap {
+                asset_out,
+                asset_in,
+                amount,
+                max_limit,
+                discount,
+            } => T::DeFi::swap(
+                origin,
+                asset_out,
+                asset_in,
+                amount.unique_saturated_into(),
+                max_limit.unique_saturated_into(),
+                discount,
+            ),
+            XbiInstruction::AddLiquidity {
+                asset_a,
+                asset_b,
+                amount_a,
+                amount_b_max_limit,
+            } => T::DeFi::add_liquidity(
+                origin,
+                asset_a,
+                asset_b,
+                amount_a.unique_saturated_into(),
+                amount_b_max_limit.unique_saturated_into(),
+            ),
+            XbiInstruction::RemoveLiquidity {
+                asset_a,
+                asset_b,
+                liquidity_amount,
+            } => T::DeFi::remove_liquidity(
+                origin,
+                asset_a,
+                asset_b,
+                liquidity_amount.unique_saturated_into(),
+            ),
+            XbiInstruction::GetPrice {
+                asset_a,
+                asset_b,
+                amount,
+            } => T::DeFi::get_price(origin, asset_a, asset_b, amount.unique_saturated_into()),
+            _ => Err(Error::<T>::UnknownInstruction.into()),
+        }
+    }
+}
@@ -1,24 +1,59 @@
-use crate::{Config, Error, Event, Pallet, XbiResponses};
+use crate::{
+    pallet::{AsyncSender, ExecutedCursor, MessageNonces, RetryCursor, Sender, TimeoutCursor},
+    primitives::xbi_callback::XBICallback,
+    weights::WeightInfo,
+    xbi_abi::{AssetId, Value},
+    BalanceOf, Config, DeadLetters, Error, Event, ExecutedRequests, MessageIdSource, Pallet,
+    PeerPortals, PeerVersions, PortalIndex, PromisedRequests, Promises, Queue, RelayPeers,
+    RetryAttempts, ScheduledRetries, VersionedMessage, XbiRequests, XbiResponses,
+};
 use codec::{Decode, Encode};
-use contracts_primitives::traits::Contracts;
-use evm_primitives::traits::Evm;
 use frame_support::{
-    traits::{fungibles::Transfer, Currency, ExistenceRequirement},
-    weights::{PostDispatchInfo, WeightToFee},
+    dispatch::DispatchResultWithPostInfo,
+    ensure,
+    storage::{with_transaction, TransactionOutcome},
+    traits::{
+        fungibles::{Inspect, Transfer},
+        Currency, ExistenceRequirement, PalletInfoAccess,
+    },
+    weights::{DispatchClass, GetDispatchInfo, PostDispatchInfo, Weight, WeightToFee},
 };
-use frame_system::ensure_signed;
+use frame_system::{ensure_signed, RawOrigin};
 use sp_core::H256;
 use sp_runtime::traits::Get;
 use sp_runtime::{
-    traits::UniqueSaturatedInto, AccountId32, DispatchError, DispatchErrorWithPostInfo, Either,
+    traits::{BlakeTwo256, Dispatchable, Hash, Saturating, UniqueSaturatedInto},
+    AccountId32, DispatchError, DispatchErrorWithPostInfo, DispatchResult, Either,
 };
-use sp_std::{default::Default, prelude::*};
+use sp_std::{default::Default, marker::PhantomData, prelude::*};
 use xp_channel::{
-    traits::{HandlerInfo, Writable, XbiInstructionHandler},
-    ChannelProgressionEmitter, Message,
+    queue::{Queue as QueueExt, QueueSignal},
+    traits::{HandlerInfo, RefundForMessage, Writable, XbiInstructionHandler, XbiInstructionRoute},
+    ChannelProgressionEmitter, ExecutionType, Message, MessageLifecycle, XcmError,
+    MAX_QUERIED_MESSAGES,
+};
+use xp_format::{
+    Fees, Status, Timestamp, XbiFormat, XbiInstruction, XbiMetadata, XbiResult, XbiVersion,
+    XBI_VERSION,
+};
+use xp_xcm::{
+    frame_traits::{AssetLookup, XcmConvert},
+    xcm::prelude::MultiLocation,
+    MultiLocationBuilder,
+};
+use xs_channel::sender::{
+    frame::{Continuation, ReceiveCallProvider},
+    CallPromise, PromiseDelegate,
 };
-use xp_format::{XbiFormat, XbiInstruction, XbiMetadata, XbiResult};
-use xs_channel::sender::frame::ReceiveCallProvider;
+
+/// The measured weight of handling a response on the source, whether it is received synchronously or through the queue
+pub struct MeasuredNotificationWeight<T>(PhantomData<T>);
+
+impl<T: Config> Get<Weight> for MeasuredNotificationWeight<T> {
+    fn get() -> Weight {
+        T::WeightInfo::receive_response().saturating_add(T::WeightInfo::process_queue_result())
+    }
+}
 
 // TODO: move to sabi
 pub fn account_from_account32<T: Config>(
@@ -28,17 +63,761 @@ pub fn account_from_account32<T: Config>(
 }
 
 // TODO: move to sabi
+/// The XBI origin of an account, accounts shorter than 32 bytes are padded with zeroes so that they decode back to themselves.
 pub fn account32_from_account<T: Config>(
     account: &T::AccountId,
 ) -> Result<AccountId32, DispatchError> {
     let account_bytes = account.encode();
+    ensure!(account_bytes.len() <= 32, Error::<T>::FailedToCastAddress);
+
+    let mut bytes = [0u8; 32];
+    bytes[..account_bytes.len()].copy_from_slice(&account_bytes);
+    Ok(AccountId32::new(bytes))
+}
 
-    Ok(AccountId32::new(
-        account_bytes
-            .get(0..32)
-            .and_then(|x| x.try_into().ok())
-            .ok_or(Error::<T>::FailedToCastAddress)?,
-    ))
+pub fn cast_hash<T: Config>(hash: &H256) -> Result<T::Hash, Error<T>> {
+    Decode::decode(&mut &hash.encode()[..]).map_err(|_| Error::<T>::FailedToCastHash)
+}
+
+impl<T: Config> Pallet<T> {
+    /// The id of a request that `who` sends with an explicit id. The explicit id is hashed with its sender, so that a sender
+    /// cannot take the ids that other senders will derive or choose.
+    pub fn explicit_message_id(who: &T::AccountId, id: H256) -> H256 {
+        BlakeTwo256::hash_of(&(who, id))
+    }
+
+    /// Prepare a request sent by `who`, assigning its id and tracking it so that it can be timed out if no response is received.
+    ///
+    /// The id is derived from the next nonce of `who`, unless it is given by `id`. Ids must be unique, a request with the id of
+    /// a request that is in-flight or resolved is rejected.
+    pub(crate) fn prepare_request(
+        who: &T::AccountId,
+        kind: &ExecutionType,
+        mut msg: XbiFormat,
+        id: Option<MessageIdSource>,
+    ) -> Result<Message, DispatchError> {
+        // Async requests are rejected rather than overwriting messages in the queue
+        if *kind == ExecutionType::Async {
+            ensure!(
+                !<Queue<Pallet<T>>>::default().is_full(),
+                Error::<T>::QueueFull
+            );
+        }
+
+        ensure!(msg.metadata.has_valid_route(), Error::<T>::InvalidRoute);
+        msg.metadata
+            .enrich_origin(&account32_from_account::<T>(who)?);
+
+        let id = match id {
+            None => {
+                // Get and increment the nonce
+                let nonce = Self::message_nonce(who).wrapping_add(1);
+                <MessageNonces<T>>::insert(who, nonce);
+                msg.metadata.derive_id::<BlakeTwo256>(nonce, None)
+            }
+            Some(MessageIdSource::Seed(seed)) => {
+                msg.metadata.derive_id::<BlakeTwo256>(0, Some(&seed))
+            }
+            Some(MessageIdSource::Explicit(id)) => Self::explicit_message_id(who, id),
+        };
+        let hash = cast_hash::<T>(&id)?;
+        ensure!(
+            !XbiRequests::<T>::contains_key(hash) && !XbiResponses::<T>::contains_key(hash),
+            Error::<T>::MessageIdInUse
+        );
+        msg.metadata.set_id(id);
+
+        let current_block: u32 = <frame_system::Pallet<T>>::block_number().unique_saturated_into();
+        msg.metadata.progress(Timestamp::Submitted(current_block));
+        if *kind == ExecutionType::Sync {
+            msg.metadata.progress(Timestamp::Sent(current_block));
+        }
+        XbiRequests::<T>::insert(hash, msg.clone());
+
+        Ok(Message::Request(msg))
+    }
+
+    /// Send a request on behalf of `who`, dispatching the call of the promise with its result once it is in.
+    ///
+    /// The result must be the last argument of the call, see `Continuation`.
+    pub fn send_then(
+        who: &T::AccountId,
+        kind: ExecutionType,
+        msg: XbiFormat,
+        promise: CallPromise<XbiResult, <T as Config>::Call>,
+    ) -> DispatchResultWithPostInfo {
+        let msg = Self::prepare_request(who, &kind, msg, None)?;
+        match kind {
+            ExecutionType::Sync => <Sender<T> as PromiseDelegate<_, _>>::then(msg, promise),
+            ExecutionType::Async => <AsyncSender<T> as PromiseDelegate<_, _>>::then(msg, promise),
+        }
+    }
+
+    /// Send a batch of requests on behalf of `who`, dispatching the call of the promise with all of their results once they are in.
+    ///
+    /// The results are given in the order of the requests, and must be the last argument of the call, see `Continuation`.
+    /// The requests are sent in a storage transaction, none of them are sent if any of them fail.
+    pub fn send_join(
+        who: &T::AccountId,
+        kind: ExecutionType,
+        msgs: Vec<XbiFormat>,
+        promise: CallPromise<Vec<XbiResult>, <T as Config>::Call>,
+    ) -> DispatchResultWithPostInfo {
+        with_transaction(|| {
+            let result = msgs
+                .into_iter()
+                .map(|msg| Self::prepare_request(who, &kind, msg, None))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(DispatchErrorWithPostInfo::from)
+                .and_then(|msgs| match kind {
+                    ExecutionType::Sync => {
+                        <Sender<T> as PromiseDelegate<_, _>>::join(msgs, promise)
+                    }
+                    ExecutionType::Async => {
+                        <AsyncSender<T> as PromiseDelegate<_, _>>::join(msgs, promise)
+                    }
+                });
+
+            match result {
+                Ok(_) => TransactionOutcome::Commit(result),
+                Err(ref err) => {
+                    log::debug!(target: "xbi", "Joined requests failed, rolling back: {:?}", err);
+                    TransactionOutcome::Rollback(result)
+                }
+            }
+        })
+    }
+
+    /// Dispatch the continuation of the promise the request belongs to, if the results of all of its requests are in
+    fn resolve_promise(hash: T::Hash) {
+        let promise = match PromisedRequests::<T>::take(hash) {
+            Some(promise) => promise,
+            None => return,
+        };
+        let continuation = match Promises::<T>::get(promise) {
+            Some(continuation) => continuation,
+            None => return,
+        };
+
+        // A joined promise waits for the results of every request
+        let results: Option<Vec<XbiResult>> = continuation
+            .ids
+            .iter()
+            .map(|id| cast_hash::<T>(id).ok().and_then(XbiResponses::<T>::get))
+            .collect();
+        let results = match results {
+            Some(results) => results,
+            None => return,
+        };
+        Promises::<T>::remove(promise);
+
+        let mut weight = 0;
+        let result = continuation
+            .resolve::<<T as Config>::Call>(results)
+            .and_then(|call| {
+                let origin = continuation
+                    .origin
+                    .as_ref()
+                    .ok_or(DispatchError::Other("A promise has no origin"))?;
+                let who = account_from_account32::<T>(origin).map_err(|e| e.error)?;
+                // The continuation is dispatched within the weight a response may use
+                let declared = call.get_dispatch_info().weight;
+                ensure!(
+                    declared <= T::ResponseWeightLimit::get(),
+                    DispatchError::Exhausted
+                );
+                weight = declared;
+
+                match call.dispatch(RawOrigin::Signed(who).into()) {
+                    Ok(info) => {
+                        weight = info.actual_weight.unwrap_or(weight);
+                        Ok(())
+                    }
+                    Err(e) => {
+                        weight = e.post_info.actual_weight.unwrap_or(weight);
+                        Err(e.error)
+                    }
+                }
+            });
+        if let Err(e) = result {
+            log::warn!(target: "xbi", "Failed to resolve promise {:?}: {:?}", promise, e);
+        }
+
+        <frame_system::Pallet<T>>::register_extra_weight_unchecked(
+            weight.min(T::ResponseWeightLimit::get()),
+            DispatchClass::Mandatory,
+        );
+        Self::deposit_event(Event::XbiPromiseResolved {
+            hash: promise,
+            result,
+        });
+    }
+
+    /// The weight reserved to execute a queued request, the execution limit of the fees is the most the handler may use
+    pub(crate) fn execution_weight(fees: &Fees) -> Weight {
+        fees.execution_cost_limit.unique_saturated_into()
+    }
+
+    /// Push a message that follows on from one popped from the queue, popping it freed the slot that this is pushed to
+    pub(crate) fn push_follow_up(queue: &mut Queue<Pallet<T>>, item: (Message, QueueSignal)) {
+        let metadata = item.0.get_metadata().clone();
+        if let Err(e) = queue.push(item) {
+            log::error!(target: "xbi", "Failed to push follow up message to the queue: {:?}", e);
+            Self::emit_queue_overflow(&metadata);
+        }
+    }
+
+    /// The weight of processing a message from the queue, excluding the execution of any instruction
+    pub(crate) fn queued_message_weight(signal: &QueueSignal) -> Weight {
+        match signal {
+            QueueSignal::PendingRequest => T::WeightInfo::process_queue_request(),
+            QueueSignal::PendingExecution => T::WeightInfo::process_queue_execution(),
+            QueueSignal::PendingResponse => T::WeightInfo::process_queue_response(),
+            QueueSignal::PendingResult => T::WeightInfo::process_queue_result(),
+            QueueSignal::ProtocolError(_) => T::WeightInfo::process_queue_protocol_error(),
+        }
+    }
+
+    /// The XBI version supported by the peer
+    pub fn peer_version(para_id: u32) -> XbiVersion {
+        PeerVersions::<T>::get(para_id).unwrap_or(XBI_VERSION)
+    }
+
+    /// Where the portal is on the peer
+    pub fn peer_portal(para_id: u32) -> PortalIndex {
+        PeerPortals::<T>::get(para_id).unwrap_or_else(T::DefaultPortalIndex::get)
+    }
+
+    /// Where peers deposit whatever is left of the fees of our messages once they are executed, our sovereign account on the peer
+    pub fn surplus_beneficiary() -> MultiLocation {
+        MultiLocationBuilder::new_parachain(T::ParachainId::get())
+            .with_parents(1)
+            .build()
+    }
+
+    /// The location of the asset the fees of a message are paid in, the native asset unless the fees name one
+    pub(crate) fn payment_asset(fees: &Fees) -> Result<MultiLocation, DispatchError> {
+        match fees.asset {
+            Some(id) => {
+                let id: <T::Assets as Inspect<T::AccountId>>::AssetId =
+                    Decode::decode(&mut &id.encode()[..])
+                        .map_err(|_| DispatchError::CannotLookup)?;
+                T::AssetRegistry::reverse_ref(id).map_err(|_| DispatchError::CannotLookup)
+            }
+            None => Ok(MultiLocationBuilder::new_native().build()),
+        }
+    }
+
+    /// Where this portal is in the runtime, as peers should record it
+    pub fn own_portal_index() -> PortalIndex {
+        let receive = crate::pallet::Call::<T>::receive {
+            msg: VersionedMessage::Response(Default::default(), Default::default()),
+        };
+        PortalIndex {
+            pallet: <Self as PalletInfoAccess>::index().unique_saturated_into(),
+            // The call index is the first byte of any of its encoded calls
+            call: receive.encode()[0],
+        }
+    }
+
+    /// Peers without a record are assumed to be on the latest version, so an older request from one of them
+    /// means the peer has not upgraded.
+    pub(crate) fn note_peer_version(para_id: u32, version: XbiVersion) {
+        if version < XBI_VERSION && !PeerVersions::<T>::contains_key(para_id) {
+            PeerVersions::<T>::insert(para_id, version);
+            Self::deposit_event(Event::PeerVersionUpdated {
+                para_id,
+                version: Some(version),
+            });
+        }
+    }
+
+    /// Queue a message on its way to another parachain to be forwarded to the next hop of its route, recording the hop in its timesheet.
+    ///
+    /// Messages are only forwarded between `RelayPeers`, and the `ForwardingFee` is charged to the fees of the message.
+    pub(crate) fn forward(mut msg: Message) -> DispatchResultWithPostInfo {
+        let here = T::ParachainId::get();
+        let (metadata, signal) = match &mut msg {
+            Message::Request(format) => (&mut format.metadata, QueueSignal::PendingRequest),
+            Message::Response(_, metadata) => (metadata, QueueSignal::PendingResponse),
+        };
+        ensure!(metadata.has_valid_route(), Error::<T>::InvalidRoute);
+
+        let previous_hop = metadata.previous_hop(here);
+        let next_hop = metadata.next_hop(here);
+        ensure!(
+            RelayPeers::<T>::contains_key(previous_hop) && RelayPeers::<T>::contains_key(next_hop),
+            Error::<T>::NotRelayPeer
+        );
+
+        let fee = T::ForwardingFee::get();
+        metadata.fees.push_aggregate(fee);
+        ensure!(
+            !metadata.fees.limit_exceeded(),
+            Error::<T>::ForwardingFeeNotCovered
+        );
+        Self::charge_forwarding_fee(previous_hop, metadata.fees.asset, fee)?;
+
+        let current_block: u32 = <frame_system::Pallet<T>>::block_number().unique_saturated_into();
+        metadata.progress(Timestamp::Forwarded(here, current_block));
+        let hash = cast_hash::<T>(&metadata.get_id())?;
+
+        log::debug!(target: "xbi", "Forwarding message {:?} to {:?}", hash, next_hop);
+        <Queue<Pallet<T>>>::default()
+            .push((msg, signal))
+            .map_err(|_| Error::<T>::QueueFull)?;
+
+        Self::deposit_event(Event::XbiForwarded { hash, next_hop });
+        Ok(().into())
+    }
+
+    /// The sovereign account of a peer parachain here
+    pub(crate) fn peer_account(para_id: u32) -> Result<T::AccountId, DispatchError> {
+        T::LocationToAccountId::convert(
+            MultiLocationBuilder::new_parachain(para_id)
+                .with_parents(1)
+                .build(),
+        )
+        .map_err(|_| DispatchError::CannotLookup)
+    }
+
+    /// Take the forwarding fee of a message from the sovereign account of the peer it was received from, the previous hop withdraws
+    /// the fees of the message from that account to pay for it here and deposits whatever is left of them back into it.
+    pub(crate) fn charge_forwarding_fee(
+        previous_hop: u32,
+        asset: Option<AssetId>,
+        fee: Value,
+    ) -> DispatchResult {
+        if fee == 0 {
+            return Ok(());
+        }
+
+        let payer = Self::peer_account(previous_hop)?;
+        let beneficiary = T::XcmSovereignOrigin::get();
+
+        match asset {
+            Some(id) => {
+                let id: <T::Assets as Inspect<T::AccountId>>::AssetId =
+                    Decode::decode(&mut &id.encode()[..])
+                        .map_err(|_| DispatchError::CannotLookup)?;
+                let fee: <T::Assets as Inspect<T::AccountId>>::Balance =
+                    Decode::decode(&mut &fee.encode()[..])
+                        .map_err(|_| Error::<T>::FailedToCastValue)?;
+                T::Assets::transfer(id, &payer, &beneficiary, fee, false)?;
+            }
+            None => {
+                let fee: BalanceOf<T> = Decode::decode(&mut &fee.encode()[..])
+                    .map_err(|_| Error::<T>::FailedToCastValue)?;
+                T::Currency::transfer(&payer, &beneficiary, fee, ExistenceRequirement::AllowDeath)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Progress the timesheet of a tracked request, if it is still in-flight
+    pub(crate) fn progress_request(id: &H256, timestamp: Timestamp<u32>) {
+        if let Ok(hash) = cast_hash::<T>(id) {
+            XbiRequests::<T>::mutate(hash, |request| {
+                if let Some(request) = request {
+                    request.metadata.progress(timestamp);
+                }
+            });
+        }
+    }
+
+    /// The request sent from here that a response is for, if it is still in-flight and the response came from its destination
+    /// along the reverse of its route
+    pub(crate) fn matched_request(metadata: &XbiMetadata) -> Result<XbiFormat, DispatchError> {
+        let hash = cast_hash::<T>(&metadata.get_id())?;
+        ensure!(
+            !XbiResponses::<T>::contains_key(hash),
+            Error::<T>::DuplicateResponse
+        );
+        XbiRequests::<T>::get(hash)
+            .filter(|request| {
+                request.metadata.src_para_id == metadata.dest_para_id
+                    && request.metadata.dest_para_id == metadata.src_para_id
+                    && request.metadata.get_origin() == metadata.get_origin()
+                    && request
+                        .metadata
+                        .route
+                        .iter()
+                        .rev()
+                        .eq(metadata.route.iter())
+            })
+            .ok_or_else(|| Error::<T>::UnmatchedResponse.into())
+    }
+
+    /// The fees charged for a request, with the cost the destination aggregated in its response.
+    ///
+    /// Only the cost is taken from the response, so that a peer cannot claim a larger limit to be refunded.
+    pub(crate) fn refundable_fees(request: &XbiMetadata, response: &XbiMetadata) -> Fees {
+        let mut fees = request.fees.clone();
+        fees.push_aggregate(response.fees.get_aggregated_cost());
+        fees
+    }
+
+    /// Correlate a received response with the request sent from here, marking the request as received so that
+    /// it is only responded to once. The fees of the response are replaced with those of the request.
+    pub(crate) fn correlate_response(metadata: &mut XbiMetadata) -> DispatchResult {
+        let request = Self::matched_request(metadata)?;
+        ensure!(
+            request.metadata.get_timesheet().received.is_none(),
+            Error::<T>::DuplicateResponse
+        );
+
+        Self::progress_request(
+            &metadata.get_id(),
+            Timestamp::Received(<frame_system::Pallet<T>>::block_number().unique_saturated_into()),
+        );
+        metadata.fees = Self::refundable_fees(&request.metadata, metadata);
+        Ok(())
+    }
+
+    /// Record a request as executed, rejecting it if it already was. Records are kept for the lifetime of the request given by
+    /// its timeouts, after which its source no longer accepts a response to it, but never for longer than `MaxExecutedRetention`.
+    ///
+    /// An expired record no longer rejects the request, whether or not it was pruned yet.
+    pub(crate) fn note_executed(metadata: &XbiMetadata) -> DispatchResult {
+        let hash = cast_hash::<T>(&metadata.get_id())?;
+        let now: u32 = <frame_system::Pallet<T>>::block_number().unique_saturated_into();
+        ensure!(
+            ExecutedRequests::<T>::get(metadata.src_para_id, hash)
+                .map_or(true, |expiry| expiry < now),
+            Error::<T>::DuplicateRequest
+        );
+
+        let lifetime = metadata
+            .timeouts
+            .lifetime_in_blocks(T::ExpectedBlockTimeMs::get())
+            .min(T::MaxExecutedRetention::get());
+        ExecutedRequests::<T>::insert(metadata.src_para_id, hash, now.saturating_add(lifetime));
+        Ok(())
+    }
+
+    /// Forget the executed requests that expired, checking at most `ExecutedPruneLimit` records.
+    ///
+    /// Each check continues after the last record the previous one checked, and starts over once every record was checked.
+    pub(crate) fn prune_executed_requests(block: u32) -> Weight {
+        let limit = T::ExecutedPruneLimit::get() as usize;
+        let records: Vec<(u32, T::Hash, u32)> = match ExecutedCursor::<T>::take() {
+            Some(cursor) => ExecutedRequests::<T>::iter_from(cursor),
+            None => ExecutedRequests::<T>::iter(),
+        }
+        .take(limit)
+        .collect();
+        let mut weight = T::DbWeight::get().reads_writes(records.len() as u64 + 1, 1);
+
+        if let Some((src_para_id, hash, _)) = records.last().filter(|_| records.len() == limit) {
+            ExecutedCursor::<T>::put(ExecutedRequests::<T>::hashed_key_for(src_para_id, hash));
+        }
+        for (src_para_id, hash, expiry) in records {
+            if expiry < block {
+                ExecutedRequests::<T>::remove(src_para_id, hash);
+                weight = weight.saturating_add(T::DbWeight::get().writes(1));
+            }
+        }
+        weight
+    }
+
+    /// Resolve a request with a timeout status, refunding the reserved fees of the stages it never reached to the origin.
+    /// A request that was sent may have been executed by its destination, so only the notification limit is refunded for it,
+    /// while a request that was never sent is refunded in full.
+    /// The request is resolved even if the refund fails, which is reported with `XbiRefundFailed`.
+    ///
+    /// Does nothing if the request was already resolved.
+    pub(crate) fn resolve_timeout(metadata: &XbiMetadata, status: Status) -> DispatchResult {
+        let hash = cast_hash::<T>(&metadata.get_id())?;
+        if XbiResponses::<T>::contains_key(hash) {
+            XbiRequests::<T>::remove(hash);
+            return Ok(());
+        }
+
+        let mut fees = metadata.fees.clone();
+        if metadata.get_timesheet().sent.is_some() {
+            fees.push_aggregate(fees.execution_cost_limit);
+        }
+
+        let origin: T::AccountId = xs_channel::xbi_origin(metadata)?;
+        if let Err(e) = <() as RefundForMessage<
+            T::AccountId,
+            T::Currency,
+            T::Assets,
+            T::ReserveBalanceCustodian,
+        >>::refund(&origin, &fees)
+        {
+            log::error!(target: "xbi", "Failed to refund fees for timed out request: {:?}", e);
+            Self::emit_refund_failed(metadata, &e);
+        }
+
+        Self::emit_timed_out(metadata, &status);
+        Self::write((
+            metadata.get_id(),
+            XbiResult {
+                status,
+                ..Default::default()
+            },
+        ))
+    }
+
+    /// Schedule a message that failed to be transported to be retried after a backoff, or move it to the dead letters
+    /// once it has been retried `MaxRetries` times.
+    ///
+    /// A dead-lettered request is resolved with the failed status so that the requester is not left waiting for it.
+    pub(crate) fn retry_or_dead_letter(msg: Message, status: Status) -> DispatchResult {
+        let id = msg.get_metadata().get_id();
+        let hash = cast_hash::<T>(&id)?;
+        let attempt = RetryAttempts::<T>::get(hash).saturating_add(1);
+
+        if attempt <= T::MaxRetries::get() {
+            let backoff = T::RetryBackoff::get()
+                .saturating_mul(2_u32.saturating_pow((attempt - 1).into()).into());
+            let at = <frame_system::Pallet<T>>::block_number().saturating_add(backoff);
+            log::debug!(target: "xbi", "Retrying message {:?} at block {:?}, attempt {:?}", id, at, attempt);
+
+            RetryAttempts::<T>::insert(hash, attempt);
+            ScheduledRetries::<T>::insert(hash, (at, msg));
+            Self::deposit_event(Event::XbiRetryScheduled { hash, attempt, at });
+            return Ok(());
+        }
+
+        log::warn!(target: "xbi", "Message {:?} exhausted its retries, moving it to the dead letters", id);
+        RetryAttempts::<T>::remove(hash);
+        DeadLetters::<T>::insert(hash, (msg.clone(), status.clone()));
+        Self::deposit_event(Event::XbiDeadLettered {
+            hash,
+            status: status.clone(),
+        });
+
+        // Requests forwarded along their route are resolved by their source
+        let forwarded = msg.get_metadata().route.contains(&T::ParachainId::get());
+        if let (Message::Request(_), false) = (&msg, forwarded) {
+            if let Err(e) = Self::write((
+                id,
+                XbiResult {
+                    status,
+                    ..Default::default()
+                },
+            )) {
+                log::warn!(target: "xbi", "Dead-lettered request {:?} was already resolved: {:?}", id, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget the failed attempts of a message once it was transported
+    pub(crate) fn clear_retries(id: &H256) {
+        if let Ok(hash) = cast_hash::<T>(id) {
+            RetryAttempts::<T>::remove(hash);
+        }
+    }
+
+    /// Queue any retries that are due, checking at most `TimeoutChecksLimit` scheduled retries.
+    ///
+    /// Each check continues after the last retry the previous one checked, and starts over once every retry was checked, so
+    /// that retries which are not due yet cannot keep the due ones from being checked. Retries are left scheduled if the queue is full.
+    pub(crate) fn queue_due_retries(block: T::BlockNumber) -> Weight {
+        let limit = T::TimeoutChecksLimit::get() as usize;
+        let start = RetryCursor::<T>::take();
+        let retries: Vec<(T::Hash, (T::BlockNumber, Message))> = match start.clone() {
+            Some(cursor) => ScheduledRetries::<T>::iter_from(cursor),
+            None => ScheduledRetries::<T>::iter(),
+        }
+        .take(limit)
+        .collect();
+        let mut weight = T::DbWeight::get().reads_writes(retries.len() as u64 + 1, 1);
+
+        let mut cursor = match retries.last() {
+            Some((hash, _)) if retries.len() == limit => {
+                Some(ScheduledRetries::<T>::hashed_key_for(hash))
+            }
+            _ => None,
+        };
+        let mut checked = start;
+        let mut queue = <Queue<Pallet<T>>>::default();
+        for (hash, (at, msg)) in retries {
+            if at <= block {
+                let signal = match &msg {
+                    Message::Request(_) => QueueSignal::PendingRequest,
+                    Message::Response(..) => QueueSignal::PendingResponse,
+                };
+                if let Err(e) = queue.push((msg, signal)) {
+                    log::warn!(target: "xbi", "Failed to queue retry {:?}, leaving it scheduled: {:?}", hash, e);
+                    // The retry is the first to be checked once the queue has room
+                    cursor = checked;
+                    break;
+                }
+                ScheduledRetries::<T>::remove(hash);
+                weight = weight.saturating_add(T::DbWeight::get().reads_writes(3, 4));
+            }
+            checked = Some(ScheduledRetries::<T>::hashed_key_for(hash));
+        }
+
+        if let Some(cursor) = cursor {
+            RetryCursor::<T>::put(cursor);
+        }
+        weight
+    }
+
+    /// Route a single instruction to `Config::InstructionHandlers`
+    fn route_instruction(
+        origin: &T::Origin,
+        xbi: &mut XbiFormat,
+    ) -> Result<HandlerInfo<Weight>, DispatchErrorWithPostInfo<PostDispatchInfo>> {
+        let identifier = xbi.instr.identifier();
+        match xbi.instr {
+            // Results are handled as responses by the receiver, and batches cannot be nested
+            XbiInstruction::Result { .. } | XbiInstruction::Batch { .. } => {
+                Err(Error::<T>::InstructionuctionNotAllowedHere.into())
+            }
+            _ if T::InstructionHandlers::accepts(identifier) => {
+                T::InstructionHandlers::handle(origin, xbi)
+            }
+            _ => {
+                log::debug!(target: "xbi", "unhandled instruction: {:?}", identifier);
+                Err(Error::<T>::UnknownInstruction.into())
+            }
+        }
+    }
+
+    /// Handle each instruction of a batch in a storage transaction, the changes of every instruction are rolled back if any of them fail.
+    ///
+    /// The output is the encoded output of each instruction, and the weight is the sum of their weights.
+    fn handle_batch(
+        origin: &T::Origin,
+        xbi: &mut XbiFormat,
+    ) -> Result<HandlerInfo<Weight>, DispatchErrorWithPostInfo<PostDispatchInfo>> {
+        let instructions = match xbi.instr {
+            XbiInstruction::Batch { ref instructions } => instructions.clone(),
+            _ => return Err(Error::<T>::UnknownInstruction.into()),
+        };
+
+        with_transaction(|| {
+            let mut outputs = Vec::with_capacity(instructions.len());
+            let mut weight: Weight = 0;
+
+            for instr in instructions {
+                let mut step = XbiFormat {
+                    instr,
+                    metadata: xbi.metadata.clone(),
+                };
+                match Self::route_instruction(origin, &mut step) {
+                    Ok(info) => {
+                        weight = weight.saturating_add(info.weight);
+                        outputs.push(info.output);
+                    }
+                    Err(mut err) => {
+                        log::debug!(target: "xbi", "Batch instruction {} failed, rolling back: {:?}", outputs.len(), err);
+                        err.post_info.actual_weight =
+                            Some(weight.saturating_add(err.post_info.actual_weight.unwrap_or(0)));
+                        return TransactionOutcome::Rollback(Err(err));
+                    }
+                }
+            }
+
+            TransactionOutcome::Commit(Ok(HandlerInfo {
+                output: outputs.encode(),
+                weight,
+            }))
+        })
+    }
+
+    /// Expire any in-flight requests that breached their deadlines, checking at most `TimeoutChecksLimit` requests.
+    ///
+    /// Each check continues after the last request the previous one checked, and starts over once every request was checked,
+    /// so that requests are checked even when more are in-flight than the limit.
+    pub(crate) fn check_timeouts(current_block: u32) -> Weight {
+        let limit = T::TimeoutChecksLimit::get() as usize;
+        let requests: Vec<(T::Hash, XbiFormat)> = match TimeoutCursor::<T>::take() {
+            Some(cursor) => XbiRequests::<T>::iter_from(cursor),
+            None => XbiRequests::<T>::iter(),
+        }
+        .take(limit)
+        .collect();
+        if requests.len() == limit {
+            if let Some((hash, _)) = requests.last() {
+                TimeoutCursor::<T>::put(XbiRequests::<T>::hashed_key_for(hash));
+            }
+        }
+        let mut weight = T::DbWeight::get().reads_writes(requests.len() as u64 + 1, 1);
+
+        for (_, request) in requests {
+            if let Some(status) = request
+                .metadata
+                .timeout_status(current_block, T::ExpectedBlockTimeMs::get())
+            {
+                log::debug!(target: "xbi", "Request {:?} timed out with {:?}", request.metadata.get_id(), status);
+                if let Err(e) = Self::resolve_timeout(&request.metadata, status) {
+                    log::error!(target: "xbi", "Failed to resolve timeout: {:?}", e);
+                }
+                weight = weight.saturating_add(T::DbWeight::get().reads_writes(2, 3));
+            }
+        }
+        weight
+    }
+}
+
+/// Queries of the messages the portal holds, these back the `XbiPortalApi` runtime API
+impl<T: Config> Pallet<T> {
+    /// What the portal knows of the message with the given id, if anything
+    pub fn message_lifecycle(id: H256) -> Option<MessageLifecycle> {
+        let hash = cast_hash::<T>(&id).ok()?;
+        let request = XbiRequests::<T>::get(hash);
+        let queued = <Queue<Pallet<T>>>::default().get_by_id(&id);
+        let retry = ScheduledRetries::<T>::get(hash).map(|(_, msg)| msg);
+        let dead_letter = DeadLetters::<T>::get(hash);
+
+        // A request in-flight from here is progressed in place, otherwise the copy being handled is the latest
+        let timesheet = request
+            .as_ref()
+            .map(|format| &format.metadata)
+            .or_else(|| queued.as_ref().map(|(msg, _)| msg.get_metadata()))
+            .or_else(|| retry.as_ref().map(Message::get_metadata))
+            .or_else(|| dead_letter.as_ref().map(|(msg, _)| msg.get_metadata()))
+            .map(|metadata| metadata.get_timesheet().clone());
+
+        let lifecycle = MessageLifecycle {
+            request,
+            timesheet,
+            signal: queued.map(|(_, signal)| signal),
+            dead_letter: dead_letter.map(|(_, status)| status),
+            result: XbiResponses::<T>::get(hash),
+        };
+        (lifecycle != MessageLifecycle::default()).then_some(lifecycle)
+    }
+
+    /// A page of up to `count` messages in the queue from the `start`th, see `KeyedQueue::items` for their order.
+    ///
+    /// No more than `MAX_QUERIED_MESSAGES` are returned.
+    pub fn pending_queue(start: u32, count: u32) -> Vec<(Message, QueueSignal)> {
+        <Queue<Pallet<T>>>::default().page(
+            start.unique_saturated_into(),
+            count.min(MAX_QUERIED_MESSAGES).unique_saturated_into(),
+        )
+    }
+
+    /// The result of each message, if it is in, only the first `MAX_QUERIED_MESSAGES` ids are looked up
+    pub fn results(ids: Vec<H256>) -> Vec<Option<XbiResult>> {
+        ids.iter()
+            .take(MAX_QUERIED_MESSAGES.unique_saturated_into())
+            .map(|id| cast_hash::<T>(id).ok().and_then(XbiResponses::<T>::get))
+            .collect()
+    }
+}
+
+impl<T: Config> Writable<Continuation> for Pallet<T> {
+    fn write(continuation: Continuation) -> DispatchResult {
+        let first = continuation
+            .ids
+            .first()
+            .ok_or(DispatchError::Other("A promise has no requests"))?;
+        let promise = cast_hash::<T>(first)?;
+        for id in continuation.ids.iter() {
+            PromisedRequests::<T>::insert(cast_hash::<T>(id)?, promise);
+        }
+        Promises::<T>::insert(promise, continuation);
+        Ok(())
+    }
 }
 
 impl<T: Config> ChannelProgressionEmitter for Pallet<T> {
@@ -81,17 +860,55 @@ impl<T: Config> ChannelProgressionEmitter for Pallet<T> {
         use crate::Event::*;
         Self::deposit_event(XbiMessageSent { msg });
     }
+
+    fn emit_send_failed(metadata: &XbiMetadata, error: &XcmError) {
+        if let Ok(hash) = cast_hash::<T>(&metadata.get_id()) {
+            Self::deposit_event(Event::XbiSendFailed {
+                hash,
+                error: *error,
+            });
+        }
+    }
+
+    fn emit_refund_failed(metadata: &XbiMetadata, error: &DispatchError) {
+        if let Ok(hash) = cast_hash::<T>(&metadata.get_id()) {
+            Self::deposit_event(Event::XbiRefundFailed {
+                hash,
+                error: *error,
+            });
+        }
+    }
+
+    fn emit_timed_out(metadata: &XbiMetadata, status: &Status) {
+        if let Ok(hash) = cast_hash::<T>(&metadata.get_id()) {
+            Self::deposit_event(Event::XbiTimedOut {
+                hash,
+                status: status.clone(),
+            });
+        }
+    }
+
+    fn emit_queue_overflow(metadata: &XbiMetadata) {
+        if let Ok(hash) = cast_hash::<T>(&metadata.get_id()) {
+            Self::deposit_event(Event::XbiQueueOverflow { hash });
+        }
+    }
 }
 
 impl<C: Config> ReceiveCallProvider for Pallet<C> {
-    fn provide<T: Into<Message>>(t: T) -> Vec<u8> {
-        let msg = t.into();
-        let mut xbi_call: sp_std::collections::vec_deque::VecDeque<u8> =
-            crate::pallet::Call::receive::<C> { msg }.encode().into();
-        // FIXME: lookup index for target from metadata, cannot be retrieved from PalletInfo
-        // TODO: implement dynamism to this
-        xbi_call.push_front(200);
-        xbi_call.into()
+    fn provide<T: Into<Message>>(t: T) -> Result<Vec<u8>, DispatchError> {
+        let msg: Message = t.into();
+        let peer = msg.get_metadata().next_hop(C::ParachainId::get());
+        let version = Self::peer_version(peer);
+        let msg = msg.into_version(version).map_err(|e| {
+            log::warn!(target: "xbi", "Cannot send message to peer on XBI version {}: {}", version, e);
+            Error::<C>::UnsupportedXbiVersion
+        })?;
+
+        let portal = Self::peer_portal(peer);
+        let mut xbi_call = vec![portal.pallet, portal.call];
+        msg.encode_to(&mut xbi_call);
+        Ok(xbi_call)
     }
 }
 
@@ -109,120 +926,18 @@ impl<T: Config> XbiInstructionHandler<T::Origin> for Pallet<T> {
 
         log::debug!(target: "xbi", "Handling instruction for caller {:?} and message {:?}", caller, xbi);
 
-        let result = match xbi.instr {
-            XbiInstruction::Transfer { ref dest, value } => T::Currency::transfer(
-                &caller,
-                &account_from_account32::<T>(dest)?,
-                value.unique_saturated_into(),
-                ExistenceRequirement::AllowDeath,
-            )
-            .map(|_| Default::default())
-            .map_err(|_| Error::<T>::TransferFailed.into()),
-            XbiInstruction::CallWasm {
-                ref dest,
-                value,
-                gas_limit,
-                storage_deposit_limit,
-                ref data,
-            } => {
-                let contract_result = T::Contracts::call(
-                    caller,
-                    account_from_account32::<T>(dest)?,
-                    value.unique_saturated_into(),
-                    gas_limit,
-                    storage_deposit_limit.map(UniqueSaturatedInto::unique_saturated_into),
-                    data.clone(),
-                    false, // ALWAYS FALSE, could panic the runtime unless over rpc
-                );
-                contract_result
-                    .result
-                    .map(|r| HandlerInfo {
-                        output: r.data.0,
-                        weight: contract_result.gas_consumed,
-                    })
-                    .map_err(|e| DispatchErrorWithPostInfo {
-                        post_info: PostDispatchInfo {
-                            actual_weight: Some(contract_result.gas_consumed),
-                            pays_fee: Default::default(),
-                        },
-                        error: e,
-                    })
-            }
-            XbiInstruction::CallEvm {
-                source,
-                target,
-                value,
-                ref input,
-                gas_limit,
-                max_fee_per_gas,
-                max_priority_fee_per_gas,
-                nonce,
-                ref access_list,
-            } => {
-                let evm_result = T::Evm::call(
-                    origin.clone(),
-                    source,
-                    target,
-                    input.clone(),
-                    value,
-                    gas_limit,
-                    max_fee_per_gas,
-                    max_priority_fee_per_gas,
-                    nonce,
-                    access_list.clone(),
-                );
-                let weight = evm_result.clone().map(|(_, weight)| weight);
-
-                evm_result
-                    .map(|(x, weight)| HandlerInfo {
-                        output: x.value,
-                        weight,
-                    })
-                    .map_err(|e| DispatchErrorWithPostInfo {
-                        post_info: PostDispatchInfo {
-                            actual_weight: weight.ok(),
-                            pays_fee: Default::default(),
-                        },
-                        error: e,
-                    })
-            }
-            XbiInstruction::Swap { .. }
-            | XbiInstruction::AddLiquidity { .. }
-            | XbiInstruction::RemoveLiquidity { .. }
-            | XbiInstruction::GetPrice { .. } => Err(Error::<T>::DefiUnsupported.into()),
-            XbiInstruction::TransferAssets {
-                currency_id,
-                ref dest,
-                value,
-            } => {
-                let keep_alive = true;
-
-                let currency_id = <T::Assets as frame_support::traits::fungibles::Inspect<
-                    T::AccountId,
-                >>::AssetId::decode(
-                    &mut &currency_id.encode()[..]
-                )
-                .map_err(|_| Error::<T>::FailedToCastValue)?;
-
-                // TODO: have an assertion that the destination actually was updated
-                T::Assets::transfer(
-                    currency_id,
-                    &caller,
-                    &account_from_account32::<T>(dest)?,
-                    value.unique_saturated_into(),
-                    keep_alive,
-                )
-                .map(|_| Default::default())
-                .map_err(|_| Error::<T>::TransferFailed.into())
-            }
-            ref x => {
-                log::debug!(target: "xbi", "unhandled instruction: {:?}", x);
-                Ok(Default::default())
-            }
+        let result = if let XbiInstruction::Batch { .. } = xbi.instr {
+            Self::handle_batch(origin, xbi)
+        } else {
+            Self::route_instruction(origin, xbi)
         };
 
+        // The user pays for handling the request and notifying them of its result, as well as for the instruction
         xbi.metadata.fees.push_aggregate(
-            T::FeeConversion::weight_to_fee(&T::NotificationWeight::get()).unique_saturated_into(),
+            T::FeeConversion::weight_to_fee(
+                &T::WeightInfo::receive_request().saturating_add(T::NotificationWeight::get()),
+            )
+            .unique_saturated_into(),
         );
         match &result {
             Ok(info) => {
@@ -241,15 +956,28 @@ impl<T: Config> XbiInstructionHandler<T::Origin> for Pallet<T> {
     }
 }
 
-// TODO: benchmarking
 impl<T: Config> Writable<(H256, XbiResult)> for Pallet<T> {
     fn write(t: (H256, XbiResult)) -> sp_runtime::DispatchResult {
         let (hash, result) = t;
-        let hash: T::Hash =
-            Decode::decode(&mut &hash.encode()[..]).map_err(|_| Error::<T>::FailedToCastHash)?;
+        let hash = cast_hash::<T>(&hash)?;
         if !XbiResponses::<T>::contains_key(hash) {
+            // The request is no longer in-flight once it has a result
+            let request = XbiRequests::<T>::take(hash);
             XbiResponses::<T>::insert(hash, result.clone());
+
+            // Requests that were sent from here are called back, the weight of the callback cannot be known upfront so it is
+            // limited to the weight a response may use
+            if let Some(request) = request {
+                let limit = T::ResponseWeightLimit::get();
+                let weight = T::Callback::callback(&request.metadata, &result, limit);
+                <frame_system::Pallet<T>>::register_extra_weight_unchecked(
+                    weight.min(limit),
+                    DispatchClass::Mandatory,
+                );
+            }
+
             Self::deposit_event(Event::<T>::ResponseStored { hash, result });
+            Self::resolve_promise(hash);
             Ok(())
         } else {
             Err(Error::<T>::ResponseAlreadyStored.into())
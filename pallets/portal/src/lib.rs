@@ -7,7 +7,7 @@
 pub use pallet::*;
 pub use substrate_abi;
 pub use substrate_contracts_abi;
-pub use xp_channel::{queue::QueueSignal, ChannelProgressionEmitter, Message};
+pub use xp_channel::{queue::QueueSignal, ChannelProgressionEmitter, Message, VersionedMessage};
 pub use xp_format;
 
 use codec::{Decode, Encode};
@@ -19,10 +19,11 @@ use frame_system::{ensure_signed, pallet_prelude::OriginFor};
 use sp_runtime::{traits::UniqueSaturatedInto, DispatchError};
 use sp_std::{default::Default, prelude::*};
 use xp_channel::{
-    queue::ringbuffer::RingBufferTransient,
-    traits::{HandlerInfo, Writable, XbiInstructionHandler},
+    queue::keyed::KeyedQueue,
+    traits::{Writable, XbiInstructionHandler, XbiInstructionRoute},
+    XcmError,
 };
-use xp_format::{Status, XbiFormat, XbiMetadata, XbiResult};
+use xp_format::{Status, XbiFormat, XbiMetadata, XbiResult, XbiVersion, XBI_VERSION};
 use xs_channel::receiver::Receiver as XbiReceiver;
 use xs_channel::sender::{frame::ReceiveCallProvider, Sender as XbiSender};
 
@@ -31,35 +32,64 @@ mod mock;
 #[cfg(test)]
 mod tests;
 
+#[cfg(feature = "runtime-benchmarks")]
+mod benchmarking;
+
+pub mod handlers;
 pub mod impls;
+pub mod migrations;
+pub mod origins;
 pub mod primitives;
+pub mod weights;
 pub mod xbi_abi;
 pub mod xbi_scabi;
 
 t3rn_primitives::reexport_currency_types!();
 
+/// Where the portal is in the runtime of a peer parachain: the index of the pallet, and of the `receive` call in the pallet
+#[derive(Clone, Copy, Eq, PartialEq, Encode, Decode, scale_info::TypeInfo, Debug)]
+pub struct PortalIndex {
+    pub pallet: u8,
+    pub call: u8,
+}
+
+/// How a request sent with `send` is given its id, so that its sender can know the id before the request is sent.
+/// Requests are otherwise given an id derived from the next nonce of their sender, see `XbiMetadata::derive_id`.
+#[derive(Clone, Eq, PartialEq, Encode, Decode, scale_info::TypeInfo, Debug)]
+pub enum MessageIdSource {
+    /// An id derived from a seed chosen by the sender, rather than from its nonce
+    Seed(Vec<u8>),
+    /// An id chosen by the sender, the request is identified by its hash with the sender, see `Pallet::explicit_message_id`
+    Explicit(sp_core::H256),
+}
+
 #[frame_support::pallet]
 pub mod pallet {
+    pub use crate::weights::WeightInfo;
     use crate::{
-        impls::account32_from_account,
-        primitives::{defi::DeFi, xbi_callback::XBICallback},
+        impls::cast_hash,
+        primitives::{custom_vm::CustomVm, defi::DeFi, xbi_callback::XBICallback},
         Event::{QueueEmpty, QueuePopped},
         *,
     };
     use contracts_primitives::ContractExecResult;
     use frame_support::traits::{
         fungibles::{Inspect, Mutate},
-        OriginTrait,
+        Contains, OriginTrait,
     };
+    use frame_support::weights::GetDispatchInfo;
     use frame_support::{
         pallet_prelude::*,
         traits::{fungibles::Transfer, ReservableCurrency},
     };
-    use frame_system::pallet_prelude::*;
-    use sp_runtime::traits::{BlakeTwo256, Zero};
+    use frame_system::{pallet_prelude::*, RawOrigin};
+    use sp_runtime::{
+        traits::{Dispatchable, Zero},
+        DispatchErrorWithPostInfo,
+    };
     use xcm::v2::SendXcm;
     use xp_channel::{
-        queue::{ringbuffer::DefaultIdx, Queue as QueueExt, QueueSignal},
+        queue::{ringbuffer::DefaultIdx, Queue as QueueExt, QueueSignal, SubQueue},
         traits::RefundForMessage,
         ExecutionType,
     };
@@ -69,15 +99,16 @@ pub mod pallet {
     use xp_xcm::MultiLocationBuilder;
     use xp_xcm::{xcm::prelude::*, XcmBuilder};
     use xs_channel::receiver::frame::{handle_instruction_result, invert_destination_from_message};
+    use xs_channel::sender::frame::Continuation;
 
-    type AssetIdOf<T> =
-        <<T as Config>::Assets as Inspect<<T as frame_system::Config>::AccountId>>::AssetId;
-
-    /// A reexport of the Queue backed by a RingBufferTransient
-    pub(crate) type Queue<Pallet> = RingBufferTransient<
+    /// A reexport of the Queue backed by a KeyedQueue, with a sub-queue for each priority and peer parachain
+    pub(crate) type Queue<Pallet> = KeyedQueue<
         (Message, QueueSignal),
-        <Pallet as Store>::BufferRange,
+        <Pallet as Store>::QueueKeys,
+        <Pallet as Store>::QueueRanges,
         <Pallet as Store>::QueueItems,
+        <Pallet as Store>::QueueIndex,
+        <Pallet as Store>::QueueLength,
         DefaultIdx,
     >;
 
@@ -92,6 +123,8 @@ pub mod pallet {
         <T as Config>::AssetRegistry,
         (),
         <T as Config>::ReserveBalanceCustodian,
+        <T as Config>::ResponseWeightLimit,
+        Pallet<T>,
     >;
 
     /// A reexport of the Sender backed by the Queue
@@ -102,6 +135,7 @@ pub mod pallet {
         <T as Config>::Assets,
         (),
         <T as Config>::ReserveBalanceCustodian,
+        Pallet<T>,
     >;
 
     /// A reexport of the synchronous receiver
@@ -133,36 +167,124 @@ pub mod pallet {
     pub type XbiResponses<T> =
         StorageMap<_, Blake2_128Concat, <T as frame_system::Config>::Hash, XbiResult, OptionQuery>;
 
+    /// The storage key of the last request checked for timeouts, the next check continues after it
+    #[pallet::storage]
+    pub(super) type TimeoutCursor<T> = StorageValue<_, Vec<u8>, OptionQuery>;
+
     #[pallet::storage]
     #[pallet::getter(fn queue_item)]
     pub(super) type QueueItems<T> =
-        StorageMap<_, Blake2_128Concat, DefaultIdx, (Message, QueueSignal), ValueQuery>;
+        StorageMap<_, Blake2_128Concat, (SubQueue, DefaultIdx), (Message, QueueSignal), ValueQuery>;
 
+    /// The bounds of each sub-queue that has messages
     #[pallet::storage]
     #[pallet::getter(fn queue_range)]
-    pub(super) type BufferRange<T: Config> =
-        StorageValue<_, (DefaultIdx, DefaultIdx), ValueQuery, BufferIndexDefaultValue>;
+    pub(super) type QueueRanges<T> =
+        StorageMap<_, Blake2_128Concat, SubQueue, (DefaultIdx, DefaultIdx), ValueQuery>;
 
-    #[pallet::type_value]
-    pub(super) fn BufferIndexDefaultValue() -> (DefaultIdx, DefaultIdx) {
-        (0, 0)
-    }
+    /// The sub-queues that have messages, in the order they are next processed within their priority
+    #[pallet::storage]
+    pub(super) type QueueKeys<T> = StorageValue<_, Vec<SubQueue>, ValueQuery>;
+
+    /// The position of each queued message by its id, so that it can be found without reading the queue
+    #[pallet::storage]
+    pub(super) type QueueIndex<T> =
+        StorageMap<_, Blake2_128Concat, sp_core::H256, (SubQueue, DefaultIdx), OptionQuery>;
+
+    /// The number of messages across every sub-queue
+    #[pallet::storage]
+    pub(super) type QueueLength<T> = StorageValue<_, DefaultIdx, ValueQuery>;
 
+    /// The nonce of the last request sent by each account, the ids of requests are derived from it
     #[pallet::storage]
     #[pallet::getter(fn message_nonce)]
-    pub(super) type MessageNonce<T: Config> =
-        StorageValue<_, u32, ValueQuery, MessageNonceDefaultValue>;
+    pub type MessageNonces<T: Config> =
+        StorageMap<_, Blake2_128Concat, T::AccountId, u32, ValueQuery>;
 
-    #[pallet::type_value]
-    pub(super) fn MessageNonceDefaultValue() -> u32 {
-        0
-    }
+    /// The XBI version each peer parachain supports, peers without a record are assumed to be on the latest version
+    #[pallet::storage]
+    pub type PeerVersions<T> = StorageMap<_, Blake2_128Concat, u32, XbiVersion, OptionQuery>;
+
+    /// Where the portal is on each peer parachain, peers without a record are assumed to be at `DefaultPortalIndex`
+    #[pallet::storage]
+    pub type PeerPortals<T> = StorageMap<_, Blake2_128Concat, u32, PortalIndex, OptionQuery>;
+
+    /// The peers this parachain relays messages between, set by governance. Messages are only forwarded from and to these peers
+    #[pallet::storage]
+    pub type RelayPeers<T> = StorageMap<_, Blake2_128Concat, u32, (), OptionQuery>;
+
+    /// The continuations of promises, keyed by the id of their first request
+    #[pallet::storage]
+    pub type Promises<T> = StorageMap<
+        _,
+        Blake2_128Concat,
+        <T as frame_system::Config>::Hash,
+        Continuation,
+        OptionQuery,
+    >;
+
+    /// The promise each request with a promise belongs to, until its result is in
+    #[pallet::storage]
+    pub type PromisedRequests<T> = StorageMap<
+        _,
+        Blake2_128Concat,
+        <T as frame_system::Config>::Hash,
+        <T as frame_system::Config>::Hash,
+        OptionQuery,
+    >;
+
+    /// The number of times a message failed to be transported, cleared once it is sent or dead-lettered
+    #[pallet::storage]
+    pub type RetryAttempts<T> =
+        StorageMap<_, Blake2_128Concat, <T as frame_system::Config>::Hash, u8, ValueQuery>;
+
+    /// Messages that failed to be transported, waiting until the given block to be queued again
+    #[pallet::storage]
+    pub type ScheduledRetries<T: Config> =
+        StorageMap<_, Blake2_128Concat, T::Hash, (T::BlockNumber, Message), OptionQuery>;
+
+    /// The storage key of the last scheduled retry checked, the next check continues after it
+    #[pallet::storage]
+    pub(super) type RetryCursor<T> = StorageValue<_, Vec<u8>, OptionQuery>;
+
+    /// The requests executed from each source parachain and the block until which they are remembered, so that they are
+    /// not executed again if they are delivered twice
+    #[pallet::storage]
+    pub type ExecutedRequests<T> = StorageDoubleMap<
+        _,
+        Blake2_128Concat,
+        u32,
+        Blake2_128Concat,
+        <T as frame_system::Config>::Hash,
+        u32,
+        OptionQuery,
+    >;
+
+    /// The storage key of the last executed request checked for expiry, the next check continues after it
+    #[pallet::storage]
+    pub(super) type ExecutedCursor<T> = StorageValue<_, Vec<u8>, OptionQuery>;
+
+    /// Messages that could not be transported within `MaxRetries`, these can be inspected and replayed by governance
+    #[pallet::storage]
+    #[pallet::getter(fn dead_letter)]
+    pub type DeadLetters<T> = StorageMap<
+        _,
+        Blake2_128Concat,
+        <T as frame_system::Config>::Hash,
+        (Message, Status),
+        OptionQuery,
+    >;
 
     #[pallet::config]
     pub trait Config: frame_system::Config {
         // TODO: disable SendTransactionTypes<Call<Self>> for now
         type Event: From<Event<Self>> + IsType<<Self as frame_system::Config>::Event>;
-        type Call: From<Call<Self>>;
+        type Call: Parameter
+            + Dispatchable<Origin = Self::Origin, PostInfo = PostDispatchInfo>
+            + GetDispatchInfo
+            + From<Call<Self>>;
+        /// The runtime calls that can be dispatched by `CallNative` instructions
+        type NativeCallFilter: Contains<<Self as Config>::Call>;
         type XcmSovereignOrigin: Get<Self::AccountId>;
         /// Access to XCM functionality outside of this consensus system TODO: use XcmSender && ExecuteXcm for self execution
         type Xcm: SendXcm;
@@ -184,15 +306,25 @@ pub mod pallet {
         type AssetRegistry: AssetLookup<<Self::Assets as Inspect<Self::AccountId>>::AssetId>;
         /// Provide access to DeFI
         type DeFi: DeFi<Self>;
-        // TODO: might not actually need this
+        /// Provide access to any other VM for `CallCustom` instructions
+        type CustomVm: CustomVm<Self>;
+        /// The handlers that instructions are routed to, see `handlers::DefaultHandlers`
+        type InstructionHandlers: XbiInstructionRoute<Self::Origin>;
+        /// Notified of the result of every request sent from this parachain
         type Callback: XBICallback<Self>;
         /// Convert XBI instruction weights to fees
         type FeeConversion: WeightToFee;
         /// A place to store reserved funds whilst we approach a nicer way of reserving asset funds
         type ReserveBalanceCustodian: Get<Self::AccountId>;
+        /// The weights of the portal, which should be generated from its benchmarks
+        type WeightInfo: WeightInfo;
 
+        /// The weight of handling the response to a request on the source, see `impls::MeasuredNotificationWeight`
         #[pallet::constant]
         type NotificationWeight: Get<Weight>;
+        /// The weight a peer may use to dispatch a response on the source, it is bought with the notification budget of the request
+        #[pallet::constant]
+        type ResponseWeightLimit: Get<Weight>;
 
         // Queue management constants, needs revisiting TODO
         #[pallet::constant]
@@ -205,12 +337,47 @@ pub mod pallet {
         type CheckInLimit: Get<u32>;
         #[pallet::constant]
         type CheckOutLimit: Get<u32>;
+        /// The maximum weight that processing the queue may use each interval, messages that don't fit are left for the next
+        #[pallet::constant]
+        type QueueWeightLimit: Get<Weight>;
+        /// The number of times a message that failed to be transported is retried before it is dead-lettered
+        #[pallet::constant]
+        type MaxRetries: Get<u8>;
+        /// The blocks to wait before the first retry, doubling with each attempt after
+        #[pallet::constant]
+        type RetryBackoff: Get<Self::BlockNumber>;
         #[pallet::constant]
         type ParachainId: Get<u32>;
+        /// Where the portal is on peers without a record in `PeerPortals`
+        #[pallet::constant]
+        type DefaultPortalIndex: Get<PortalIndex>;
+        /// The origin of a peer parachain, resolving to its para id, see `origins::EnsureSibling`
+        type PeerOrigin: EnsureOrigin<Self::Origin, Success = u32>;
+        /// Whether local accounts may `receive` messages, rather than only peers. This trusts any signed origin with the source of
+        /// the message, and should only be enabled for testing.
+        #[pallet::constant]
+        type AllowLocalReceive: Get<bool>;
+        /// The most blocks a request is remembered as executed, however long the lifetime its timeouts give it
+        #[pallet::constant]
+        type MaxExecutedRetention: Get<u32>;
+        /// The most records of executed requests checked for expiry every `CheckInterval`
+        #[pallet::constant]
+        type ExecutedPruneLimit: Get<u32>;
+        /// The fee charged for each message this parachain forwards along its route, in the asset the fees of the message are paid in.
+        /// It is aggregated into the costs of the message and taken from the sovereign account of the previous hop, where the
+        /// fees that the message brought are deposited, and paid to the `XcmSovereignOrigin` that sends it on.
+        #[pallet::constant]
+        type ForwardingFee: Get<u128>;
+        /// Converts the location of a peer parachain to its sovereign account here, see `origins::EnsureSibling`
+        type LocationToAccountId: XcmConvert<MultiLocation, Self::AccountId>;
     }
 
+    /// The version of the storage of the portal, see `migrations`
+    const STORAGE_VERSION: StorageVersion = StorageVersion::new(1);
+
     #[pallet::pallet]
     #[pallet::generate_store(pub(super) trait Store)]
+    #[pallet::storage_version(STORAGE_VERSION)]
     #[pallet::without_storage_info]
     pub struct Pallet<T>(_);
 
@@ -223,9 +390,16 @@ pub mod pallet {
         fn on_initialize(block: T::BlockNumber) -> Weight {
             // TODO: enable when confident it works
             if block % T::CheckInterval::get() == Zero::zero() {
-                Pallet::<T>::process_queue(T::Origin::root())
-                    .map(|i| i.actual_weight.unwrap_or_default())
-                    .unwrap_or_else(|e| e.post_info.actual_weight.unwrap_or_default())
+                Pallet::<T>::check_timeouts(block.unique_saturated_into())
+                    .saturating_add(Pallet::<T>::queue_due_retries(block))
+                    .saturating_add(Pallet::<T>::prune_executed_requests(
+                        block.unique_saturated_into(),
+                    ))
+                    .saturating_add(
+                        Pallet::<T>::process_queue(T::Origin::root())
+                            .map(|i| i.actual_weight.unwrap_or_default())
+                            .unwrap_or_else(|e| e.post_info.actual_weight.unwrap_or_default()),
+                    )
             } else {
                 0
             }
@@ -269,6 +443,68 @@ pub mod pallet {
             hash: T::Hash,
             result: XbiResult,
         },
+        PeerVersionUpdated {
+            para_id: u32,
+            version: Option<XbiVersion>,
+        },
+        /// A message failed to be transported and will be queued again at block `at`
+        XbiRetryScheduled {
+            hash: T::Hash,
+            attempt: u8,
+            at: T::BlockNumber,
+        },
+        /// A message could not be transported within `MaxRetries` and was moved to the dead letters
+        XbiDeadLettered {
+            hash: T::Hash,
+            status: Status,
+        },
+        DeadLetterReplayed {
+            hash: T::Hash,
+        },
+        /// The results of every request of a promise are in, and its call was dispatched
+        XbiPromiseResolved {
+            hash: T::Hash,
+            result: DispatchResult,
+        },
+        /// A message could not be sent over XCM
+        XbiSendFailed {
+            hash: T::Hash,
+            error: XcmError,
+        },
+        /// The fees reserved for a message could not be refunded
+        XbiRefundFailed {
+            hash: T::Hash,
+            error: DispatchError,
+        },
+        /// A message breached one of its timeouts
+        XbiTimedOut {
+            hash: T::Hash,
+            status: Status,
+        },
+        /// A message was dropped because the queue is full
+        XbiQueueOverflow {
+            hash: T::Hash,
+        },
+        /// A message on its way to another parachain was queued to be forwarded to the next hop of its route
+        XbiForwarded {
+            hash: T::Hash,
+            next_hop: u32,
+        },
+        /// The record of where the portal is on a peer parachain was updated
+        PeerPortalUpdated {
+            para_id: u32,
+            index: Option<PortalIndex>,
+        },
+        /// Where this portal is was announced to a peer parachain
+        PortalAnnounced {
+            para_id: u32,
+            index: PortalIndex,
+        },
+        /// Whether messages are relayed from and to a peer parachain was updated
+        RelayPeerUpdated {
+            para_id: u32,
+            allowed: bool,
+        },
     }
 
     /// Errors that can occur while checking the authorship inherent.
@@ -307,63 +543,176 @@ pub mod pallet {
         ArithmeticErrorOverflow,
         TransferFailed,
         ResponseAlreadyStored,
+        UnsupportedXbiVersion,
+        FailedToDecodeCall,
+        CallFiltered,
+        UnknownInstruction,
+        QueueFull,
+        DeadLetterNotFound,
+        InvalidRoute,
+        PortalAnnouncementFailed,
+        UnexpectedPeer,
+        UnmatchedResponse,
+        DuplicateResponse,
+        DuplicateRequest,
+        MessageIdInUse,
+        NotRelayPeer,
+        ForwardingFeeNotCovered,
     }
 
-    /// TODO: implement benchmarks
     #[pallet::call]
     impl<T: Config> Pallet<T> {
-        #[pallet::weight(50_000 + T::DbWeight::get().writes(1) + T::DbWeight::get().reads(3))]
-        pub fn send(origin: OriginFor<T>, kind: ExecutionType, msg: XbiFormat) -> DispatchResult {
+        #[pallet::weight(match kind {
+            ExecutionType::Sync => T::WeightInfo::send_sync(),
+            ExecutionType::Async => T::WeightInfo::send_async(),
+        })]
+        pub fn send(
+            origin: OriginFor<T>,
+            kind: ExecutionType,
+            msg: XbiFormat,
+            id: Option<MessageIdSource>,
+        ) -> DispatchResult {
             let who = ensure_signed(origin)?;
-            let mut msg = msg;
-
-            msg.metadata
-                .enrich_origin(&account32_from_account::<T>(&who)?);
-
-            // Get and increment the nonce
-            let nonce = Self::message_nonce().wrapping_add(1);
-            <MessageNonce<T>>::set(nonce);
-            msg.metadata.enrich_id::<BlakeTwo256>(nonce, None);
+            let msg = Self::prepare_request(&who, &kind, msg, id)?;
 
             match kind {
-                ExecutionType::Sync => <Sender<T> as XbiSender<_>>::send(Message::Request(msg)),
-                ExecutionType::Async => {
-                    <AsyncSender<T> as XbiSender<_>>::send(Message::Request(msg))
-                }
+                ExecutionType::Sync => <Sender<T> as XbiSender<_>>::send(msg),
+                ExecutionType::Async => <AsyncSender<T> as XbiSender<_>>::send(msg),
             }
         }
 
         /// This receive api is called by the sender on the source parachain and needs to exist for
         /// the handler to be able to invoke
         ///
+        /// The origin must be the `PeerOrigin` of the parachain the message was sent from, which is the source or the previous hop
+        /// of its route, and a previous hop other than the source must be one of the `RelayPeers`. Signed origins of local accounts
+        /// are rejected unless `AllowLocalReceive`.
+        ///
         /// There are additional ways this can be called:
         ///     - expose the same interface but allow some pathway to it: Contracts::call {..}
         ///     - expose a way to call a pallet method
-        #[pallet::weight(50_000 + T::DbWeight::get().writes(1) + T::DbWeight::get().reads(3))]
-        pub fn receive(origin: OriginFor<T>, msg: Message) -> DispatchResultWithPostInfo {
-            let _who = ensure_signed(origin.clone())?;
-            <Receiver<T> as XbiReceiver>::receive(origin, msg)
+        #[pallet::weight(match msg {
+            VersionedMessage::LegacyRequest(format) => T::WeightInfo::receive_request()
+                .saturating_add(Pallet::<T>::execution_weight(&format.metadata.fees)),
+            VersionedMessage::Request(format) if !format.is_result() => T::WeightInfo::receive_request()
+                .saturating_add(Pallet::<T>::execution_weight(format.fees())),
+            VersionedMessage::Request(_)
+            | VersionedMessage::LegacyResponse(..)
+            | VersionedMessage::Response(..) => T::WeightInfo::receive_response(),
+        })]
+        pub fn receive(origin: OriginFor<T>, msg: VersionedMessage) -> DispatchResultWithPostInfo {
+            let version = msg.request_version();
+            let msg: Message = msg.into();
+
+            match T::PeerOrigin::try_origin(origin.clone()) {
+                Ok(peer) => {
+                    let metadata = msg.get_metadata();
+                    ensure!(
+                        peer == metadata.previous_hop(T::ParachainId::get()),
+                        Error::<T>::UnexpectedPeer
+                    );
+                    // A peer that relays a message vouches for its source, which only relay peers are trusted to do
+                    ensure!(
+                        peer == metadata.src_para_id || <RelayPeers<T>>::contains_key(peer),
+                        Error::<T>::NotRelayPeer
+                    );
+                }
+                Err(origin) => {
+                    ensure_signed(origin)?;
+                    ensure!(T::AllowLocalReceive::get(), DispatchError::BadOrigin);
+                }
+            }
+
+            // Messages on their way to another parachain are passed on along their route
+            if msg.get_metadata().route.contains(&T::ParachainId::get()) {
+                return Self::forward(msg);
+            }
+
+            if let Some(version) = version {
+                Self::note_peer_version(msg.get_metadata().src_para_id, version);
+            }
+
+            // Responses must be for a request in-flight from here, and are refunded by the fees charged for it
+            let msg = match msg {
+                Message::Response(result, mut metadata) => {
+                    Self::correlate_response(&mut metadata)?;
+                    Message::Response(result, metadata)
+                }
+                // Requests are only executed once, however many times they are delivered
+                Message::Request(format) => {
+                    Self::note_executed(&format.metadata)?;
+                    Message::Request(format)
+                }
+            };
+
+            let overhead = match &msg {
+                Message::Request(_) => T::WeightInfo::receive_request(),
+                Message::Response(..) => T::WeightInfo::receive_response(),
+            };
+            // The receiver only meters the instruction it handles
+            <Receiver<T> as XbiReceiver>::receive(origin, msg).map(|info| PostDispatchInfo {
+                actual_weight: info
+                    .actual_weight
+                    .map(|weight| weight.saturating_add(overhead)),
+                ..info
+            })
         }
 
-        /// TODO: implement benchmarks
-        #[pallet::weight(50_000 + T::DbWeight::get().writes(1) + T::DbWeight::get().reads(3))]
+        /// Process up to `CheckOutLimit` messages from the queue, stopping early if the next message would exceed `QueueWeightLimit`.
+        /// Any messages that are not processed are left in the queue for the next interval.
+        #[pallet::weight(T::QueueWeightLimit::get())]
         pub fn process_queue(origin: OriginFor<T>) -> DispatchResultWithPostInfo {
             ensure_root(origin)?;
 
             let current_block: u32 =
                 <frame_system::Pallet<T>>::block_number().unique_saturated_into();
 
-            let mut weight: u64 = 0;
+            let max_messages = T::CheckOutLimit::get();
+            let weight_limit = T::QueueWeightLimit::get();
 
-            // TODO: terminal operations
-            let _max_events_to_process = T::CheckOutLimit::get();
+            // Reading the sub-queues that have messages
+            let mut weight: Weight = T::DbWeight::get().reads(1);
+            let mut processed: u32 = 0;
 
             let mut queue = <Queue<Pallet<T>>>::default();
 
             if queue.is_empty() {
                 Self::deposit_event(QueueEmpty);
             } else {
-                while let Some((mut msg, signal)) = queue.pop() {
+                while processed < max_messages {
+                    let (message_weight, required) = match queue.peek() {
+                        Some((msg, signal)) => {
+                            let message_weight = Pallet::<T>::queued_message_weight(&signal);
+                            let execution = match (msg, signal) {
+                                (Message::Request(format), QueueSignal::PendingExecution) => {
+                                    let execution =
+                                        Pallet::<T>::execution_weight(&format.metadata.fees);
+                                    // Executions that could never fit are rejected without being handled
+                                    if execution.saturating_add(message_weight) > weight_limit {
+                                        0
+                                    } else {
+                                        execution
+                                    }
+                                }
+                                _ => 0,
+                            };
+                            (message_weight, execution.saturating_add(message_weight))
+                        }
+                        None => break,
+                    };
+
+                    if weight.saturating_add(required) > weight_limit {
+                        log::debug!(target: "xbi", "Queue weight limit reached after {:?} messages, leaving the rest for the next interval", processed);
+                        break;
+                    }
+
+                    let (mut msg, signal) = match queue.pop() {
+                        Some(item) => item,
+                        None => break,
+                    };
+                    processed += 1;
+                    weight = weight.saturating_add(message_weight);
+
                     Self::deposit_event(QueuePopped {
                         signal: signal.clone(),
                         msg: msg.clone(),
@@ -372,28 +721,66 @@ pub mod pallet {
                     match signal {
                         QueueSignal::PendingRequest => {
                             if let Message::Request(format) = &mut msg {
-                                format.metadata.progress(Timestamp::Sent(current_block));
+                                let here = T::ParachainId::get();
+
+                                // Requests forwarded along their route are timed by their source
+                                if !format.metadata.route.contains(&here) {
+                                    if let Some(status) = format.metadata.timeout_status(
+                                        current_block,
+                                        T::ExpectedBlockTimeMs::get(),
+                                    ) {
+                                        log::debug!(target: "xbi", "Request {:?} timed out before being sent", format.metadata.get_id());
+                                        Pallet::<T>::resolve_timeout(&format.metadata, status)
+                                            .unwrap_or_else(|e| {
+                                                log::error!(target: "xbi", "Failed to resolve timeout: {:?}", e);
+                                            });
+                                        continue;
+                                    }
+
+                                    format.metadata.progress(Timestamp::Sent(current_block));
+                                }
+                                let message_id = format.metadata.get_id();
 
                                 // let o: T::AccountId = xbi_origin(&format.metadata)?;
                                 // ChargeForMessage::charge(&o, &format.metadata.fees)?; // FIXME
 
                                 // TODO: make function
                                 let dest = MultiLocationBuilder::new_parachain(
-                                    format.metadata.dest_para_id,
+                                    format.metadata.next_hop(here),
                                 )
                                 .with_parents(1)
                                 .build();
 
-                                // TODO: make function
-                                let payment_asset = match format.metadata.fees.asset {
-                                    Some(id) => {
-                                        let id: AssetIdOf<T> =
-                                            Decode::decode(&mut &id.encode()[..])
-                                                .map_err(|_| DispatchError::CannotLookup)?;
-                                        T::AssetRegistry::reverse_ref(id)
-                                            .map_err(|_| DispatchError::CannotLookup)?
+                                let payment_asset = match Pallet::<T>::payment_asset(
+                                    &format.metadata.fees,
+                                ) {
+                                    Ok(asset) => asset,
+                                    Err(e) => {
+                                        log::error!(target: "xbi", "Failed to look up the payment asset of request: {:?}", e);
+                                        Pallet::<T>::push_follow_up(
+                                            &mut queue,
+                                            (
+                                                msg,
+                                                QueueSignal::ProtocolError(Status::DispatchFailed),
+                                            ),
+                                        );
+                                        continue;
+                                    }
+                                };
+
+                                let call = match Pallet::<T>::provide(format.clone()) {
+                                    Ok(call) => call,
+                                    Err(e) => {
+                                        log::error!(target: "xbi", "Failed to provide call for request: {:?}", e);
+                                        Pallet::<T>::push_follow_up(
+                                            &mut queue,
+                                            (
+                                                msg,
+                                                QueueSignal::ProtocolError(Status::DispatchFailed),
+                                            ),
+                                        );
+                                        continue;
                                     }
-                                    None => MultiLocationBuilder::new_native().build(),
                                 };
 
                                 // TODO: make function
@@ -410,28 +797,88 @@ pub mod pallet {
                                     .with_transact(
                                         Some(OriginKind::SovereignAccount),
                                         Some(format.metadata.fees.execution_cost_limit as u64),
-                                        Pallet::<T>::provide(format.clone()),
+                                        call,
                                     )
+                                    .with_refund_surplus()
+                                    .with_deposit_asset(Pallet::<T>::surplus_beneficiary(), 1)
                                     .build();
 
                                 T::Xcm::send_xcm(dest, xbi_format_msg)
                                     .map(|_| {
                                         log::trace!(target: "xbi", "Successfully sent xcm message");
+                                        Pallet::<T>::progress_request(
+                                            &message_id,
+                                            Timestamp::Sent(current_block),
+                                        );
+                                        Pallet::<T>::clear_retries(&message_id);
                                         Pallet::<T>::emit_sent(msg.clone());
                                     })
                                     .unwrap_or_else(|e| {
                                         log::error!(target: "xbi", "Failed to send xcm request: {:?}", e);
-                                        queue.push((msg, QueueSignal::ProtocolError(Status::DispatchFailed)));
+                                        Pallet::<T>::emit_send_failed(msg.get_metadata(), &e.into());
+                                        Pallet::<T>::push_follow_up(&mut queue, (msg, QueueSignal::ProtocolError(Status::DispatchFailed)));
                                     });
                             }
                         }
                         QueueSignal::PendingExecution => {
                             if let Message::Request(msg) = &mut msg {
-                                invert_destination_from_message(&mut msg.metadata);
+                                // The request is only inverted into its response once it is handled, the handlers derive the
+                                // origin of the request from the parachain it came from
+                                if let Some(status) = msg
+                                    .metadata
+                                    .timeout_status(current_block, T::ExpectedBlockTimeMs::get())
+                                {
+                                    log::debug!(target: "xbi", "Request {:?} timed out before being executed", msg.metadata.get_id());
+                                    invert_destination_from_message(&mut msg.metadata);
+                                    Pallet::<T>::emit_timed_out(&msg.metadata, &status);
+                                    Pallet::<T>::push_follow_up(
+                                        &mut queue,
+                                        (
+                                            Message::Response(
+                                                XbiResult {
+                                                    status,
+                                                    ..Default::default()
+                                                },
+                                                msg.metadata.clone(),
+                                            ),
+                                            QueueSignal::PendingResponse,
+                                        ),
+                                    );
+                                    continue;
+                                }
 
-                                let instruction_result =
-                                    Pallet::<T>::handle(&T::Origin::root(), msg);
+                                // The execution limit is larger than the queue could ever process
+                                if Pallet::<T>::execution_weight(&msg.metadata.fees)
+                                    .saturating_add(message_weight)
+                                    > weight_limit
+                                {
+                                    log::debug!(target: "xbi", "Request {:?} exceeds the queue weight limit", msg.metadata.get_id());
+                                    invert_destination_from_message(&mut msg.metadata);
+                                    Pallet::<T>::push_follow_up(
+                                        &mut queue,
+                                        (
+                                            Message::Response(
+                                                XbiResult {
+                                                    status: Status::ExecutionLimitExceeded,
+                                                    ..Default::default()
+                                                },
+                                                msg.metadata.clone(),
+                                            ),
+                                            QueueSignal::PendingResponse,
+                                        ),
+                                    );
+                                    continue;
+                                }
+
+                                // Executions are dispatched as the peer the request was received from, as they are when it is received synchronously
+                                let peer = msg.metadata.previous_hop(T::ParachainId::get());
+                                let instruction_result = Pallet::<T>::peer_account(peer)
+                                    .map_err(DispatchErrorWithPostInfo::from)
+                                    .and_then(|peer| {
+                                        Pallet::<T>::handle(&RawOrigin::Signed(peer).into(), msg)
+                                    });
                                 log::debug!(target: "xbi", "Instruction result: {:?}", instruction_result);
+                                invert_destination_from_message(&mut msg.metadata);
 
                                 let xbi_result = handle_instruction_result::<Pallet<T>>(
                                     &instruction_result,
@@ -441,97 +888,142 @@ pub mod pallet {
                                 log::debug!(target: "xbi", "Instruction handled: {:?}", xbi_result);
                                 msg.metadata.progress(Timestamp::Executed(current_block));
 
+                                let handler_weight = match &instruction_result {
+                                    Ok(info) => Some(info.weight),
+                                    Err(e) => e.post_info.actual_weight,
+                                }
+                                .unwrap_or_default();
+                                weight = weight.saturating_add(handler_weight);
+
                                 Pallet::<T>::emit_request_handled(
                                     &xbi_result,
                                     &msg.metadata,
-                                    &match &instruction_result {
-                                        Ok(info) => Some(info.weight),
-                                        Err(e) => e.post_info.actual_weight,
-                                    }
-                                    .unwrap_or_default(),
+                                    &handler_weight,
                                 );
 
-                                queue.push((
-                                    Message::Response(xbi_result, msg.metadata.clone()),
-                                    QueueSignal::PendingResponse,
-                                ));
-
-                                let handler: DispatchResultWithPostInfo =
-                                    instruction_result.map(HandlerInfo::into);
-
-                                if let Ok(info) = handler {
-                                    weight = weight
-                                        .saturating_add(info.actual_weight.unwrap_or_default());
-                                }
+                                Pallet::<T>::push_follow_up(
+                                    &mut queue,
+                                    (
+                                        Message::Response(xbi_result, msg.metadata.clone()),
+                                        QueueSignal::PendingResponse,
+                                    ),
+                                );
                             }
                         }
                         QueueSignal::PendingResponse => {
                             if let Message::Response(result, metadata) = &mut msg {
-                                let require_weight_at_most = 1_000_000_000;
-
-                                let dest =
-                                    MultiLocationBuilder::new_parachain(metadata.dest_para_id)
-                                        .with_parents(1)
-                                        .build();
-
-                                // NOTE: do we want to allow the user to control what asset we pay for in response?
-                                // I think that should be configured by the channel implementation, not the user
-                                let _payment_asset = match metadata.fees.asset {
-                                    Some(id) => {
-                                        let id: AssetIdOf<T> =
-                                            Decode::decode(&mut &id.encode()[..])
-                                                .map_err(|_| DispatchError::CannotLookup)?;
-                                        T::AssetRegistry::reverse_ref(id)
-                                            .map_err(|_| DispatchError::CannotLookup)?
+                                let dest = MultiLocationBuilder::new_parachain(
+                                    metadata.next_hop(T::ParachainId::get()),
+                                )
+                                .with_parents(1)
+                                .build();
+
+                                // The response is paid for from the notification budget of the user, in the asset they paid the request in
+                                let payment_asset = match Pallet::<T>::payment_asset(&metadata.fees)
+                                {
+                                    Ok(asset) => asset,
+                                    Err(e) => {
+                                        log::error!(target: "xbi", "Failed to look up the payment asset of response: {:?}", e);
+                                        Pallet::<T>::push_follow_up(
+                                            &mut queue,
+                                            (
+                                                msg,
+                                                QueueSignal::ProtocolError(Status::DispatchFailed),
+                                            ),
+                                        );
+                                        continue;
+                                    }
+                                };
+
+                                let call = match Pallet::<T>::provide((
+                                    result.clone(),
+                                    metadata.clone(),
+                                )) {
+                                    Ok(call) => call,
+                                    Err(e) => {
+                                        log::error!(target: "xbi", "Failed to provide call for response: {:?}", e);
+                                        Pallet::<T>::push_follow_up(
+                                            &mut queue,
+                                            (
+                                                msg,
+                                                QueueSignal::ProtocolError(Status::DispatchFailed),
+                                            ),
+                                        );
+                                        continue;
                                     }
-                                    None => MultiLocationBuilder::new_native().build(),
                                 };
 
                                 let xbi_format_msg = XcmBuilder::<()>::default()
-                                    // TODO: reenable based on above conversations
-                                    // .with_withdraw_concrete_asset(payment_asset.clone(), 1_000_000_000_000) // TODO: take amount from new costs field
-                                    // .with_buy_execution(payment_asset, 1_000_000_000, None) // TODO: same as above
+                                    .with_withdraw_concrete_asset(
+                                        payment_asset.clone(),
+                                        metadata.fees.notification_cost_limit,
+                                    )
+                                    .with_buy_execution(
+                                        payment_asset,
+                                        metadata.fees.notification_cost_limit,
+                                        None,
+                                    )
                                     .with_transact(
                                         Some(OriginKind::SovereignAccount),
-                                        Some(require_weight_at_most),
-                                        Pallet::<T>::provide((result.clone(), metadata.clone())),
+                                        Some(T::ResponseWeightLimit::get()),
+                                        call,
                                     )
+                                    .with_refund_surplus()
+                                    .with_deposit_asset(Pallet::<T>::surplus_beneficiary(), 1)
                                     .build();
 
                                 T::Xcm::send_xcm(dest, xbi_format_msg)
                                     .map(|_| {
                                         log::trace!(target: "xbi", "Successfully sent xcm message");
+                                        Pallet::<T>::clear_retries(&metadata.get_id());
                                         Pallet::<T>::emit_sent(msg.clone())
                                     })
                                     .unwrap_or_else(|e| {
                                         log::error!(target: "xbi", "Failed to send xcm request: {:?}", e);
-                                        queue.push((msg, QueueSignal::ProtocolError(Status::DispatchFailed)));
+                                        Pallet::<T>::emit_send_failed(msg.get_metadata(), &e.into());
+                                        Pallet::<T>::push_follow_up(&mut queue, (msg, QueueSignal::ProtocolError(Status::DispatchFailed)));
                                     });
                             }
                         }
                         QueueSignal::PendingResult => {
                             if let Message::Response(res, meta) = msg {
-                                let o: T::AccountId = xs_channel::xbi_origin(&meta)?;
-                                <() as RefundForMessage<
-                                    T::AccountId,
-                                    T::Currency,
-                                    T::Assets,
-                                    T::ReserveBalanceCustodian,
-                                >>::refund(&o, &meta.fees)?;
-
-                                Pallet::<T>::write((meta.get_id(), res))?;
+                                // A response may arrive after the request was already resolved, e.g by a timeout
+                                match Pallet::<T>::matched_request(&meta) {
+                                    Ok(request) => {
+                                        let fees =
+                                            Pallet::<T>::refundable_fees(&request.metadata, &meta);
+
+                                        if let Err(e) = Pallet::<T>::write((meta.get_id(), res)) {
+                                            log::error!(target: "xbi", "Failed to store result of request {:?}: {:?}", meta.get_id(), e);
+                                            continue;
+                                        }
+                                        if let Err(e) = xs_channel::xbi_origin::<T::AccountId>(
+                                            &request.metadata,
+                                        )
+                                        .and_then(|o| {
+                                            <() as RefundForMessage<
+                                                T::AccountId,
+                                                T::Currency,
+                                                T::Assets,
+                                                T::ReserveBalanceCustodian,
+                                            >>::refund(
+                                                &o, &fees
+                                            )
+                                        }) {
+                                            log::error!(target: "xbi", "Failed to refund fees: {:?}", e);
+                                            Pallet::<T>::emit_refund_failed(&meta, &e);
+                                        }
+                                    }
+                                    Err(e) => {
+                                        log::warn!(target: "xbi", "Discarding response for request {:?}: {:?}", meta.get_id(), e);
+                                    }
+                                }
                             }
                         }
                         QueueSignal::ProtocolError(status) => {
-                            // TODO: emit an error
-
-                            if let Message::Request(req) = msg {
-                                let result = XbiResult {
-                                    status,
-                                    output: vec![],
-                                    witness: vec![],
-                                };
-                                Pallet::<T>::write((req.metadata.get_id(), result))?;
+                            let id = msg.get_metadata().get_id();
+                            if let Err(e) = Pallet::<T>::retry_or_dead_letter(msg, status) {
+                                log::error!(target: "xbi", "Failed to retry or dead-letter message {:?}: {:?}", id, e);
                             }
                         }
                     }
@@ -542,6 +1034,125 @@ pub mod pallet {
                 pays_fee: Pays::Yes,
             })
         }
+
+        /// Record the XBI version a peer parachain supports. Messages to the peer are converted to this version,
+        /// or rejected if they cannot be represented in it. Clearing the record assumes the latest version.
+        #[pallet::weight(T::DbWeight::get().writes(1))]
+        pub fn set_peer_version(
+            origin: OriginFor<T>,
+            para_id: u32,
+            version: Option<XbiVersion>,
+        ) -> DispatchResult {
+            ensure_root(origin)?;
+
+            if let Some(version) = version {
+                ensure!(
+                    version > 0 && version <= XBI_VERSION,
+                    Error::<T>::UnsupportedXbiVersion
+                );
+            }
+
+            <PeerVersions<T>>::set(para_id, version);
+            Self::deposit_event(Event::PeerVersionUpdated { para_id, version });
+            Ok(())
+        }
+
+        /// Record where the portal is on a peer parachain, clearing the record assumes `DefaultPortalIndex`.
+        ///
+        /// This can be called by governance, or by the peer itself to announce where its portal is, see `announce_portal`.
+        #[pallet::weight(T::DbWeight::get().writes(1))]
+        pub fn set_peer_portal(
+            origin: OriginFor<T>,
+            para_id: u32,
+            index: Option<PortalIndex>,
+        ) -> DispatchResult {
+            match T::PeerOrigin::try_origin(origin) {
+                Ok(peer) => ensure!(peer == para_id, DispatchError::BadOrigin),
+                Err(origin) => ensure_root(origin)?,
+            }
+
+            <PeerPortals<T>>::set(para_id, index);
+            Self::deposit_event(Event::PeerPortalUpdated { para_id, index });
+            Ok(())
+        }
+
+        /// Announce where this portal is to a peer parachain, which records it with `set_peer_portal`.
+        ///
+        /// The peer is expected to have the same calls as this portal, and is reached at its record in `PeerPortals`.
+        #[pallet::weight(T::DbWeight::get().reads(2))]
+        pub fn announce_portal(origin: OriginFor<T>, para_id: u32) -> DispatchResult {
+            ensure_root(origin)?;
+
+            let index = Self::own_portal_index();
+            let call = Call::<T>::set_peer_portal {
+                para_id: T::ParachainId::get(),
+                index: Some(index),
+            };
+            let require_weight_at_most = call.get_dispatch_info().weight;
+
+            // The peer dispatches the call on its own portal, which has the same calls as ours
+            let mut encoded_call = vec![Self::peer_portal(para_id).pallet];
+            call.encode_to(&mut encoded_call);
+
+            let dest = MultiLocationBuilder::new_parachain(para_id)
+                .with_parents(1)
+                .build();
+            let announcement = XcmBuilder::<()>::default()
+                .with_transact(
+                    Some(OriginKind::SovereignAccount),
+                    Some(require_weight_at_most),
+                    encoded_call,
+                )
+                .build();
+
+            T::Xcm::send_xcm(dest, announcement).map_err(|e| {
+                log::error!(target: "xbi", "Failed to announce portal to {:?}: {:?}", para_id, e);
+                Error::<T>::PortalAnnouncementFailed
+            })?;
+
+            Self::deposit_event(Event::PortalAnnounced { para_id, index });
+            Ok(())
+        }
+
+        /// Queue a dead-lettered message to be transported again, with a fresh set of retries.
+        ///
+        /// A replayed request is tracked as in-flight again, replacing the failed result it was resolved with.
+        #[pallet::weight(T::DbWeight::get().reads_writes(5, 6))]
+        pub fn replay_dead_letter(origin: OriginFor<T>, hash: T::Hash) -> DispatchResult {
+            ensure_root(origin)?;
+
+            let (msg, _status) =
+                <DeadLetters<T>>::get(hash).ok_or(Error::<T>::DeadLetterNotFound)?;
+            let signal = match &msg {
+                Message::Request(_) => QueueSignal::PendingRequest,
+                Message::Response(..) => QueueSignal::PendingResponse,
+            };
+            <Queue<Pallet<T>>>::default()
+                .push((msg.clone(), signal))
+                .map_err(|_| Error::<T>::QueueFull)?;
+
+            if let Message::Request(format) = msg {
+                <XbiResponses<T>>::remove(hash);
+                <XbiRequests<T>>::insert(hash, format);
+            }
+            <DeadLetters<T>>::remove(hash);
+            Self::deposit_event(Event::DeadLetterReplayed { hash });
+            Ok(())
+        }
+
+        /// Allow or disallow relaying messages from and to a peer parachain, see `RelayPeers`.
+        #[pallet::weight(T::DbWeight::get().writes(1))]
+        pub fn set_relay_peer(origin: OriginFor<T>, para_id: u32, allowed: bool) -> DispatchResult {
+            ensure_root(origin)?;
+
+            if allowed {
+                <RelayPeers<T>>::insert(para_id, ());
+            } else {
+                <RelayPeers<T>>::remove(para_id);
+            }
+            Self::deposit_event(Event::RelayPeerUpdated { para_id, allowed });
+            Ok(())
+        }
     }
 
     #[pallet::inherent]
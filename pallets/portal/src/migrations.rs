@@ -0,0 +1,59 @@
+//! Migrations of the storage of the portal, these are run by the runtime on upgrade.
+use crate::{
+    impls::account_from_account32, Config, MessageNonces, Pallet, QueueIndex, QueueItems,
+    QueueLength, XbiRequests,
+};
+use frame_support::{
+    storage::migration::take_storage_value,
+    traits::{Get, GetStorageVersion, OnRuntimeUpgrade, PalletInfoAccess, StorageVersion},
+    weights::Weight,
+};
+use sp_std::marker::PhantomData;
+
+/// Replaces the global `MessageNonce` with the nonce of each sender in `MessageNonces`.
+///
+/// The ids of the requests still in `XbiRequests` were derived from the global nonce, so their senders continue from it rather
+/// than from zero, which could derive those ids again.
+///
+/// The messages that are already queued are also indexed in `QueueIndex` by their id, and counted in `QueueLength`.
+pub struct MigrateToSenderNonces<T>(PhantomData<T>);
+
+impl<T: Config> OnRuntimeUpgrade for MigrateToSenderNonces<T> {
+    fn on_runtime_upgrade() -> Weight {
+        if Pallet::<T>::on_chain_storage_version() >= 1 {
+            log::info!(target: "xbi", "Nonces are already kept per sender, skipping the migration");
+            return T::DbWeight::get().reads(1);
+        }
+
+        let nonce: u32 = take_storage_value(<Pallet<T>>::name().as_bytes(), b"MessageNonce", &[])
+            .unwrap_or_default();
+        let mut reads: Weight = 2;
+        let mut writes: Weight = 2;
+        for request in XbiRequests::<T>::iter_values() {
+            reads = reads.saturating_add(1);
+            if let Some(who) = request
+                .metadata
+                .get_origin()
+                .and_then(|origin| account_from_account32::<T>(origin).ok())
+            {
+                MessageNonces::<T>::insert(who, nonce);
+                writes = writes.saturating_add(1);
+            }
+        }
+
+        let senders = writes - 2;
+        let mut queued: u16 = 0;
+        for (position, (msg, _)) in QueueItems::<T>::iter() {
+            QueueIndex::<T>::insert(msg.get_metadata().get_id(), position);
+            queued = queued.saturating_add(1);
+            reads = reads.saturating_add(1);
+            writes = writes.saturating_add(1);
+        }
+        QueueLength::<T>::put(queued);
+        writes = writes.saturating_add(1);
+
+        StorageVersion::new(1).put::<Pallet<T>>();
+        log::info!(target: "xbi", "Migrated the global nonce {} to the senders of {} requests", nonce, senders);
+        T::DbWeight::get().reads_writes(reads, writes)
+    }
+}
@@ -1,17 +1,38 @@
 use crate as pallet_xbi_portal;
+use crate::{
+    origins::EnsureSibling,
+    primitives::{
+        custom_vm::{CustomVm, CustomVmResult},
+        defi::{DeFi, DeFiResult},
+        xbi_callback::XBICallback,
+    },
+    PortalIndex, XbiRequests,
+};
+use codec::Encode;
 use frame_support::{
-    parameter_types,
-    traits::{ConstU16, ConstU64},
-    weights::IdentityFee,
+    assert_ok, parameter_types,
+    traits::{ConstU16, ConstU64, Contains, Currency},
+    weights::{constants::RocksDbWeight, GetDispatchInfo, IdentityFee, Weight},
 };
 use frame_system as system;
-use frame_system::EnsureRoot;
+use frame_system::{ensure_signed, EnsureRoot};
 use sp_core::H256;
 use sp_runtime::{
     testing::Header,
     traits::ConstU32,
+    traits::Dispatchable,
     traits::{BlakeTwo256, IdentityLookup},
+    AccountId32, DispatchError, DispatchErrorWithPostInfo,
+};
+use std::{borrow::Borrow, cell::RefCell, collections::BTreeMap};
+use xp_channel::traits::{HandlerInfo, XbiInstructionRoute};
+use xp_channel::Message;
+use xp_format::{Fees, Timestamp, XbiFormat, XbiMetadata};
+use xp_xcm::{
+    frame_traits::XcmConvert,
+    xcm::prelude::{MultiLocation, Parachain, SendError, SendResult, SendXcm, Xcm, X1},
 };
+use xs_channel::Sender as SenderExt;
 
 pub type Balance = u128;
 pub type AssetId = u32;
@@ -20,6 +41,43 @@ pub type AccountId = u64;
 type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
 type Block = frame_system::mocking::MockBlock<Test>;
 
+/// A pallet that resumes once the promises of its requests are resolved, recording the results they were resolved with
+#[frame_support::pallet]
+pub mod promised {
+    use frame_support::pallet_prelude::*;
+    use frame_system::pallet_prelude::*;
+    use sp_std::prelude::*;
+    use xp_format::XbiResult;
+
+    #[pallet::config]
+    pub trait Config: frame_system::Config {}
+
+    #[pallet::pallet]
+    #[pallet::without_storage_info]
+    pub struct Pallet<T>(_);
+
+    #[pallet::storage]
+    pub type Resolved<T: Config> =
+        StorageMap<_, Blake2_128Concat, T::AccountId, Vec<XbiResult>, ValueQuery>;
+
+    #[pallet::call]
+    impl<T: Config> Pallet<T> {
+        #[pallet::weight(1)]
+        pub fn then(origin: OriginFor<T>, result: XbiResult) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+            Resolved::<T>::append(who, result);
+            Ok(())
+        }
+
+        #[pallet::weight(1)]
+        pub fn join(origin: OriginFor<T>, results: Vec<XbiResult>) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+            Resolved::<T>::mutate(who, |resolved| resolved.extend(results));
+            Ok(())
+        }
+    }
+}
+
 // Configure a mock runtime to test the pallet.
 frame_support::construct_runtime!(
     pub enum Test where
@@ -31,9 +89,12 @@ frame_support::construct_runtime!(
         XbiPortal: pallet_xbi_portal,
         Assets: pallet_assets,
         Balances: pallet_balances,
+        Promised: promised,
     }
 );
 
+impl promised::Config for Test {}
+
 impl system::Config for Test {
     type AccountData = pallet_balances::AccountData<Balance>;
     type AccountId = AccountId;
@@ -43,7 +104,7 @@ impl system::Config for Test {
     type BlockNumber = u64;
     type BlockWeights = ();
     type Call = Call;
-    type DbWeight = ();
+    type DbWeight = RocksDbWeight;
     type Event = Event;
     type Hash = H256;
     type Hashing = BlakeTwo256;
@@ -100,31 +161,355 @@ impl evm_primitives::traits::Evm<<Test as frame_system::Config>::Origin> for Non
     }
 }
 
+pub const DEFI_WEIGHT: Weight = 10_000;
+
+#[derive(Clone, Default)]
+pub struct Pool {
+    pub reserve_a: Balance,
+    pub reserve_b: Balance,
+    pub total_liquidity: Balance,
+    pub liquidity: BTreeMap<AccountId, Balance>,
+}
+
+impl Pool {
+    fn flipped(self) -> Self {
+        Pool {
+            reserve_a: self.reserve_b,
+            reserve_b: self.reserve_a,
+            ..self
+        }
+    }
+}
+
+thread_local! {
+    pub static POOLS: RefCell<BTreeMap<(AssetId, AssetId), Pool>> = RefCell::new(Default::default());
+}
+
+/// A reference DeFi implementation backed by constant product pools.
+///
+/// Only the reserves and liquidity of each pool are tracked, no assets are moved.
+pub struct ConstantProductPool;
+
+impl ConstantProductPool {
+    /// Update the pool for a pair, seen in the order the assets were given. The pool is only updated if `f` succeeds.
+    fn mutate_pool<R>(
+        asset_a: AssetId,
+        asset_b: AssetId,
+        f: impl FnOnce(&mut Pool) -> Result<R, DispatchError>,
+    ) -> Result<R, DispatchError> {
+        POOLS.with(|pools| {
+            let mut pools = pools.borrow_mut();
+            let flip = asset_a > asset_b;
+            let key = if flip {
+                (asset_b, asset_a)
+            } else {
+                (asset_a, asset_b)
+            };
+
+            let stored = pools.get(&key).cloned().unwrap_or_default();
+            let mut pool = if flip { stored.flipped() } else { stored };
+            let result = f(&mut pool)?;
+            pools.insert(key, if flip { pool.flipped() } else { pool });
+            Ok(result)
+        })
+    }
+
+    fn output<O: Encode>(output: O) -> DeFiResult {
+        Ok(HandlerInfo {
+            output: output.encode(),
+            weight: DEFI_WEIGHT,
+        })
+    }
+}
+
+impl DeFi<Test> for ConstantProductPool {
+    fn add_liquidity(
+        origin: Origin,
+        asset_a: AssetId,
+        asset_b: AssetId,
+        amount_a: Balance,
+        amount_b_max_limit: Balance,
+    ) -> DeFiResult {
+        let who = ensure_signed(origin)?;
+        let added = Self::mutate_pool(asset_a, asset_b, |pool| {
+            let (amount_b, minted) = if pool.total_liquidity == 0 {
+                (amount_b_max_limit, amount_a)
+            } else {
+                (
+                    // Round in favour of the pool
+                    (amount_a * pool.reserve_b + pool.reserve_a - 1) / pool.reserve_a,
+                    amount_a * pool.total_liquidity / pool.reserve_a,
+                )
+            };
+            if amount_a == 0 || amount_b == 0 {
+                return Err(DispatchError::Other(
+                    "Liquidity must be provided in both assets",
+                ));
+            }
+            if amount_b > amount_b_max_limit {
+                return Err(DispatchError::Other(
+                    "Liquidity exceeds the limit for asset B",
+                ));
+            }
+
+            pool.reserve_a += amount_a;
+            pool.reserve_b += amount_b;
+            pool.total_liquidity += minted;
+            *pool.liquidity.entry(who).or_default() += minted;
+            Ok((amount_b, minted))
+        })?;
+        Self::output(added)
+    }
+
+    fn remove_liquidity(
+        origin: Origin,
+        asset_a: AssetId,
+        asset_b: AssetId,
+        liquidity_amount: Balance,
+    ) -> DeFiResult {
+        let who = ensure_signed(origin)?;
+        let removed = Self::mutate_pool(asset_a, asset_b, |pool| {
+            let held = pool.liquidity.get(&who).copied().unwrap_or_default();
+            if liquidity_amount == 0 || liquidity_amount > held {
+                return Err(DispatchError::Other("Insufficient liquidity"));
+            }
+
+            let amount_a = liquidity_amount * pool.reserve_a / pool.total_liquidity;
+            let amount_b = liquidity_amount * pool.reserve_b / pool.total_liquidity;
+            pool.reserve_a -= amount_a;
+            pool.reserve_b -= amount_b;
+            pool.total_liquidity -= liquidity_amount;
+            pool.liquidity.insert(who, held - liquidity_amount);
+            Ok((amount_a, amount_b))
+        })?;
+        Self::output(removed)
+    }
+
+    fn swap(
+        origin: Origin,
+        asset_out: AssetId,
+        asset_in: AssetId,
+        amount: Balance,
+        max_limit: Balance,
+        _discount: bool,
+    ) -> DeFiResult {
+        ensure_signed(origin)?;
+        let paid = Self::mutate_pool(asset_in, asset_out, |pool| {
+            if amount == 0 || amount >= pool.reserve_b {
+                return Err(DispatchError::Other("Insufficient liquidity"));
+            }
+
+            // Keep reserve_a * reserve_b constant, rounding in favour of the pool
+            let remaining = pool.reserve_b - amount;
+            let paid = (pool.reserve_a * amount + remaining - 1) / remaining;
+            if paid > max_limit {
+                return Err(DispatchError::Other("Swap exceeds the limit"));
+            }
+
+            pool.reserve_a += paid;
+            pool.reserve_b = remaining;
+            Ok(paid)
+        })?;
+        Self::output(paid)
+    }
+
+    fn get_price(
+        _origin: Origin,
+        asset_a: AssetId,
+        asset_b: AssetId,
+        amount: Balance,
+    ) -> DeFiResult {
+        let quote = Self::mutate_pool(asset_a, asset_b, |pool| {
+            if pool.total_liquidity == 0 {
+                return Err(DispatchError::Other("Insufficient liquidity"));
+            }
+            Ok(pool.reserve_b * amount / (pool.reserve_a + amount))
+        })?;
+        Self::output(quote)
+    }
+}
+
+/// Only remarks and transfers can be dispatched by `CallNative` in the mock
+pub struct RemarksAndTransfers;
+impl Contains<Call> for RemarksAndTransfers {
+    fn contains(call: &Call) -> bool {
+        matches!(
+            call,
+            Call::System(frame_system::Call::remark_with_event { .. })
+                | Call::Balances(pallet_balances::Call::transfer { .. })
+        )
+    }
+}
+
+/// A custom VM which echoes the input back, using the limit as the weight
+pub struct EchoVm;
+impl CustomVm<Test> for EchoVm {
+    fn call(
+        _origin: Origin,
+        _caller: sp_runtime::AccountId32,
+        _dest: sp_runtime::AccountId32,
+        _value: Balance,
+        input: Vec<u8>,
+        limit: u64,
+        _additional_params: Vec<u8>,
+    ) -> CustomVmResult {
+        Ok(HandlerInfo {
+            output: input,
+            weight: limit,
+        })
+    }
+}
+
+/// The identifier of the custom instruction handled by `Ping`
+pub const PING: u8 = 42;
+
+/// A handler for a custom instruction, which echoes the parameters back
+pub struct Ping;
+impl XbiInstructionRoute<Origin> for Ping {
+    fn accepts(identifier: u8) -> bool {
+        identifier == PING
+    }
+
+    fn handle(
+        _origin: &Origin,
+        xbi: &mut xp_format::XbiFormat,
+    ) -> Result<HandlerInfo<Weight>, DispatchErrorWithPostInfo> {
+        match xbi.instr {
+            xp_format::XbiInstruction::Unknown { ref params, .. } => Ok(HandlerInfo {
+                output: params.clone(),
+                weight: 1,
+            }),
+            _ => Err(DispatchError::Other("Ping only handles unknown instructions").into()),
+        }
+    }
+}
+
+/// A callback that follows up on results by remarking them on behalf of the origin of the request, if the remark fits in the weight limit
+pub struct RemarkCallback;
+impl XBICallback<Test> for RemarkCallback {
+    fn callback(
+        metadata: &xp_format::XbiMetadata,
+        result: &xp_format::XbiResult,
+        weight_limit: Weight,
+    ) -> Weight {
+        let call = Call::System(frame_system::Call::remark_with_event {
+            remark: result.encode(),
+        });
+        let weight = call.get_dispatch_info().weight;
+        if weight > weight_limit {
+            return 0;
+        }
+
+        match xs_channel::xbi_origin::<AccountId>(metadata) {
+            Ok(who) => call
+                .dispatch(Origin::signed(who))
+                .map_or_else(|e| e.post_info, |info| info)
+                .actual_weight
+                .unwrap_or(weight),
+            Err(_) => 0,
+        }
+    }
+}
+
+/// The sovereign account of a sibling parachain is its para id offset by `SIBLING_ACCOUNT_OFFSET`
+pub const SIBLING_ACCOUNT_OFFSET: AccountId = 1000;
+
+pub struct SiblingToAccountId;
+
+impl XcmConvert<MultiLocation, AccountId> for SiblingToAccountId {
+    fn convert_ref(location: impl Borrow<MultiLocation>) -> Result<AccountId, ()> {
+        match location.borrow() {
+            MultiLocation {
+                parents: 1,
+                interior: X1(Parachain(id)),
+            } => Ok(SIBLING_ACCOUNT_OFFSET + *id as AccountId),
+            _ => Err(()),
+        }
+    }
+
+    fn reverse_ref(who: impl Borrow<AccountId>) -> Result<MultiLocation, ()> {
+        match who.borrow().checked_sub(SIBLING_ACCOUNT_OFFSET) {
+            Some(id) if id <= u32::MAX as AccountId => {
+                Ok(MultiLocation::new(1, X1(Parachain(id as u32))))
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+thread_local! {
+    pub static SENT_XCM: RefCell<Vec<(MultiLocation, Xcm<()>)>> = RefCell::new(vec![]);
+}
+
+/// Records the XCM messages that are sent in `SENT_XCM`, they are only delivered if `DeliverXcm` is set
+pub struct RecordingXcm;
+impl SendXcm for RecordingXcm {
+    fn send_xcm(destination: impl Into<MultiLocation>, message: Xcm<()>) -> SendResult {
+        let destination = destination.into();
+        if !DeliverXcm::get() {
+            return Err(SendError::CannotReachDestination(destination, message));
+        }
+        SENT_XCM.with(|sent| sent.borrow_mut().push((destination, message)));
+        Ok(())
+    }
+}
+
+/// The XCM messages that were delivered, in the order they were sent
+pub fn sent_xcm() -> Vec<(MultiLocation, Xcm<()>)> {
+    SENT_XCM.with(|sent| sent.borrow().clone())
+}
+
 parameter_types! {
     pub ReserveBalanceCustodian: AccountId = 64;
+    pub const DefaultPortalIndex: PortalIndex = PortalIndex { pallet: 200, call: 1 };
+    pub static CheckOutLimit: u32 = 100;
+    pub static QueueWeightLimit: Weight = 10_000_000_000;
+    pub static MaxRetries: u8 = 0;
+    pub static AllowLocalReceive: bool = false;
+    pub static MaxExecutedRetention: u32 = 100;
+    pub static ExecutedPruneLimit: u32 = 100;
+    pub static TimeoutChecksLimit: u32 = 3000;
+    pub static DeliverXcm: bool = false;
+    pub static ResponseWeightLimit: Weight = 1_000_000_000;
+    pub static ForwardingFee: u128 = 0;
 }
 
 impl pallet_xbi_portal::Config for Test {
     type Call = Call;
     type Event = Event;
     type XcmSovereignOrigin = XcmSovereignOrigin;
-    type Xcm = ();
+    type Xcm = RecordingXcm;
     type Contracts = ();
     type Evm = NonsenseNoopEvm;
     type Currency = Balances;
     type AssetRegistry = ();
-    type Callback = ();
+    type Callback = RemarkCallback;
     type CheckInLimit = ConstU32<100>;
     type CheckInterval = ConstU64<3>;
-    type CheckOutLimit = ConstU32<100>;
+    type CheckOutLimit = CheckOutLimit;
+    type QueueWeightLimit = QueueWeightLimit;
+    type MaxRetries = MaxRetries;
+    type RetryBackoff = ConstU64<3>;
     type ExpectedBlockTimeMs = ConstU32<6000>;
     type ParachainId = ConstU32<3333>;
-    type TimeoutChecksLimit = ConstU32<3000>;
+    type DefaultPortalIndex = DefaultPortalIndex;
+    type PeerOrigin = EnsureSibling<Test, SiblingToAccountId>;
+    type AllowLocalReceive = AllowLocalReceive;
+    type MaxExecutedRetention = MaxExecutedRetention;
+    type ExecutedPruneLimit = ExecutedPruneLimit;
+    type ForwardingFee = ForwardingFee;
+    type LocationToAccountId = SiblingToAccountId;
+    type TimeoutChecksLimit = TimeoutChecksLimit;
     type Assets = Assets;
     type FeeConversion = IdentityFee<Balance>;
-    type DeFi = ();
+    type DeFi = ConstantProductPool;
+    type CustomVm = EchoVm;
+    type NativeCallFilter = RemarksAndTransfers;
+    type InstructionHandlers = (Ping, pallet_xbi_portal::handlers::DefaultHandlers<Test>);
     type ReserveBalanceCustodian = ReserveBalanceCustodian;
     type NotificationWeight = ConstU64<1>;
+    type WeightInfo = ();
+    type ResponseWeightLimit = ResponseWeightLimit;
 }
 
 parameter_types! {
@@ -174,5 +559,69 @@ impl pallet_assets::Config for Test {
 }
 
 pub fn new_test_ext() -> sp_io::TestExternalities {
+    POOLS.with(|pools| pools.borrow_mut().clear());
+    SENT_XCM.with(|sent| sent.borrow_mut().clear());
+    DeliverXcm::set(false);
     sp_io::TestExternalities::default()
 }
+
+/// Externalities for the benchmarks, which deliver the XCM messages that they send
+#[cfg(feature = "runtime-benchmarks")]
+pub fn new_bench_ext() -> sp_io::TestExternalities {
+    let ext = new_test_ext();
+    DeliverXcm::set(true);
+    ext
+}
+
+/// The XBI origin of a local account, as the portal enriches the requests of the account with
+pub fn origin_of(account: AccountId) -> AccountId32 {
+    crate::impls::account32_from_account::<Test>(&account).unwrap()
+}
+
+/// A request from `src` to `dest` with the given nonce and origin, without any timeouts or fees
+pub fn request(src: u32, dest: u32, nonce: u32, origin: Option<AccountId32>) -> XbiFormat {
+    XbiFormat {
+        metadata: XbiMetadata::new(
+            src,
+            dest,
+            Default::default(),
+            Default::default(),
+            origin,
+            nonce,
+            None,
+        ),
+        ..Default::default()
+    }
+}
+
+/// A request from this parachain to `dest` without an origin
+pub fn request_to(dest_para_id: u32, nonce: u32) -> Message {
+    Message::Request(request(3333, dest_para_id, nonce, None))
+}
+
+/// A request from this parachain to parachain 1, tracked as in-flight since block 1
+pub fn track_request(nonce: u32) -> XbiMetadata {
+    let mut format = request(3333, 1, nonce, Some(AccountId32::new([1u8; 32])));
+    format.metadata.progress(Timestamp::Submitted(1));
+    XbiRequests::<Test>::insert(format.metadata.get_id(), format.clone());
+    format.metadata
+}
+
+/// A request from `user` to `dest`, sent and tracked as in-flight, and the response the destination would send for it
+pub fn charged_request(user: AccountId, dest: u32) -> (XbiMetadata, XbiMetadata) {
+    let _ = Balances::deposit_creating(&user, 1_000);
+    let mut format = request(3333, dest, 0, Some(origin_of(user)));
+    format.metadata.fees = Fees::new(None, Some(100), Some(50));
+
+    // Sending the request charges the user for it
+    DeliverXcm::set(true);
+    assert_ok!(crate::pallet::Sender::<Test>::send(Message::Request(
+        format.clone()
+    )));
+    let metadata = format.metadata.clone();
+    XbiRequests::<Test>::insert(metadata.get_id(), format);
+
+    let mut response = metadata.clone();
+    xs_channel::receiver::frame::invert_destination_from_message(&mut response);
+    (metadata, response)
+}
@@ -0,0 +1,42 @@
+//! The origins of peer parachains.
+//!
+//! Peers dispatch calls on the portal over XCM with their sovereign account, these origins resolve that account back to the para id of the peer.
+use crate::Config;
+use frame_support::traits::EnsureOrigin;
+use frame_system::RawOrigin;
+use sp_std::marker::PhantomData;
+use xp_xcm::{frame_traits::XcmConvert, xcm::prelude::*};
+
+/// Ensures the origin is signed by the sovereign account of a sibling parachain, resolving to its para id.
+///
+/// `LocationToAccountId` is the converter the runtime uses for XCM origins, e.g. `SiblingParachainConvertsVia<Sibling, AccountId>`,
+/// it must be able to reverse a sovereign account to the location of its parachain.
+pub struct EnsureSibling<T, LocationToAccountId>(PhantomData<(T, LocationToAccountId)>);
+
+impl<T, LocationToAccountId> EnsureOrigin<T::Origin> for EnsureSibling<T, LocationToAccountId>
+where
+    T: Config,
+    LocationToAccountId: XcmConvert<MultiLocation, T::AccountId>,
+{
+    type Success = u32;
+
+    fn try_origin(o: T::Origin) -> Result<Self::Success, T::Origin> {
+        o.into().and_then(|o| match o {
+            RawOrigin::Signed(who) => match LocationToAccountId::reverse_ref(&who) {
+                Ok(MultiLocation {
+                    parents: 1,
+                    interior: X1(Parachain(para_id)),
+                }) => Ok(para_id),
+                _ => Err(T::Origin::from(RawOrigin::Signed(who))),
+            },
+            o => Err(T::Origin::from(o)),
+        })
+    }
+
+    #[cfg(feature = "runtime-benchmarks")]
+    fn successful_origin() -> T::Origin {
+        LocationToAccountId::convert(MultiLocation::new(1, X1(Parachain(1))))
+            .map(|who| RawOrigin::Signed(who).into())
+            .unwrap_or_else(|_| RawOrigin::Root.into())
+    }
+}
@@ -0,0 +1,38 @@
+use crate::{
+    xbi_abi::{AccountId32, Data, Gas},
+    BalanceOf,
+};
+use frame_support::{dispatch::DispatchErrorWithPostInfo, weights::Weight};
+
+use frame_system::pallet_prelude::OriginFor;
+use xp_channel::traits::HandlerInfo;
+
+/// The outcome of a call to a custom VM, the output is returned to the sender of the instruction
+pub type CustomVmResult = Result<HandlerInfo<Weight>, DispatchErrorWithPostInfo>;
+
+/// Provides access to any VM other than EVM and WASM contracts, used to handle `CallCustom` instructions.
+pub trait CustomVm<T: frame_system::Config + crate::pallet::Config> {
+    fn call(
+        origin: OriginFor<T>,
+        caller: AccountId32,
+        dest: AccountId32,
+        value: BalanceOf<T>,
+        input: Data,
+        limit: Gas,
+        additional_params: Data,
+    ) -> CustomVmResult;
+}
+
+impl<T: frame_system::Config + crate::pallet::Config> CustomVm<T> for () {
+    fn call(
+        _origin: OriginFor<T>,
+        _caller: AccountId32,
+        _dest: AccountId32,
+        _value: BalanceOf<T>,
+        _input: Data,
+        _limit: Gas,
+        _additional_params: Data,
+    ) -> CustomVmResult {
+        Err(crate::Error::<T>::CallCustomUnsupported.into())
+    }
+}
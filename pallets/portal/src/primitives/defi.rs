@@ -1,9 +1,20 @@
 use crate::{xbi_abi::AssetId, BalanceOf};
-use frame_support::dispatch::DispatchResultWithPostInfo;
+use frame_support::{dispatch::DispatchErrorWithPostInfo, weights::Weight};
 
 use frame_system::pallet_prelude::OriginFor;
 use sp_std::marker::PhantomData;
+use xp_channel::traits::HandlerInfo;
 
+/// The outcome of a DeFi operation, the output is returned to the sender of the instruction
+pub type DeFiResult = Result<HandlerInfo<Weight>, DispatchErrorWithPostInfo>;
+
+/// Provides the DeFi instructions of XBI.
+///
+/// Implementations should return the SCALE encoded output of each operation:
+/// - `add_liquidity`: the amount of asset B taken and the liquidity minted, `(BalanceOf<T>, BalanceOf<T>)`
+/// - `remove_liquidity`: the amounts of asset A and B returned, `(BalanceOf<T>, BalanceOf<T>)`
+/// - `swap`: the amount of `asset_in` paid for `amount` of `asset_out`, `BalanceOf<T>`
+/// - `get_price`: the amount of asset B quoted for `amount` of asset A, `BalanceOf<T>`
 pub trait DeFi<T: frame_system::Config + crate::pallet::Config> {
     fn add_liquidity(
         origin: OriginFor<T>,
@@ -11,14 +22,14 @@ pub trait DeFi<T: frame_system::Config + crate::pallet::Config> {
         asset_b: AssetId,
         amount_a: BalanceOf<T>,
         amount_b_max_limit: BalanceOf<T>,
-    ) -> DispatchResultWithPostInfo;
+    ) -> DeFiResult;
 
     fn remove_liquidity(
         origin: OriginFor<T>,
         asset_a: AssetId,
         asset_b: AssetId,
         liquidity_amount: BalanceOf<T>,
-    ) -> DispatchResultWithPostInfo;
+    ) -> DeFiResult;
 
     fn swap(
         origin: OriginFor<T>,
@@ -27,14 +38,14 @@ pub trait DeFi<T: frame_system::Config + crate::pallet::Config> {
         amount: BalanceOf<T>,
         max_limit: BalanceOf<T>,
         discount: bool,
-    ) -> DispatchResultWithPostInfo;
+    ) -> DeFiResult;
 
     fn get_price(
         origin: OriginFor<T>,
         asset_a: AssetId,
         asset_b: AssetId,
         amount: BalanceOf<T>,
-    ) -> DispatchResultWithPostInfo;
+    ) -> DeFiResult;
 }
 
 pub struct DeFiMock<T> {
@@ -48,8 +59,8 @@ impl<T: frame_system::Config + crate::pallet::Config> DeFi<T> for DeFiMock<T> {
         _asset_b: AssetId,
         _amount_a: BalanceOf<T>,
         _amount_b_max_limit: BalanceOf<T>,
-    ) -> DispatchResultWithPostInfo {
-        Ok(().into())
+    ) -> DeFiResult {
+        Ok(Default::default())
     }
 
     fn remove_liquidity(
@@ -57,8 +68,8 @@ impl<T: frame_system::Config + crate::pallet::Config> DeFi<T> for DeFiMock<T> {
         _asset_a: AssetId,
         _asset_b: AssetId,
         _liquidity_amount: BalanceOf<T>,
-    ) -> DispatchResultWithPostInfo {
-        Ok(().into())
+    ) -> DeFiResult {
+        Ok(Default::default())
     }
 
     fn swap(
@@ -68,8 +79,8 @@ impl<T: frame_system::Config + crate::pallet::Config> DeFi<T> for DeFiMock<T> {
         _amount: BalanceOf<T>,
         _max_limit: BalanceOf<T>,
         _discount: bool,
-    ) -> DispatchResultWithPostInfo {
-        Ok(().into())
+    ) -> DeFiResult {
+        Ok(Default::default())
     }
 
     fn get_price(
@@ -77,8 +88,8 @@ impl<T: frame_system::Config + crate::pallet::Config> DeFi<T> for DeFiMock<T> {
         _asset_a: AssetId,
         _asset_b: AssetId,
         _amount: BalanceOf<T>,
-    ) -> DispatchResultWithPostInfo {
-        Ok(().into())
+    ) -> DeFiResult {
+        Ok(Default::default())
     }
 }
 
@@ -93,7 +104,7 @@ impl<T: frame_system::Config + crate::pallet::Config> DeFi<T> for () {
         _asset_b: AssetId,
         _amount_a: BalanceOf<T>,
         _amount_b_max_limit: BalanceOf<T>,
-    ) -> DispatchResultWithPostInfo {
+    ) -> DeFiResult {
         Err(crate::Error::<T>::DefiUnsupported.into())
     }
 
@@ -102,7 +113,7 @@ impl<T: frame_system::Config + crate::pallet::Config> DeFi<T> for () {
         _asset_a: AssetId,
         _asset_b: AssetId,
         _liquidity_amount: BalanceOf<T>,
-    ) -> DispatchResultWithPostInfo {
+    ) -> DeFiResult {
         Err(crate::Error::<T>::DefiUnsupported.into())
     }
 
@@ -113,7 +124,7 @@ impl<T: frame_system::Config + crate::pallet::Config> DeFi<T> for () {
         _amount: BalanceOf<T>,
         _max_limit: BalanceOf<T>,
         _discount: bool,
-    ) -> DispatchResultWithPostInfo {
+    ) -> DeFiResult {
         Err(crate::Error::<T>::DefiUnsupported.into())
     }
 
@@ -122,7 +133,7 @@ impl<T: frame_system::Config + crate::pallet::Config> DeFi<T> for () {
         _asset_a: AssetId,
         _asset_b: AssetId,
         _amount: BalanceOf<T>,
-    ) -> DispatchResultWithPostInfo {
+    ) -> DeFiResult {
         Err(crate::Error::<T>::DefiUnsupported.into())
     }
 }
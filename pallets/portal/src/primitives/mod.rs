@@ -1,2 +1,3 @@
+pub mod custom_vm;
 pub mod defi;
 pub mod xbi_callback;
@@ -1,10 +1,15 @@
+use frame_support::weights::Weight;
 use sp_std::marker::PhantomData;
+use xp_format::{XbiMetadata, XbiResult};
 
+/// Notifies the pallet that initiated a request of its result, so that it can resume its own state machine.
 pub trait XBICallback<T: frame_system::Config + crate::pallet::Config> {
-    fn callback(
-        xbi_checkin: xp_format::XbiCheckIn<T::BlockNumber>,
-        xbi_checkout: xp_format::XbiCheckOut,
-    );
+    /// Called once the result of a request sent by this parachain is stored, whether it is the response from the destination,
+    /// a timeout or a failure to transport the request.
+    ///
+    /// The callback may dispatch a follow-up call, and returns the weight it used including that of the call. It must not use
+    /// more than `weight_limit`, the portal registers no more than that with the block.
+    fn callback(metadata: &XbiMetadata, result: &XbiResult, weight_limit: Weight) -> Weight;
 }
 
 pub struct XBICallbackMock<T> {
@@ -12,17 +17,13 @@ pub struct XBICallbackMock<T> {
 }
 
 impl<T: frame_system::Config + crate::pallet::Config> XBICallback<T> for XBICallbackMock<T> {
-    fn callback(
-        _xbi_checkin: xp_format::XbiCheckIn<T::BlockNumber>,
-        _xbi_checkout: xp_format::XbiCheckOut,
-    ) {
+    fn callback(_metadata: &XbiMetadata, _result: &XbiResult, _weight_limit: Weight) -> Weight {
+        0
     }
 }
 
 impl<T: frame_system::Config + crate::pallet::Config> XBICallback<T> for () {
-    fn callback(
-        _xbi_checkin: xp_format::XbiCheckIn<T::BlockNumber>,
-        _xbi_checkout: xp_format::XbiCheckOut,
-    ) {
+    fn callback(_metadata: &XbiMetadata, _result: &XbiResult, _weight_limit: Weight) -> Weight {
+        0
     }
 }
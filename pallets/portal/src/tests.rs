@@ -1,21 +1,34 @@
-use crate::Queue;
-use crate::{mock::*, xbi_abi::AccountId32, BufferRange, Error, Pallet, XbiResponses};
-use frame_support::{assert_err, assert_ok};
+use crate::{
+    mock::*, xbi_abi::AccountId32, DeadLetters, Error, ExecutedCursor, MessageNonces, Pallet,
+    PeerPortals, PeerVersions, PortalIndex, PromisedRequests, Promises, QueueIndex, QueueKeys,
+    QueueLength, QueueRanges, RelayPeers, RetryAttempts, RetryCursor, ScheduledRetries,
+    TimeoutCursor, XbiRequests, XbiResponses,
+};
+use crate::{pallet::AsyncSender, Queue};
+use codec::{Decode, Encode};
+use frame_support::{
+    assert_err, assert_ok,
+    traits::{Currency, Get, ReservableCurrency},
+};
 use sp_core::H256;
-use xp_channel::traits::Writable;
-use xp_channel::Message;
+use xp_channel::traits::{Writable, XbiInstructionHandler};
 use xp_channel::XbiResult;
-use xp_channel::{queue::Queue as QueueExt, XbiMetadata};
-use xp_format::Timestamp;
-use xp_format::XbiFormat;
+use xp_channel::{
+    queue::{Queue as QueueExt, QueuePriority, QueueSignal, SubQueue},
+    XbiMetadata, MAX_QUERIED_MESSAGES,
+};
+use xp_channel::{Message, VersionedMessage};
+use xp_format::{Status, Timestamp};
+use xp_format::{VersionedXbiFormat, XbiFormat};
+use xp_xcm::xcm::prelude::{DepositAsset, MultiLocation, Parachain, RefundSurplus, X1};
+use xs_channel::sender::{frame::ReceiveCallProvider, CallPromise, PromiseDelegate};
 use xs_channel::Receiver as ReceiverExt;
 use xs_channel::Sender as SenderExt;
 
 macro_rules! get_len {
     () => {{
-        fn get_len() -> u16 {
-            let (start, end) = <BufferRange<Test>>::get();
-            (end - start)
+        fn get_len() -> usize {
+            <Queue<Pallet<Test>>>::default().len()
         }
         get_len()
     }};
@@ -37,7 +50,30 @@ fn cannot_store_duplicate_responses() {
 }
 
 #[test]
-fn assert_nonce_incremented_and_id_enriched() {}
+fn assert_nonce_incremented_and_id_enriched() {
+    new_test_ext().execute_with(|| {
+        let mut expected = XbiMetadata::default();
+        expected.enrich_origin(&crate::impls::account32_from_account::<Test>(&1).unwrap());
+
+        for nonce in 1..=2 {
+            assert_ok!(XbiPortal::send(
+                Origin::signed(1),
+                xp_channel::ExecutionType::Async,
+                XbiFormat::default(),
+                None
+            ));
+            assert_eq!(XbiPortal::message_nonce(1), nonce);
+
+            // The id is derived from the nonce of the sender and the metadata with their origin
+            let id = expected.derive_id::<sp_runtime::traits::BlakeTwo256>(nonce, None);
+            let request = XbiRequests::<Test>::get(id).unwrap();
+            assert_eq!(request.metadata.get_id(), id);
+            assert_eq!(request.metadata.get_origin(), expected.get_origin());
+        }
+        // Every sender has a nonce of their own
+        assert_eq!(XbiPortal::message_nonce(2), 0);
+    });
+}
 
 #[test]
 fn test_async_sender_pushes_request_to_queue() {
@@ -47,15 +83,7 @@ fn test_async_sender_pushes_request_to_queue() {
                 dest: AccountId32::new([4u8; 32]),
                 value: 100,
             },
-            metadata: XbiMetadata::new(
-                0,
-                0,
-                Default::default(),
-                Default::default(),
-                Some(AccountId32::new([1u8; 32])),
-                0,
-                None,
-            ),
+            ..request(0, 0, 0, Some(AccountId32::new([1u8; 32])))
         };
         assert_ok!(crate::pallet::AsyncSender::<Test>::send(Message::Request(
             format.clone()
@@ -66,6 +94,13 @@ fn test_async_sender_pushes_request_to_queue() {
 
         assert!(!queue.is_empty());
         assert_eq!(get_len!(), 1);
+        assert_eq!(
+            queue.peek(),
+            Some((
+                Message::Request(format.clone()),
+                xp_channel::queue::QueueSignal::PendingRequest
+            ))
+        );
 
         let (msg, signal) = queue.pop().unwrap();
         assert_eq!(msg, Message::Request(format));
@@ -77,15 +112,7 @@ fn test_async_sender_pushes_request_to_queue() {
 fn test_async_sender_pushes_response_to_queue() {
     new_test_ext().execute_with(|| {
         let result = XbiResult::default();
-        let mut meta = XbiMetadata::new(
-            0,
-            0,
-            Default::default(),
-            Default::default(),
-            Some(AccountId32::new([1u8; 32])),
-            0,
-            None,
-        );
+        let mut meta = request(0, 0, 0, Some(AccountId32::new([1u8; 32]))).metadata;
 
         assert_ok!(crate::pallet::AsyncSender::<Test>::send(Message::Response(
             result.clone(),
@@ -153,3 +180,2028 @@ fn test_async_receiver_pushes_result_to_queue() {
         assert_eq!(signal, xp_channel::queue::QueueSignal::PendingResult);
     });
 }
+
+#[test]
+fn test_async_receiver_pushes_result_instruction_to_queue() {
+    new_test_ext().execute_with(|| {
+        let mut metadata = XbiMetadata::default();
+        let result = XbiResult {
+            status: Status::FailedExecution,
+            output: vec![1, 2, 3],
+            witness: vec![],
+        };
+
+        assert_ok!(crate::pallet::AsyncReceiver::<Test>::receive(
+            <Test as frame_system::Config>::Origin::root(),
+            Message::Request(XbiFormat::from_result(result.clone(), metadata.clone()))
+        ));
+        metadata.progress(Timestamp::Received(0));
+
+        let mut queue = <Queue<Pallet<Test>>>::default();
+        assert_eq!(get_len!(), 1);
+
+        let (msg, signal) = queue.pop().unwrap();
+        assert_eq!(msg, Message::Response(result, metadata));
+        assert_eq!(signal, QueueSignal::PendingResult);
+    });
+}
+
+#[test]
+fn responses_refund_the_unused_fees_to_the_user() {
+    new_test_ext().execute_with(|| {
+        let (request, mut response) = charged_request(1, 2);
+        assert_eq!(Balances::reserved_balance(1), 150);
+
+        // The destination deposits whatever is left of the fees back to our sovereign account
+        let sovereign = MultiLocation::new(1, X1(Parachain(3333)));
+        let (dest, program) = sent_xcm().pop().unwrap();
+        assert_eq!(dest, MultiLocation::new(1, X1(Parachain(2))));
+        match &program.0[program.0.len() - 2..] {
+            [RefundSurplus, DepositAsset { beneficiary, .. }] => {
+                assert_eq!(beneficiary, &sovereign)
+            }
+            tail => panic!("Unexpected end of program: {:?}", tail),
+        }
+
+        // The destination used some of the fees
+        response.fees.push_aggregate(30);
+        assert_ok!(<Queue<Pallet<Test>>>::default().push((
+            Message::Response(XbiResult::default(), response),
+            QueueSignal::PendingResult
+        )));
+        assert_ok!(XbiPortal::process_queue(Origin::root()));
+
+        assert!(XbiResponses::<Test>::contains_key(request.get_id()));
+        assert_eq!(Balances::reserved_balance(1), 30);
+        assert_eq!(Balances::free_balance(1), 970);
+    });
+}
+
+#[test]
+fn responses_are_refunded_by_the_fees_charged_for_the_request() {
+    new_test_ext().execute_with(|| {
+        let (request, mut response) = charged_request(1, 2);
+
+        // The destination claims far higher limits than were charged
+        response.fees = xp_format::Fees::new(None, Some(10_000), Some(10_000));
+        response.fees.push_aggregate(30);
+        assert_ok!(XbiPortal::receive(
+            Origin::signed(SIBLING_ACCOUNT_OFFSET + 2),
+            VersionedMessage::Response(XbiResult::default(), response)
+        ));
+
+        assert!(XbiResponses::<Test>::contains_key(request.get_id()));
+        assert!(!XbiRequests::<Test>::contains_key(request.get_id()));
+        assert_eq!(Balances::reserved_balance(1), 30);
+        assert_eq!(Balances::free_balance(1), 970);
+    });
+}
+
+#[test]
+fn result_instructions_are_refunded_by_the_costs_they_report() {
+    new_test_ext().execute_with(|| {
+        let (request, mut response) = charged_request(1, 2);
+
+        response.fees.push_aggregate(30);
+        let result = XbiFormat::from_result(XbiResult::default(), response);
+        assert_ok!(XbiPortal::receive(
+            Origin::signed(SIBLING_ACCOUNT_OFFSET + 2),
+            VersionedMessage::Request(result.into())
+        ));
+
+        assert!(XbiResponses::<Test>::contains_key(request.get_id()));
+        assert_eq!(Balances::reserved_balance(1), 30);
+        assert_eq!(Balances::free_balance(1), 970);
+        // A result is not a request from the peer
+        assert!(crate::ExecutedRequests::<Test>::iter_prefix(2)
+            .next()
+            .is_none());
+    });
+}
+
+#[test]
+fn unmatched_responses_are_rejected() {
+    new_test_ext().execute_with(|| {
+        let (_, response) = charged_request(1, 2);
+
+        // No request was sent with this id
+        let mut unknown = request(2, 3333, 42, response.get_origin().cloned()).metadata;
+        unknown.fees = response.fees.clone();
+        assert_err!(
+            XbiPortal::receive(
+                Origin::signed(SIBLING_ACCOUNT_OFFSET + 2),
+                VersionedMessage::Response(XbiResult::default(), unknown)
+            ),
+            Error::<Test>::UnmatchedResponse
+        );
+
+        // The request was not sent to the peer that responded
+        let mut misdirected = response.clone();
+        misdirected.src_para_id = 4;
+        assert_err!(
+            XbiPortal::receive(
+                Origin::signed(SIBLING_ACCOUNT_OFFSET + 4),
+                VersionedMessage::Response(XbiResult::default(), misdirected)
+            ),
+            Error::<Test>::UnmatchedResponse
+        );
+
+        // The request was not routed through the peer that relayed the response
+        relay_between(&[4]);
+        let mut misrouted = response;
+        misrouted.route = vec![4];
+        assert_err!(
+            XbiPortal::receive(
+                Origin::signed(SIBLING_ACCOUNT_OFFSET + 4),
+                VersionedMessage::Response(XbiResult::default(), misrouted)
+            ),
+            Error::<Test>::UnmatchedResponse
+        );
+        assert_eq!(Balances::reserved_balance(1), 150);
+    });
+}
+
+#[test]
+fn duplicate_responses_are_rejected() {
+    new_test_ext().execute_with(|| {
+        let (_, response) = charged_request(1, 2);
+        let receive = || {
+            XbiPortal::receive(
+                Origin::signed(SIBLING_ACCOUNT_OFFSET + 2),
+                VersionedMessage::Response(XbiResult::default(), response.clone()),
+            )
+        };
+
+        assert_ok!(receive());
+        assert_err!(receive(), Error::<Test>::DuplicateResponse);
+        assert_eq!(Balances::reserved_balance(1), 0);
+        assert_eq!(Balances::free_balance(1), 1_000);
+    });
+}
+
+#[test]
+fn queued_responses_are_only_received_once() {
+    new_test_ext().execute_with(|| {
+        let (request, mut response) = charged_request(1, 2);
+
+        // The response is queued until it is processed, in the meantime the request is marked as received
+        assert_ok!(XbiPortal::correlate_response(&mut response));
+        assert!(XbiRequests::<Test>::get(request.get_id())
+            .unwrap()
+            .metadata
+            .get_timesheet()
+            .received
+            .is_some());
+        assert_err!(
+            XbiPortal::correlate_response(&mut response.clone()),
+            Error::<Test>::DuplicateResponse
+        );
+    });
+}
+
+#[test]
+fn in_flight_requests_are_timed_out() {
+    new_test_ext().execute_with(|| {
+        let id = track_request(0).get_id();
+
+        // Default timeouts allow 16 blocks to send the message
+        XbiPortal::check_timeouts(17);
+        assert!(XbiRequests::<Test>::contains_key(id));
+
+        XbiPortal::check_timeouts(18);
+        assert!(!XbiRequests::<Test>::contains_key(id));
+        assert_eq!(
+            XbiResponses::<Test>::get(id).unwrap().status,
+            Status::SendTimeout
+        );
+    });
+}
+
+#[test]
+fn timed_out_requests_are_refunded_for_the_stages_never_reached() {
+    new_test_ext().execute_with(|| {
+        // Never sent, so nothing was spent on it
+        let (request, _) = charged_request(1, 2);
+        XbiRequests::<Test>::mutate(request.get_id(), |request| {
+            request
+                .as_mut()
+                .unwrap()
+                .metadata
+                .progress(Timestamp::Submitted(1));
+        });
+        assert_eq!(Balances::reserved_balance(1), 150);
+
+        XbiPortal::check_timeouts(18);
+        assert_eq!(
+            XbiResponses::<Test>::get(request.get_id()).unwrap().status,
+            Status::SendTimeout
+        );
+        assert_eq!(Balances::reserved_balance(1), 0);
+        assert_eq!(Balances::free_balance(1), 1_000);
+
+        // Sent, so the destination may have executed it, but its result never came back
+        let (request, _) = charged_request(2, 2);
+        XbiRequests::<Test>::mutate(request.get_id(), |request| {
+            request
+                .as_mut()
+                .unwrap()
+                .metadata
+                .progress(Timestamp::Sent(1));
+        });
+
+        XbiPortal::check_timeouts(1_000);
+        assert_eq!(
+            XbiResponses::<Test>::get(request.get_id()).unwrap().status,
+            Status::DeliveryTimeout
+        );
+        assert_eq!(Balances::reserved_balance(2), 100);
+        assert_eq!(Balances::free_balance(2), 900);
+    });
+}
+
+#[test]
+fn timeouts_are_checked_beyond_the_limit() {
+    new_test_ext().execute_with(|| {
+        TimeoutChecksLimit::set(2);
+        let expired = track_request(0).get_id();
+        // The others were sent, so they are not due to time out for a while
+        let pending = (1..5)
+            .map(|nonce| {
+                let id = track_request(nonce).get_id();
+                XbiRequests::<Test>::mutate(id, |request| {
+                    request
+                        .as_mut()
+                        .unwrap()
+                        .metadata
+                        .progress(Timestamp::Sent(10));
+                });
+                id
+            })
+            .collect::<Vec<_>>();
+
+        // Whatever order the requests are stored in, each of them is checked within three passes
+        for _ in 0..3 {
+            XbiPortal::check_timeouts(18);
+        }
+        assert!(!XbiRequests::<Test>::contains_key(expired));
+        assert!(pending
+            .iter()
+            .all(|id| XbiRequests::<Test>::contains_key(id)));
+        assert!(!TimeoutCursor::<Test>::exists());
+    });
+}
+
+#[test]
+fn queued_request_is_not_sent_after_timeout() {
+    new_test_ext().execute_with(|| {
+        let format = request(0, 1, 0, Some(AccountId32::new([1u8; 32])));
+        let id = format.metadata.get_id();
+        assert_ok!(crate::pallet::AsyncSender::<Test>::send(Message::Request(
+            format
+        )));
+
+        System::set_block_number(17);
+        assert_ok!(XbiPortal::process_queue(
+            <Test as frame_system::Config>::Origin::root()
+        ));
+
+        assert!(<Queue<Pallet<Test>>>::default().is_empty());
+        assert_eq!(
+            XbiResponses::<Test>::get(id).unwrap().status,
+            Status::SendTimeout
+        );
+    });
+}
+
+#[test]
+fn queued_execution_is_skipped_after_timeout() {
+    new_test_ext().execute_with(|| {
+        let format = XbiFormat {
+            instr: xp_format::XbiInstruction::Transfer {
+                dest: AccountId32::new([4u8; 32]),
+                value: 100,
+            },
+            ..Default::default()
+        };
+        assert_ok!(crate::pallet::AsyncReceiver::<Test>::handle_request(
+            &<Test as frame_system::Config>::Origin::root(),
+            &mut format.clone()
+        ));
+
+        System::set_block_number(17);
+        // Xcm is not configured in the mock, so only the response is left in the queue once it fails to send
+        assert_ok!(XbiPortal::process_queue(
+            <Test as frame_system::Config>::Origin::root()
+        ));
+
+        assert!(System::events().iter().any(|r| matches!(
+            &r.event,
+            Event::XbiPortal(crate::Event::QueuePopped {
+                signal: QueueSignal::PendingResponse,
+                msg: Message::Response(
+                    XbiResult {
+                        status: Status::ExecutionTimeout,
+                        ..
+                    },
+                    _
+                ),
+            })
+        )));
+    });
+}
+
+#[test]
+fn timeouts_and_failed_sends_are_reported() {
+    new_test_ext().execute_with(|| {
+        let format = XbiFormat {
+            instr: xp_format::XbiInstruction::Transfer {
+                dest: AccountId32::new([4u8; 32]),
+                value: 100,
+            },
+            ..Default::default()
+        };
+        let hash = format.metadata.get_id();
+        assert_ok!(crate::pallet::AsyncReceiver::<Test>::handle_request(
+            &<Test as frame_system::Config>::Origin::root(),
+            &mut format.clone()
+        ));
+
+        System::set_block_number(17);
+        assert_ok!(XbiPortal::process_queue(
+            <Test as frame_system::Config>::Origin::root()
+        ));
+
+        System::assert_has_event(Event::XbiPortal(crate::Event::XbiTimedOut {
+            hash,
+            status: Status::ExecutionTimeout,
+        }));
+        assert!(System::events().iter().any(|r| matches!(
+            &r.event,
+            Event::XbiPortal(crate::Event::XbiSendFailed { hash: h, .. }) if *h == hash
+        )));
+    });
+}
+
+fn provided_message(call: Vec<u8>) -> VersionedMessage {
+    // The first byte is the pallet index of the receiver
+    match crate::Call::<Test>::decode(&mut &call[1..]).unwrap() {
+        crate::Call::receive { msg } => msg,
+        call => panic!("Unexpected call provided: {:?}", call),
+    }
+}
+
+#[test]
+fn only_root_can_set_supported_peer_versions() {
+    new_test_ext().execute_with(|| {
+        assert!(XbiPortal::set_peer_version(Origin::signed(1), 2, Some(1)).is_err());
+        assert_err!(
+            XbiPortal::set_peer_version(Origin::root(), 2, Some(0)),
+            Error::<Test>::UnsupportedXbiVersion
+        );
+        assert_err!(
+            XbiPortal::set_peer_version(Origin::root(), 2, Some(xp_format::XBI_VERSION + 1)),
+            Error::<Test>::UnsupportedXbiVersion
+        );
+
+        assert_ok!(XbiPortal::set_peer_version(Origin::root(), 2, Some(1)));
+        assert_eq!(XbiPortal::peer_version(2), 1);
+
+        assert_ok!(XbiPortal::set_peer_version(Origin::root(), 2, None));
+        assert_eq!(XbiPortal::peer_version(2), xp_format::XBI_VERSION);
+    });
+}
+
+#[test]
+fn messages_are_downgraded_for_older_peers() {
+    new_test_ext().execute_with(|| {
+        let mut format = XbiFormat::default();
+        format.metadata.dest_para_id = 2;
+
+        let msg = provided_message(XbiPortal::provide(format.clone()).unwrap());
+        assert!(matches!(
+            msg,
+            VersionedMessage::Request(VersionedXbiFormat::V3(_))
+        ));
+
+        assert_ok!(XbiPortal::set_peer_version(Origin::root(), 2, Some(2)));
+        let msg = provided_message(XbiPortal::provide(format.clone()).unwrap());
+        assert!(matches!(
+            msg,
+            VersionedMessage::Request(VersionedXbiFormat::V2(_))
+        ));
+        assert_eq!(Message::from(msg), Message::Request(format.clone()));
+
+        assert_ok!(XbiPortal::set_peer_version(Origin::root(), 2, Some(1)));
+        let msg = provided_message(XbiPortal::provide(format.clone()).unwrap());
+        assert!(matches!(msg, VersionedMessage::LegacyRequest(_)));
+        assert_eq!(Message::from(msg), Message::Request(format));
+    });
+}
+
+#[test]
+fn messages_that_cannot_be_downgraded_are_rejected() {
+    new_test_ext().execute_with(|| {
+        let format = XbiFormat {
+            instr: xp_format::XbiInstruction::CallCustom {
+                caller: AccountId32::new([1u8; 32]),
+                dest: AccountId32::new([2u8; 32]),
+                value: 0,
+                input: vec![1u8; 300],
+                limit: 0,
+                additional_params: vec![],
+            },
+            ..request(0, 2, 0, None)
+        };
+
+        assert_ok!(XbiPortal::set_peer_version(Origin::root(), 2, Some(1)));
+        assert_err!(
+            XbiPortal::provide(format),
+            Error::<Test>::UnsupportedXbiVersion
+        );
+    });
+}
+
+#[test]
+fn routed_messages_are_not_sent_to_peers_before_v3() {
+    new_test_ext().execute_with(|| {
+        let mut format = XbiFormat::default();
+        format.metadata.dest_para_id = 3;
+        format.metadata.route = vec![2];
+
+        assert_ok!(XbiPortal::set_peer_version(Origin::root(), 2, Some(2)));
+        assert_err!(
+            XbiPortal::provide(format),
+            Error::<Test>::UnsupportedXbiVersion
+        );
+    });
+}
+
+#[test]
+fn older_requests_record_the_peer_version() {
+    new_test_ext().execute_with(|| {
+        XbiPortal::note_peer_version(3, xp_format::XBI_VERSION);
+        assert!(!PeerVersions::<Test>::contains_key(3));
+
+        XbiPortal::note_peer_version(3, 1);
+        assert_eq!(XbiPortal::peer_version(3), 1);
+
+        // Governance records take precedence
+        assert_ok!(XbiPortal::set_peer_version(Origin::root(), 4, Some(2)));
+        XbiPortal::note_peer_version(4, 1);
+        assert_eq!(XbiPortal::peer_version(4), 2);
+    });
+}
+
+#[test]
+fn calls_are_provided_for_the_portal_of_the_peer() {
+    new_test_ext().execute_with(|| {
+        let mut format = XbiFormat::default();
+        format.metadata.dest_para_id = 2;
+
+        let call = XbiPortal::provide(format.clone()).unwrap();
+        assert_eq!(call[..2], [200, 1]);
+
+        let index = PortalIndex {
+            pallet: 42,
+            call: 7,
+        };
+        assert_ok!(XbiPortal::set_peer_portal(Origin::root(), 2, Some(index)));
+        let call = XbiPortal::provide(format.clone()).unwrap();
+        assert_eq!(call[..2], [42, 7]);
+        assert_eq!(
+            VersionedMessage::decode(&mut &call[2..]).unwrap(),
+            Message::Request(format.clone())
+                .into_version(xp_format::XBI_VERSION)
+                .unwrap()
+        );
+
+        // Messages on a route are provided for the next hop
+        format.metadata.route = vec![4];
+        let call = XbiPortal::provide(format).unwrap();
+        assert_eq!(call[..2], [200, 1]);
+    });
+}
+
+#[test]
+fn responses_are_provided_as_results_for_peers_on_v3() {
+    new_test_ext().execute_with(|| {
+        let mut metadata = XbiMetadata::default();
+        metadata.dest_para_id = 2;
+        metadata.fees.push_aggregate(30);
+        let response = (XbiResult::default(), metadata.clone());
+
+        let call = XbiPortal::provide(response.clone()).unwrap();
+        assert_eq!(
+            VersionedMessage::decode(&mut &call[2..]).unwrap(),
+            VersionedMessage::Request(
+                XbiFormat::from_result(XbiResult::default(), metadata).into()
+            )
+        );
+
+        assert_ok!(XbiPortal::set_peer_version(Origin::root(), 2, Some(2)));
+        let call = XbiPortal::provide(response).unwrap();
+        assert!(matches!(
+            VersionedMessage::decode(&mut &call[2..]).unwrap(),
+            VersionedMessage::LegacyResponse(..)
+        ));
+    });
+}
+
+#[test]
+fn peer_portals_can_be_set_by_root_or_the_peer() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let index = PortalIndex {
+            pallet: 42,
+            call: 7,
+        };
+
+        assert_ok!(XbiPortal::set_peer_portal(Origin::root(), 2, Some(index)));
+        assert_eq!(XbiPortal::peer_portal(2), index);
+        System::assert_last_event(Event::XbiPortal(crate::Event::PeerPortalUpdated {
+            para_id: 2,
+            index: Some(index),
+        }));
+
+        assert_ok!(XbiPortal::set_peer_portal(
+            Origin::signed(SIBLING_ACCOUNT_OFFSET + 3),
+            3,
+            Some(index)
+        ));
+        assert_eq!(PeerPortals::<Test>::get(3), Some(index));
+
+        // Peers cannot set the portal of another peer
+        assert_err!(
+            XbiPortal::set_peer_portal(Origin::signed(SIBLING_ACCOUNT_OFFSET + 3), 2, None),
+            sp_runtime::DispatchError::BadOrigin
+        );
+        assert_err!(
+            XbiPortal::set_peer_portal(Origin::signed(1), 2, None),
+            sp_runtime::DispatchError::BadOrigin
+        );
+
+        assert_ok!(XbiPortal::set_peer_portal(Origin::root(), 2, None));
+        assert_eq!(XbiPortal::peer_portal(2), DefaultPortalIndex::get());
+    });
+}
+
+#[test]
+fn own_portal_index_is_announced_to_peers() {
+    new_test_ext().execute_with(|| {
+        let index = XbiPortal::own_portal_index();
+        assert_eq!(
+            index.pallet as usize,
+            <XbiPortal as frame_support::traits::PalletInfoAccess>::index()
+        );
+        assert_eq!(index.call, DefaultPortalIndex::get().call);
+
+        assert!(XbiPortal::announce_portal(Origin::signed(1), 2).is_err());
+        // The mock cannot send XCM
+        assert_err!(
+            XbiPortal::announce_portal(Origin::root(), 2),
+            Error::<Test>::PortalAnnouncementFailed
+        );
+    });
+}
+
+fn handle_defi(instr: xp_format::XbiInstruction) -> Result<Vec<u8>, sp_runtime::DispatchError> {
+    let mut format = XbiFormat {
+        instr,
+        ..request(0, 0, 0, Some(AccountId32::new([1u8; 32])))
+    };
+    XbiPortal::handle(&Origin::signed(1), &mut format)
+        .map(|info| {
+            assert_eq!(info.weight, DEFI_WEIGHT);
+            info.output
+        })
+        .map_err(|e| e.error)
+}
+
+#[test]
+fn defi_instructions_are_routed_to_the_pool() {
+    use xp_format::XbiInstruction::*;
+
+    new_test_ext().execute_with(|| {
+        let output = handle_defi(AddLiquidity {
+            asset_a: 1,
+            asset_b: 2,
+            amount_a: 1000,
+            amount_b_max_limit: 4000,
+        })
+        .unwrap();
+        assert_eq!(output, (4000u128, 1000u128).encode());
+
+        let output = handle_defi(GetPrice {
+            asset_a: 1,
+            asset_b: 2,
+            amount: 100,
+        })
+        .unwrap();
+        assert_eq!(output, 363u128.encode());
+
+        let output = handle_defi(GetPrice {
+            asset_a: 2,
+            asset_b: 1,
+            amount: 400,
+        })
+        .unwrap();
+        assert_eq!(output, 90u128.encode());
+
+        assert!(handle_defi(Swap {
+            asset_out: 2,
+            asset_in: 1,
+            amount: 400,
+            max_limit: 100,
+            discount: false,
+        })
+        .is_err());
+
+        let output = handle_defi(Swap {
+            asset_out: 2,
+            asset_in: 1,
+            amount: 400,
+            max_limit: 200,
+            discount: false,
+        })
+        .unwrap();
+        assert_eq!(output, 112u128.encode());
+
+        let output = handle_defi(RemoveLiquidity {
+            asset_a: 1,
+            asset_b: 2,
+            liquidity_amount: 500,
+        })
+        .unwrap();
+        assert_eq!(output, (556u128, 1800u128).encode());
+
+        assert!(handle_defi(RemoveLiquidity {
+            asset_a: 1,
+            asset_b: 2,
+            liquidity_amount: 501,
+        })
+        .is_err());
+    });
+}
+
+#[test]
+fn defi_instructions_fail_without_liquidity() {
+    new_test_ext().execute_with(|| {
+        assert!(handle_defi(xp_format::XbiInstruction::GetPrice {
+            asset_a: 1,
+            asset_b: 2,
+            amount: 100,
+        })
+        .is_err());
+    });
+}
+
+fn handle_with_xbi_origin(
+    instr: xp_format::XbiInstruction,
+) -> Result<xp_channel::traits::HandlerInfo<u64>, sp_runtime::DispatchError> {
+    let mut format = XbiFormat {
+        instr,
+        ..request(0, 0, 0, Some(AccountId32::new([1u8; 32])))
+    };
+    XbiPortal::handle(&Origin::signed(1), &mut format).map_err(|e| e.error)
+}
+
+#[test]
+fn native_calls_are_dispatched_with_the_remote_origin() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let remark = vec![1u8, 2, 3];
+        let call = Call::System(frame_system::Call::remark_with_event {
+            remark: remark.clone(),
+        });
+
+        let info = handle_with_xbi_origin(xp_format::XbiInstruction::CallNative {
+            payload: call.encode(),
+        })
+        .unwrap();
+        assert_eq!(
+            info.weight,
+            frame_support::weights::GetDispatchInfo::get_dispatch_info(&call).weight
+        );
+
+        let metadata = request(0, 0, 0, Some(AccountId32::new([1u8; 32]))).metadata;
+        let sender: AccountId = xs_channel::remote_origin(&metadata).unwrap();
+        assert_ne!(sender, u64::from_le_bytes([1u8; 8]));
+        System::assert_last_event(Event::System(frame_system::Event::Remarked {
+            sender,
+            hash: <sp_runtime::traits::BlakeTwo256 as sp_runtime::traits::Hash>::hash(&remark),
+        }));
+    });
+}
+
+#[test]
+fn transfers_are_made_from_the_remote_origin() {
+    new_test_ext().execute_with(|| {
+        let metadata = request(0, 0, 0, Some(AccountId32::new([1u8; 32]))).metadata;
+        let sender: AccountId = xs_channel::remote_origin(&metadata).unwrap();
+        Balances::make_free_balance_be(&sender, 1000);
+        Balances::make_free_balance_be(&1, 1000);
+
+        assert_ok!(handle_with_xbi_origin(
+            xp_format::XbiInstruction::Transfer {
+                dest: origin_of(4),
+                value: 100,
+            }
+        ));
+        assert_eq!(Balances::free_balance(sender), 900);
+        assert_eq!(Balances::free_balance(1), 1000);
+        assert_eq!(Balances::free_balance(4), 100);
+    });
+}
+
+#[test]
+fn queued_native_calls_are_dispatched_with_the_remote_origin() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let remark = vec![1u8, 2, 3];
+        let call = Call::System(frame_system::Call::remark_with_event {
+            remark: remark.clone(),
+        });
+        let mut format = XbiFormat {
+            instr: xp_format::XbiInstruction::CallNative {
+                payload: call.encode(),
+            },
+            ..request(2, 3333, 0, Some(AccountId32::new([1u8; 32])))
+        };
+        format.metadata.fees = xp_format::Fees::new(None, Some(1_000_000_000), Some(1_000_000_000));
+        let sender: AccountId = xs_channel::remote_origin(&format.metadata).unwrap();
+
+        assert_ok!(crate::pallet::AsyncReceiver::<Test>::handle_request(
+            &Origin::signed(SIBLING_ACCOUNT_OFFSET + 2),
+            &mut format
+        ));
+        assert_ok!(XbiPortal::process_queue(
+            <Test as frame_system::Config>::Origin::root()
+        ));
+
+        System::assert_has_event(Event::System(frame_system::Event::Remarked {
+            sender,
+            hash: <sp_runtime::traits::BlakeTwo256 as sp_runtime::traits::Hash>::hash(&remark),
+        }));
+        assert!(System::events().iter().any(|r| matches!(
+            &r.event,
+            Event::XbiPortal(crate::Event::XbiRequestHandled { result, .. })
+                if result.status == Status::Success
+        )));
+    });
+}
+
+#[test]
+fn peers_cannot_dispatch_native_calls_as_local_accounts() {
+    new_test_ext().execute_with(|| {
+        let victim: AccountId = 1;
+        Balances::make_free_balance_be(&victim, 1_000);
+        let call = Call::Balances(pallet_balances::Call::transfer {
+            dest: 7,
+            value: 500,
+        });
+
+        // The peer claims the local account of the victim as the origin of its message
+        let mut format = XbiFormat {
+            instr: xp_format::XbiInstruction::CallNative {
+                payload: call.encode(),
+            },
+            ..request(2, 0, 0, Some(origin_of(victim)))
+        };
+        assert_eq!(
+            xs_channel::xbi_origin::<AccountId>(&format.metadata),
+            Ok(victim)
+        );
+
+        assert_err!(
+            XbiPortal::handle(&Origin::signed(SIBLING_ACCOUNT_OFFSET + 2), &mut format)
+                .map_err(|e| e.error),
+            pallet_balances::Error::<Test>::InsufficientBalance
+        );
+        assert_eq!(Balances::free_balance(victim), 1_000);
+        assert_eq!(Balances::free_balance(7), 0);
+    });
+}
+
+#[test]
+fn native_calls_must_pass_the_filter() {
+    new_test_ext().execute_with(|| {
+        let call = Call::System(frame_system::Call::remark {
+            remark: vec![1u8, 2, 3],
+        });
+
+        assert_err!(
+            handle_with_xbi_origin(xp_format::XbiInstruction::CallNative {
+                payload: call.encode(),
+            }),
+            Error::<Test>::CallFiltered
+        );
+        assert_err!(
+            handle_with_xbi_origin(xp_format::XbiInstruction::CallNative {
+                payload: vec![255u8, 255],
+            }),
+            Error::<Test>::FailedToDecodeCall
+        );
+    });
+}
+
+#[test]
+fn custom_calls_are_routed_to_the_custom_vm() {
+    new_test_ext().execute_with(|| {
+        let info = handle_with_xbi_origin(xp_format::XbiInstruction::CallCustom {
+            caller: AccountId32::new([1u8; 32]),
+            dest: AccountId32::new([2u8; 32]),
+            value: 0,
+            input: vec![4, 5, 6],
+            limit: 500,
+            additional_params: vec![],
+        })
+        .unwrap();
+
+        assert_eq!(info.output, vec![4, 5, 6]);
+        assert_eq!(info.weight, 500);
+    });
+}
+
+#[test]
+fn batches_resolve_with_the_output_of_each_instruction() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let call = Call::System(frame_system::Call::remark_with_event {
+            remark: vec![1u8, 2, 3],
+        });
+
+        let info = handle_with_xbi_origin(xp_format::XbiInstruction::Batch {
+            instructions: vec![
+                xp_format::XbiInstruction::Unknown {
+                    identifier: PING,
+                    params: vec![7, 8],
+                },
+                xp_format::XbiInstruction::CallNative {
+                    payload: call.encode(),
+                },
+            ],
+        })
+        .unwrap();
+
+        assert_eq!(info.output, vec![vec![7u8, 8], vec![]].encode());
+        assert_eq!(
+            info.weight,
+            1 + frame_support::weights::GetDispatchInfo::get_dispatch_info(&call).weight
+        );
+        assert_eq!(System::events().len(), 1);
+    });
+}
+
+#[test]
+fn failed_batches_are_rolled_back() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let call = Call::System(frame_system::Call::remark_with_event {
+            remark: vec![1u8, 2, 3],
+        });
+
+        assert_err!(
+            handle_with_xbi_origin(xp_format::XbiInstruction::Batch {
+                instructions: vec![
+                    xp_format::XbiInstruction::CallNative {
+                        payload: call.encode(),
+                    },
+                    xp_format::XbiInstruction::Unknown {
+                        identifier: 99,
+                        params: vec![],
+                    },
+                ],
+            }),
+            Error::<Test>::UnknownInstruction
+        );
+        assert!(System::events().is_empty());
+
+        assert_err!(
+            handle_with_xbi_origin(xp_format::XbiInstruction::Batch {
+                instructions: vec![xp_format::XbiInstruction::Batch {
+                    instructions: vec![],
+                }],
+            }),
+            Error::<Test>::InstructionuctionNotAllowedHere
+        );
+    });
+}
+
+#[test]
+fn unknown_instructions_fail() {
+    new_test_ext().execute_with(|| {
+        assert_err!(
+            handle_with_xbi_origin(xp_format::XbiInstruction::Unknown {
+                identifier: PING + 1,
+                params: vec![1, 2, 3],
+            }),
+            Error::<Test>::UnknownInstruction
+        );
+    });
+}
+
+#[test]
+fn custom_instructions_are_routed_to_their_handler() {
+    new_test_ext().execute_with(|| {
+        let info = handle_with_xbi_origin(xp_format::XbiInstruction::Unknown {
+            identifier: PING,
+            params: vec![1, 2, 3],
+        })
+        .unwrap();
+
+        assert_eq!(info.output, vec![1, 2, 3]);
+        assert_eq!(info.weight, 1);
+    });
+}
+
+#[test]
+fn results_are_not_routed_to_handlers() {
+    new_test_ext().execute_with(|| {
+        assert_err!(
+            handle_with_xbi_origin(xp_format::XbiInstruction::Result {
+                outcome: Status::Success,
+                output: vec![],
+                witness: vec![],
+                actual_aggregated_costs: 0,
+            }),
+            Error::<Test>::InstructionuctionNotAllowedHere
+        );
+    });
+}
+
+fn queue_failed_requests(count: u32) -> Vec<H256> {
+    let mut queue = <Queue<Pallet<Test>>>::default();
+    (0..count)
+        .map(|nonce| {
+            let format = request(0, 1, nonce, None);
+            let id = format.metadata.get_id();
+            assert_ok!(queue.push((
+                Message::Request(format),
+                QueueSignal::ProtocolError(Status::DispatchFailed),
+            )));
+            id
+        })
+        .collect()
+}
+
+#[test]
+fn messages_that_fail_do_not_abort_the_queue() {
+    new_test_ext().execute_with(|| {
+        let ids = queue_failed_requests(1);
+        // The asset of the fees cannot be looked up, so the request cannot be sent
+        let mut unpayable = request(0, 1, 100, None);
+        unpayable.metadata.fees = xp_format::Fees::new(Some(1), None, None);
+        let unpayable_id = unpayable.metadata.get_id();
+        assert_ok!(<Queue<Pallet<Test>>>::default()
+            .push((Message::Request(unpayable), QueueSignal::PendingRequest)));
+
+        assert_ok!(XbiPortal::process_queue(
+            <Test as frame_system::Config>::Origin::root()
+        ));
+        assert_eq!(get_len!(), 0);
+        assert!(XbiResponses::<Test>::contains_key(ids[0]));
+        assert!(DeadLetters::<Test>::contains_key(unpayable_id));
+        assert_eq!(
+            XbiResponses::<Test>::get(unpayable_id).unwrap().status,
+            Status::DispatchFailed
+        );
+    });
+}
+
+#[test]
+fn process_queue_honours_the_message_limit() {
+    new_test_ext().execute_with(|| {
+        CheckOutLimit::set(2);
+        let ids = queue_failed_requests(3);
+
+        assert_ok!(XbiPortal::process_queue(
+            <Test as frame_system::Config>::Origin::root()
+        ));
+        assert_eq!(get_len!(), 1);
+        assert!(XbiResponses::<Test>::contains_key(ids[1]));
+        assert!(!XbiResponses::<Test>::contains_key(ids[2]));
+
+        assert_ok!(XbiPortal::process_queue(
+            <Test as frame_system::Config>::Origin::root()
+        ));
+        assert_eq!(get_len!(), 0);
+        assert!(XbiResponses::<Test>::contains_key(ids[2]));
+    });
+}
+
+#[test]
+fn process_queue_honours_the_weight_limit() {
+    new_test_ext().execute_with(|| {
+        let ids = queue_failed_requests(3);
+        let bounds_weight = <Test as frame_system::Config>::DbWeight::get().reads(1);
+        let message_weight =
+            XbiPortal::queued_message_weight(&QueueSignal::ProtocolError(Status::DispatchFailed));
+        QueueWeightLimit::set(bounds_weight + 2 * message_weight);
+
+        let info =
+            XbiPortal::process_queue(<Test as frame_system::Config>::Origin::root()).unwrap();
+        assert_eq!(info.actual_weight, Some(bounds_weight + 2 * message_weight));
+        assert_eq!(get_len!(), 1);
+        assert!(XbiResponses::<Test>::contains_key(ids[1]));
+        assert!(!XbiResponses::<Test>::contains_key(ids[2]));
+    });
+}
+
+#[test]
+fn executions_exceeding_the_weight_limit_are_not_handled() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let mut format = XbiFormat {
+            instr: xp_format::XbiInstruction::Transfer {
+                dest: AccountId32::new([4u8; 32]),
+                value: 100,
+            },
+            ..request(0, 1, 0, None)
+        };
+        format.metadata.fees =
+            xp_format::Fees::new(None, Some(QueueWeightLimit::get() as u128), None);
+        assert_ok!(crate::pallet::AsyncReceiver::<Test>::handle_request(
+            &<Test as frame_system::Config>::Origin::root(),
+            &mut format.clone()
+        ));
+
+        assert_ok!(XbiPortal::process_queue(
+            <Test as frame_system::Config>::Origin::root()
+        ));
+
+        assert!(System::events().iter().any(|r| matches!(
+            &r.event,
+            Event::XbiPortal(crate::Event::QueuePopped {
+                signal: QueueSignal::PendingResponse,
+                msg: Message::Response(
+                    XbiResult {
+                        status: Status::ExecutionLimitExceeded,
+                        ..
+                    },
+                    _
+                ),
+            })
+        )));
+    });
+}
+
+fn fill_queue() {
+    let key = SubQueue {
+        priority: QueuePriority::Normal,
+        para_id: 0,
+    };
+    <QueueRanges<Test>>::insert(key, (0, u16::MAX));
+    <QueueKeys<Test>>::put(vec![key]);
+    <QueueLength<Test>>::put(u16::MAX);
+}
+
+#[test]
+fn queue_rejects_items_when_full() {
+    new_test_ext().execute_with(|| {
+        fill_queue();
+        let mut queue = <Queue<Pallet<Test>>>::default();
+        assert_eq!(queue.len(), queue.capacity());
+        assert!(queue.is_full());
+
+        assert_eq!(
+            queue.push(Default::default()),
+            Err(xp_channel::queue::QueueError::Full)
+        );
+        assert_eq!(queue.len(), usize::from(u16::MAX));
+
+        queue.pop();
+        assert!(!queue.is_full());
+        assert_ok!(queue.push(Default::default()));
+        assert!(queue.is_full());
+    });
+}
+
+#[test]
+fn explicit_ids_are_bound_to_the_sender() {
+    new_test_ext().execute_with(|| {
+        let id = H256::repeat_byte(1);
+        let send = |who| {
+            XbiPortal::send(
+                Origin::signed(who),
+                xp_channel::ExecutionType::Async,
+                XbiFormat::default(),
+                Some(crate::MessageIdSource::Explicit(id)),
+            )
+        };
+
+        // Senders choosing the same id are each given their own
+        assert_ok!(send(1));
+        assert_ok!(send(2));
+        assert!(XbiRequests::<Test>::contains_key(
+            XbiPortal::explicit_message_id(&1, id)
+        ));
+        assert!(XbiRequests::<Test>::contains_key(
+            XbiPortal::explicit_message_id(&2, id)
+        ));
+        assert!(!XbiRequests::<Test>::contains_key(id));
+
+        assert_err!(send(1), Error::<Test>::MessageIdInUse);
+    });
+}
+
+#[test]
+fn storage_is_migrated_to_sender_nonces_and_an_indexed_queue() {
+    use frame_support::{
+        storage::migration::{get_storage_value, put_storage_value},
+        traits::{GetStorageVersion, OnRuntimeUpgrade, StorageVersion},
+    };
+
+    new_test_ext().execute_with(|| {
+        StorageVersion::new(0).put::<XbiPortal>();
+        put_storage_value(b"XbiPortal", b"MessageNonce", &[], 7u32);
+        charged_request(1, 2);
+        let queued = request_to(1, 0);
+        assert_ok!(
+            <Queue<Pallet<Test>>>::default().push((queued.clone(), QueueSignal::PendingRequest))
+        );
+        // Messages queued before the upgrade were not indexed or counted
+        QueueIndex::<Test>::remove(queued.get_metadata().get_id());
+        QueueLength::<Test>::kill();
+
+        crate::migrations::MigrateToSenderNonces::<Test>::on_runtime_upgrade();
+        assert_eq!(get_len!(), 1);
+        assert_eq!(
+            XbiPortal::message_lifecycle(queued.get_metadata().get_id()).and_then(|l| l.signal),
+            Some(QueueSignal::PendingRequest)
+        );
+        assert_eq!(XbiPortal::message_nonce(1), 7);
+        assert_eq!(XbiPortal::message_nonce(2), 0);
+        assert_eq!(
+            get_storage_value::<u32>(b"XbiPortal", b"MessageNonce", &[]),
+            None
+        );
+        assert_eq!(XbiPortal::on_chain_storage_version(), 1);
+
+        // The migration only runs once
+        MessageNonces::<Test>::insert(1, 8);
+        crate::migrations::MigrateToSenderNonces::<Test>::on_runtime_upgrade();
+        assert_eq!(XbiPortal::message_nonce(1), 8);
+    });
+}
+
+#[test]
+fn async_send_is_rejected_when_the_queue_is_full() {
+    new_test_ext().execute_with(|| {
+        fill_queue();
+
+        assert_err!(
+            XbiPortal::send(
+                Origin::signed(1),
+                xp_channel::ExecutionType::Async,
+                XbiFormat::default(),
+                None
+            ),
+            Error::<Test>::QueueFull
+        );
+        assert_err!(
+            crate::pallet::AsyncReceiver::<Test>::handle_request(
+                &<Test as frame_system::Config>::Origin::root(),
+                &mut XbiFormat::default()
+            )
+            .map_err(|e| e.error),
+            sp_runtime::DispatchError::from(xp_channel::queue::QueueError::Full)
+        );
+        assert_eq!(get_len!(), usize::from(u16::MAX));
+    });
+}
+
+#[test]
+fn queue_prioritises_responses_over_requests() {
+    new_test_ext().execute_with(|| {
+        let mut queue = <Queue<Pallet<Test>>>::default();
+        let request = request_to(1, 0);
+        let response = Message::Response(Default::default(), request.get_metadata().clone());
+
+        assert_ok!(queue.push((request.clone(), QueueSignal::PendingRequest)));
+        assert_ok!(queue.push((response.clone(), QueueSignal::PendingResponse)));
+        assert_eq!(queue.len(), 2);
+
+        assert_eq!(
+            queue.peek(),
+            Some((response.clone(), QueueSignal::PendingResponse))
+        );
+        assert_eq!(queue.pop(), Some((response, QueueSignal::PendingResponse)));
+        assert_eq!(queue.pop(), Some((request, QueueSignal::PendingRequest)));
+        assert!(queue.is_empty());
+        assert!(<QueueKeys<Test>>::get().is_empty());
+    });
+}
+
+#[test]
+fn pending_queue_lists_every_sub_queue_by_priority() {
+    new_test_ext().execute_with(|| {
+        let mut queue = <Queue<Pallet<Test>>>::default();
+        let requests = (0..2).map(|nonce| request_to(1, nonce)).collect::<Vec<_>>();
+        let other = request_to(2, 0);
+        let response = Message::Response(Default::default(), other.get_metadata().clone());
+
+        for msg in requests.iter().chain([&other]) {
+            assert_ok!(queue.push((msg.clone(), QueueSignal::PendingRequest)));
+        }
+        assert_ok!(queue.push((response.clone(), QueueSignal::PendingResponse)));
+
+        let all = vec![
+            (response, QueueSignal::PendingResponse),
+            (requests[0].clone(), QueueSignal::PendingRequest),
+            (requests[1].clone(), QueueSignal::PendingRequest),
+            (other, QueueSignal::PendingRequest),
+        ];
+        assert_eq!(XbiPortal::pending_queue(0, 10), all);
+        // Listing the queue leaves it as it was
+        assert_eq!(queue.len(), 4);
+
+        // Pages are cut across sub-queues
+        assert_eq!(XbiPortal::pending_queue(0, 2), all[..2].to_vec());
+        assert_eq!(XbiPortal::pending_queue(2, 2), all[2..].to_vec());
+        assert_eq!(XbiPortal::pending_queue(3, 2), all[3..].to_vec());
+        assert!(XbiPortal::pending_queue(4, 2).is_empty());
+    });
+}
+
+#[test]
+fn queries_are_capped_at_the_max_queried_messages() {
+    new_test_ext().execute_with(|| {
+        let mut queue = <Queue<Pallet<Test>>>::default();
+        for nonce in 0..=MAX_QUERIED_MESSAGES {
+            assert_ok!(queue.push((request_to(1, nonce), QueueSignal::PendingRequest)));
+        }
+
+        let page = XbiPortal::pending_queue(1, u32::MAX);
+        assert_eq!(page.len(), MAX_QUERIED_MESSAGES as usize);
+        assert_eq!(page[0].0, request_to(1, 1));
+
+        let ids = (0..=MAX_QUERIED_MESSAGES).map(|n| H256::from_low_u64_be(n.into()));
+        assert_eq!(
+            XbiPortal::results(ids.collect()).len(),
+            MAX_QUERIED_MESSAGES as usize
+        );
+    });
+}
+
+#[test]
+fn queued_messages_are_indexed_by_id() {
+    new_test_ext().execute_with(|| {
+        let mut queue = <Queue<Pallet<Test>>>::default();
+        let request = request_to(1, 0);
+        let id = request.get_metadata().get_id();
+
+        assert_ok!(queue.push((request.clone(), QueueSignal::PendingRequest)));
+        assert_eq!(
+            queue.get_by_id(&id),
+            Some((request.clone(), QueueSignal::PendingRequest))
+        );
+
+        // The same message queued again is found at its latest position, which outlives the earlier one
+        assert_ok!(queue.push((request.clone(), QueueSignal::PendingExecution)));
+        assert_eq!(
+            queue.pop(),
+            Some((request.clone(), QueueSignal::PendingRequest))
+        );
+        assert_eq!(
+            queue.get_by_id(&id),
+            Some((request.clone(), QueueSignal::PendingExecution))
+        );
+
+        assert_eq!(queue.pop(), Some((request, QueueSignal::PendingExecution)));
+        assert_eq!(queue.get_by_id(&id), None);
+        assert!(!QueueIndex::<Test>::contains_key(id));
+    });
+}
+
+#[test]
+fn message_lifecycles_are_queried_by_id() {
+    new_test_ext().execute_with(|| {
+        let mut in_flight = request_to(1, 0);
+        if let Message::Request(format) = &mut in_flight {
+            format.metadata.progress(Timestamp::Submitted(1));
+            XbiRequests::<Test>::insert(format.metadata.get_id(), format.clone());
+        }
+        let in_flight_id = in_flight.get_metadata().get_id();
+        let queued = request_to(1, 1);
+        let dead = request_to(1, 2);
+        let resolved = request_to(1, 3);
+
+        assert_ok!(
+            <Queue<Pallet<Test>>>::default().push((queued.clone(), QueueSignal::PendingExecution))
+        );
+        DeadLetters::<Test>::insert(
+            dead.get_metadata().get_id(),
+            (dead.clone(), Status::DispatchFailed),
+        );
+        XbiResponses::<Test>::insert(resolved.get_metadata().get_id(), XbiResult::default());
+
+        let lifecycle = XbiPortal::message_lifecycle(in_flight_id).unwrap();
+        assert_eq!(lifecycle.timesheet.unwrap().submitted, Some(1));
+        assert!(lifecycle.request.is_some());
+        assert_eq!(lifecycle.signal, None);
+
+        let lifecycle = XbiPortal::message_lifecycle(queued.get_metadata().get_id()).unwrap();
+        assert_eq!(lifecycle.signal, Some(QueueSignal::PendingExecution));
+        assert_eq!(lifecycle.request, None);
+
+        let lifecycle = XbiPortal::message_lifecycle(dead.get_metadata().get_id()).unwrap();
+        assert_eq!(lifecycle.dead_letter, Some(Status::DispatchFailed));
+
+        let lifecycle = XbiPortal::message_lifecycle(resolved.get_metadata().get_id()).unwrap();
+        assert_eq!(lifecycle.result, Some(XbiResult::default()));
+        assert_eq!(lifecycle.timesheet, None);
+
+        assert_eq!(XbiPortal::message_lifecycle(H256::repeat_byte(1)), None);
+        assert_eq!(
+            XbiPortal::results(vec![
+                resolved.get_metadata().get_id(),
+                in_flight_id,
+                H256::repeat_byte(1)
+            ]),
+            vec![Some(XbiResult::default()), None, None]
+        );
+    });
+}
+
+#[test]
+fn queue_round_robins_across_destinations() {
+    new_test_ext().execute_with(|| {
+        let mut queue = <Queue<Pallet<Test>>>::default();
+        let flood = (0..3).map(|nonce| request_to(1, nonce)).collect::<Vec<_>>();
+        let other = request_to(2, 0);
+
+        for msg in flood.iter() {
+            assert_ok!(queue.push((msg.clone(), QueueSignal::PendingRequest)));
+        }
+        assert_ok!(queue.push((other.clone(), QueueSignal::PendingRequest)));
+
+        let popped = std::iter::from_fn(|| queue.pop().map(|(msg, _)| msg)).collect::<Vec<_>>();
+        assert_eq!(
+            popped,
+            vec![flood[0].clone(), other, flood[1].clone(), flood[2].clone()]
+        );
+    });
+}
+
+#[test]
+fn transport_failures_are_retried_with_backoff() {
+    new_test_ext().execute_with(|| {
+        MaxRetries::set(2);
+        System::set_block_number(1);
+        let hash = queue_failed_requests(1)[0];
+
+        assert_ok!(XbiPortal::process_queue(
+            <Test as frame_system::Config>::Origin::root()
+        ));
+        assert_eq!(get_len!(), 0);
+        assert_eq!(RetryAttempts::<Test>::get(hash), 1);
+        assert_eq!(
+            ScheduledRetries::<Test>::get(hash).map(|(at, _)| at),
+            Some(4)
+        );
+        assert!(!XbiResponses::<Test>::contains_key(hash));
+
+        XbiPortal::queue_due_retries(3);
+        assert_eq!(get_len!(), 0);
+
+        XbiPortal::queue_due_retries(4);
+        assert!(!ScheduledRetries::<Test>::contains_key(hash));
+        let (msg, signal) = <Queue<Pallet<Test>>>::default().pop().unwrap();
+        assert_eq!(signal, QueueSignal::PendingRequest);
+
+        // The backoff doubles with each attempt
+        assert_ok!(XbiPortal::retry_or_dead_letter(msg, Status::DispatchFailed));
+        assert_eq!(RetryAttempts::<Test>::get(hash), 2);
+        assert_eq!(
+            ScheduledRetries::<Test>::get(hash).map(|(at, _)| at),
+            Some(7)
+        );
+    });
+}
+
+#[test]
+fn due_retries_are_queued_beyond_the_limit() {
+    new_test_ext().execute_with(|| {
+        TimeoutChecksLimit::set(2);
+        let schedule = |nonce, at| {
+            let msg = request_to(1, nonce);
+            let hash = msg.get_metadata().get_id();
+            ScheduledRetries::<Test>::insert(hash, (at, msg));
+            hash
+        };
+        let due = schedule(0, 4);
+        let later = (1..5).map(|nonce| schedule(nonce, 100)).collect::<Vec<_>>();
+
+        // Whatever order the retries are stored in, each of them is checked within three passes
+        for _ in 0..3 {
+            XbiPortal::queue_due_retries(4);
+        }
+        assert!(!ScheduledRetries::<Test>::contains_key(due));
+        assert!(later
+            .iter()
+            .all(|hash| ScheduledRetries::<Test>::contains_key(hash)));
+        assert_eq!(get_len!(), 1);
+        assert!(!RetryCursor::<Test>::exists());
+    });
+}
+
+#[test]
+fn exhausted_requests_are_dead_lettered_and_resolved() {
+    new_test_ext().execute_with(|| {
+        let hash = queue_failed_requests(1)[0];
+
+        assert_ok!(XbiPortal::process_queue(
+            <Test as frame_system::Config>::Origin::root()
+        ));
+        assert!(matches!(
+            XbiPortal::dead_letter(hash),
+            Some((Message::Request(_), Status::DispatchFailed))
+        ));
+        assert_eq!(
+            XbiResponses::<Test>::get(hash).map(|result| result.status),
+            Some(Status::DispatchFailed)
+        );
+        assert!(!RetryAttempts::<Test>::contains_key(hash));
+        assert!(!ScheduledRetries::<Test>::contains_key(hash));
+    });
+}
+
+#[test]
+fn failed_responses_are_retried_before_being_dead_lettered() {
+    new_test_ext().execute_with(|| {
+        MaxRetries::set(1);
+        let response =
+            Message::Response(Default::default(), request_to(1, 0).get_metadata().clone());
+        let hash = response.get_metadata().get_id();
+
+        assert_ok!(XbiPortal::retry_or_dead_letter(
+            response.clone(),
+            Status::DispatchFailed
+        ));
+        assert!(ScheduledRetries::<Test>::contains_key(hash));
+
+        assert_ok!(XbiPortal::retry_or_dead_letter(
+            response.clone(),
+            Status::DispatchFailed
+        ));
+        assert_eq!(
+            XbiPortal::dead_letter(hash),
+            Some((response, Status::DispatchFailed))
+        );
+        assert!(!XbiResponses::<Test>::contains_key(hash));
+    });
+}
+
+#[test]
+fn dead_letters_can_be_replayed_by_root() {
+    new_test_ext().execute_with(|| {
+        let hash = queue_failed_requests(1)[0];
+        assert_ok!(XbiPortal::process_queue(
+            <Test as frame_system::Config>::Origin::root()
+        ));
+
+        assert_err!(
+            XbiPortal::replay_dead_letter(Origin::signed(1), hash),
+            sp_runtime::DispatchError::BadOrigin
+        );
+        assert_err!(
+            XbiPortal::replay_dead_letter(Origin::root(), H256::repeat_byte(1)),
+            Error::<Test>::DeadLetterNotFound
+        );
+
+        assert_ok!(XbiPortal::replay_dead_letter(Origin::root(), hash));
+        assert!(!DeadLetters::<Test>::contains_key(hash));
+        assert!(!XbiResponses::<Test>::contains_key(hash));
+        assert!(XbiRequests::<Test>::contains_key(hash));
+        assert!(matches!(
+            <Queue<Pallet<Test>>>::default().peek(),
+            Some((Message::Request(_), QueueSignal::PendingRequest))
+        ));
+    });
+}
+
+fn remarked(result: &XbiResult) -> bool {
+    let hash =
+        <sp_runtime::traits::BlakeTwo256 as sp_runtime::traits::Hash>::hash(&result.encode());
+    System::events().iter().any(|r| {
+        matches!(
+            &r.event,
+            Event::System(frame_system::Event::Remarked { hash: h, .. }) if *h == hash
+        )
+    })
+}
+
+#[test]
+fn results_of_tracked_requests_are_called_back() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let metadata = track_request(0);
+        let result = XbiResult {
+            output: vec![1, 2, 3],
+            ..Default::default()
+        };
+
+        assert_ok!(XbiPortal::write((metadata.get_id(), result.clone())));
+        assert!(remarked(&result));
+    });
+}
+
+#[test]
+fn timeouts_are_called_back() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        track_request(0);
+
+        XbiPortal::check_timeouts(18);
+        assert!(remarked(&XbiResult {
+            status: Status::SendTimeout,
+            ..Default::default()
+        }));
+    });
+}
+
+#[test]
+fn callbacks_are_limited_to_the_response_weight() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        ResponseWeightLimit::set(0);
+        let metadata = track_request(0);
+        let result = XbiResult::default();
+
+        assert_ok!(XbiPortal::write((metadata.get_id(), result.clone())));
+        assert!(!remarked(&result));
+    });
+}
+
+#[test]
+fn untracked_results_are_not_called_back() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let result = XbiResult::default();
+
+        assert_ok!(XbiPortal::write((H256::repeat_byte(1), result.clone())));
+        assert!(!remarked(&result));
+    });
+}
+
+/// A request to the destination, sent by the account `1`
+fn promised_request(dest_para_id: u32, nonce: u32) -> Message {
+    Message::Request(request(3333, dest_para_id, nonce, Some(origin_of(1))))
+}
+
+fn resolved_with(result: XbiResult) -> Call {
+    Call::Promised(promised::Call::then { result })
+}
+
+fn joined_with(results: Vec<XbiResult>) -> Call {
+    Call::Promised(promised::Call::join { results })
+}
+
+#[test]
+fn promises_are_resolved_with_the_result() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let request = promised_request(1, 0);
+        let hash = request.get_metadata().get_id();
+
+        assert_ok!(<AsyncSender<Test> as PromiseDelegate<_, _>>::then(
+            request,
+            CallPromise(resolved_with),
+        ));
+        assert_eq!(PromisedRequests::<Test>::get(hash), Some(hash));
+
+        let result = XbiResult {
+            output: vec![1, 2, 3],
+            ..Default::default()
+        };
+        assert_ok!(XbiPortal::write((hash, result.clone())));
+        assert_eq!(promised::Resolved::<Test>::get(1), vec![result]);
+        assert!(!Promises::<Test>::contains_key(hash));
+        assert!(!PromisedRequests::<Test>::contains_key(hash));
+        System::assert_has_event(Event::XbiPortal(crate::Event::XbiPromiseResolved {
+            hash,
+            result: Ok(()),
+        }));
+    });
+}
+
+#[test]
+fn promises_heavier_than_a_response_are_not_dispatched() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        ResponseWeightLimit::set(0);
+        let request = promised_request(1, 0);
+        let hash = request.get_metadata().get_id();
+
+        assert_ok!(<AsyncSender<Test> as PromiseDelegate<_, _>>::then(
+            request,
+            CallPromise(resolved_with),
+        ));
+        assert_ok!(XbiPortal::write((hash, XbiResult::default())));
+        assert!(promised::Resolved::<Test>::get(1).is_empty());
+        System::assert_has_event(Event::XbiPortal(crate::Event::XbiPromiseResolved {
+            hash,
+            result: Err(sp_runtime::DispatchError::Exhausted),
+        }));
+    });
+}
+
+#[test]
+fn joined_promises_wait_for_every_result() {
+    new_test_ext().execute_with(|| {
+        let requests = vec![promised_request(1, 0), promised_request(2, 0)];
+        let hashes = requests
+            .iter()
+            .map(|request| request.get_metadata().get_id())
+            .collect::<Vec<_>>();
+        let results = vec![
+            XbiResult {
+                output: vec![1],
+                ..Default::default()
+            },
+            XbiResult {
+                output: vec![2],
+                ..Default::default()
+            },
+        ];
+
+        assert_ok!(<AsyncSender<Test> as PromiseDelegate<_, _>>::join(
+            requests,
+            CallPromise(joined_with),
+        ));
+
+        // The results are given in the order of the requests, rather than the order they arrive in
+        assert_ok!(XbiPortal::write((hashes[1], results[1].clone())));
+        assert!(promised::Resolved::<Test>::get(1).is_empty());
+
+        assert_ok!(XbiPortal::write((hashes[0], results[0].clone())));
+        assert_eq!(promised::Resolved::<Test>::get(1), results);
+        assert!(!Promises::<Test>::contains_key(hashes[0]));
+    });
+}
+
+#[test]
+fn joined_requests_are_not_sent_if_any_of_them_fail() {
+    new_test_ext().execute_with(|| {
+        let mut unroutable = request(3333, 2, 0, None);
+        unroutable.metadata.route = vec![3333];
+
+        assert_err!(
+            XbiPortal::send_join(
+                &1,
+                xp_channel::ExecutionType::Async,
+                vec![request(3333, 1, 0, None), unroutable],
+                CallPromise(joined_with),
+            ),
+            Error::<Test>::InvalidRoute
+        );
+        assert_eq!(XbiPortal::message_nonce(1), 0);
+        assert_eq!(XbiRequests::<Test>::iter().count(), 0);
+        assert_eq!(get_len!(), 0);
+
+        assert_ok!(XbiPortal::send_join(
+            &1,
+            xp_channel::ExecutionType::Async,
+            vec![request(3333, 1, 0, None), request(3333, 2, 0, None)],
+            CallPromise(joined_with),
+        ));
+        assert_eq!(XbiPortal::message_nonce(1), 2);
+        assert_eq!(get_len!(), 2);
+    });
+}
+
+#[test]
+fn promises_must_take_their_result_last() {
+    new_test_ext().execute_with(|| {
+        fn remarked(_result: XbiResult) -> Call {
+            Call::System(frame_system::Call::remark { remark: vec![1] })
+        }
+
+        assert_err!(
+            <AsyncSender<Test> as PromiseDelegate<_, _>>::then(
+                promised_request(1, 0),
+                CallPromise(remarked),
+            ),
+            sp_runtime::DispatchError::Other(
+                "The promise must take its result as the last argument of its call"
+            )
+        );
+    });
+}
+
+/// A request from parachain 1 to parachain 4, routed through this parachain
+fn routed_request(route: Vec<u32>) -> XbiFormat {
+    let mut format = request(1, 4, 0, Some(AccountId32::new([1u8; 32])));
+    format.metadata.timeouts = xp_format::Timeouts::new(
+        Some(xp_format::ActionNotificationTimeouts {
+            action: 1,
+            notification: 0,
+        }),
+        None,
+        None,
+        None,
+    );
+    format.metadata.route = route;
+    format.metadata.progress(Timestamp::Submitted(1));
+    format
+}
+
+/// Relay messages between the given peers
+fn relay_between(peers: &[u32]) {
+    for peer in peers {
+        assert_ok!(XbiPortal::set_relay_peer(Origin::root(), *peer, true));
+    }
+}
+
+#[test]
+fn routed_requests_are_forwarded_to_the_next_hop() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(10);
+        relay_between(&[1, 4]);
+        let format = routed_request(vec![3333]);
+        let hash = format.metadata.get_id();
+
+        assert_ok!(XbiPortal::receive(
+            Origin::signed(SIBLING_ACCOUNT_OFFSET + 1),
+            VersionedMessage::Request(format.into())
+        ));
+        System::assert_last_event(Event::XbiPortal(crate::Event::XbiForwarded {
+            hash,
+            next_hop: 4,
+        }));
+        match <Queue<Pallet<Test>>>::default().peek() {
+            Some((Message::Request(format), QueueSignal::PendingRequest)) => {
+                assert_eq!(format.metadata.get_timesheet().hops, vec![(3333, 10)]);
+                assert_eq!(format.metadata.get_timesheet().delivered, None);
+            }
+            item => panic!("Unexpected queue item: {:?}", item),
+        }
+        // Forwarded requests are not handled, nor is the peer version of their source noted
+        assert!(!PeerVersions::<Test>::contains_key(1));
+
+        // The source times the request, the hop only transports it
+        assert_ok!(XbiPortal::process_queue(
+            <Test as frame_system::Config>::Origin::root()
+        ));
+        assert!(!System::events()
+            .iter()
+            .any(|r| matches!(r.event, Event::XbiPortal(crate::Event::XbiTimedOut { .. }))));
+        assert!(DeadLetters::<Test>::contains_key(hash));
+        assert!(!XbiResponses::<Test>::contains_key(hash));
+    });
+}
+
+#[test]
+fn routed_responses_are_forwarded_to_the_next_hop() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        relay_between(&[2, 4]);
+        let mut metadata = routed_request(vec![2, 3333]).metadata;
+        xs_channel::receiver::frame::invert_destination_from_message(&mut metadata);
+        let hash = metadata.get_id();
+
+        assert_ok!(XbiPortal::receive(
+            Origin::signed(SIBLING_ACCOUNT_OFFSET + 4),
+            VersionedMessage::Response(Default::default(), metadata)
+        ));
+        System::assert_last_event(Event::XbiPortal(crate::Event::XbiForwarded {
+            hash,
+            next_hop: 2,
+        }));
+        assert!(matches!(
+            <Queue<Pallet<Test>>>::default().peek(),
+            Some((Message::Response(..), QueueSignal::PendingResponse))
+        ));
+        assert!(!XbiResponses::<Test>::contains_key(hash));
+    });
+}
+
+#[test]
+fn messages_are_only_forwarded_between_relay_peers() {
+    new_test_ext().execute_with(|| {
+        let receive = || {
+            XbiPortal::receive(
+                Origin::signed(SIBLING_ACCOUNT_OFFSET + 1),
+                VersionedMessage::Request(routed_request(vec![3333]).into()),
+            )
+        };
+
+        assert!(XbiPortal::set_relay_peer(Origin::signed(1), 1, true).is_err());
+        assert_err!(receive(), Error::<Test>::NotRelayPeer);
+
+        // Both the previous and the next hop must be relay peers
+        assert_ok!(XbiPortal::set_relay_peer(Origin::root(), 1, true));
+        assert_err!(receive(), Error::<Test>::NotRelayPeer);
+        assert_ok!(XbiPortal::set_relay_peer(Origin::root(), 4, true));
+        assert_ok!(receive());
+        assert_eq!(get_len!(), 1);
+
+        assert_ok!(XbiPortal::set_relay_peer(Origin::root(), 4, false));
+        System::assert_last_event(Event::XbiPortal(crate::Event::RelayPeerUpdated {
+            para_id: 4,
+            allowed: false,
+        }));
+        assert!(!RelayPeers::<Test>::contains_key(4));
+        assert_err!(receive(), Error::<Test>::NotRelayPeer);
+    });
+}
+
+#[test]
+fn forwarding_is_paid_for_by_the_fees_of_the_message() {
+    new_test_ext().execute_with(|| {
+        ForwardingFee::set(10);
+        relay_between(&[1, 4]);
+        let previous_hop = SIBLING_ACCOUNT_OFFSET + 1;
+        let _ = Balances::deposit_creating(&previous_hop, 1000);
+        let receive = |fees| {
+            let mut format = routed_request(vec![3333]);
+            format.metadata.fees = fees;
+            XbiPortal::receive(
+                Origin::signed(previous_hop),
+                VersionedMessage::Request(format.into()),
+            )
+        };
+
+        assert_err!(
+            receive(xp_format::Fees::new(None, Some(5), Some(4))),
+            Error::<Test>::ForwardingFeeNotCovered
+        );
+        assert_eq!(Balances::free_balance(previous_hop), 1000);
+
+        assert_ok!(receive(xp_format::Fees::new(None, Some(100), Some(50))));
+        assert_eq!(Balances::free_balance(previous_hop), 990);
+        assert_eq!(Balances::free_balance(XcmSovereignOrigin::get()), 10);
+        match <Queue<Pallet<Test>>>::default().peek() {
+            Some((Message::Request(format), QueueSignal::PendingRequest)) => {
+                assert_eq!(format.metadata.fees.get_aggregated_cost(), 10);
+            }
+            item => panic!("Unexpected queue item: {:?}", item),
+        }
+    });
+}
+
+#[test]
+fn messages_with_invalid_routes_are_rejected() {
+    new_test_ext().execute_with(|| {
+        assert_err!(
+            XbiPortal::receive(
+                Origin::signed(SIBLING_ACCOUNT_OFFSET + 1),
+                VersionedMessage::Request(routed_request(vec![3333, 2, 3333]).into())
+            ),
+            Error::<Test>::InvalidRoute
+        );
+        assert_err!(
+            XbiPortal::receive(
+                Origin::signed(SIBLING_ACCOUNT_OFFSET + 1),
+                VersionedMessage::Request(routed_request(vec![3333, 4]).into())
+            ),
+            Error::<Test>::InvalidRoute
+        );
+        assert_eq!(get_len!(), 0);
+    });
+}
+
+/// A request from `src` that expires a block after each of its timeouts
+fn expiring_request(src: u32, nonce: u32) -> VersionedMessage {
+    let timeout = Some(xp_format::ActionNotificationTimeouts {
+        action: 1,
+        notification: 0,
+    });
+    let mut format = request(src, 3333, nonce, Some(AccountId32::new([1u8; 32])));
+    format.metadata.timeouts =
+        xp_format::Timeouts::new(timeout.clone(), timeout.clone(), timeout.clone(), timeout);
+    VersionedMessage::Request(format.into())
+}
+
+#[test]
+fn requests_are_only_executed_once() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        let peer = Origin::signed(SIBLING_ACCOUNT_OFFSET + 1);
+
+        assert_ok!(XbiPortal::receive(peer.clone(), expiring_request(1, 0)));
+        assert_err!(
+            XbiPortal::receive(peer.clone(), expiring_request(1, 0)),
+            Error::<Test>::DuplicateRequest
+        );
+
+        // The record is kept for the lifetime of the request
+        System::set_block_number(5);
+        assert_err!(
+            XbiPortal::receive(peer.clone(), expiring_request(1, 0)),
+            Error::<Test>::DuplicateRequest
+        );
+        System::set_block_number(6);
+        assert_ok!(XbiPortal::receive(peer, expiring_request(1, 0)));
+        assert_eq!(crate::ExecutedRequests::<Test>::iter_prefix(1).count(), 1);
+    });
+}
+
+#[test]
+fn executed_requests_are_remembered_for_at_most_the_retention() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        MaxExecutedRetention::set(2);
+        let peer = Origin::signed(SIBLING_ACCOUNT_OFFSET + 1);
+
+        assert_ok!(XbiPortal::receive(peer.clone(), expiring_request(1, 0)));
+        System::set_block_number(3);
+        assert_err!(
+            XbiPortal::receive(peer.clone(), expiring_request(1, 0)),
+            Error::<Test>::DuplicateRequest
+        );
+        System::set_block_number(4);
+        assert_ok!(XbiPortal::receive(peer, expiring_request(1, 0)));
+    });
+}
+
+#[test]
+fn expired_executed_requests_are_pruned_a_limited_number_at_a_time() {
+    new_test_ext().execute_with(|| {
+        System::set_block_number(1);
+        ExecutedPruneLimit::set(2);
+        for (src, nonce) in [(1, 0), (1, 1), (2, 0)] {
+            assert_ok!(XbiPortal::receive(
+                Origin::signed(SIBLING_ACCOUNT_OFFSET + src as u64),
+                expiring_request(src, nonce)
+            ));
+        }
+        System::set_block_number(6);
+        assert_ok!(XbiPortal::receive(
+            Origin::signed(SIBLING_ACCOUNT_OFFSET + 1),
+            expiring_request(1, 2)
+        ));
+
+        XbiPortal::prune_executed_requests(6);
+        assert_eq!(crate::ExecutedRequests::<Test>::iter().count(), 2);
+        assert!(ExecutedCursor::<Test>::exists());
+
+        // The next check continues after the records already checked, and only the unexpired one is kept
+        XbiPortal::prune_executed_requests(6);
+        assert_eq!(
+            crate::ExecutedRequests::<Test>::iter()
+                .map(|(src, _, expiry)| (src, expiry))
+                .collect::<Vec<_>>(),
+            vec![(1, 10)]
+        );
+    });
+}
+
+#[test]
+fn messages_are_only_received_from_the_previous_hop() {
+    new_test_ext().execute_with(|| {
+        relay_between(&[2, 4]);
+        let msg = || VersionedMessage::Request(routed_request(vec![2, 3333]).into());
+
+        // The source is not the hop before us
+        assert_err!(
+            XbiPortal::receive(Origin::signed(SIBLING_ACCOUNT_OFFSET + 1), msg()),
+            Error::<Test>::UnexpectedPeer
+        );
+        assert_ok!(XbiPortal::receive(
+            Origin::signed(SIBLING_ACCOUNT_OFFSET + 2),
+            msg()
+        ));
+    });
+}
+
+#[test]
+fn only_relay_peers_can_relay_messages_from_another_source() {
+    new_test_ext().execute_with(|| {
+        // Parachain 2 claims to have relayed a request from parachain 1
+        let mut format = request(1, 3333, 0, Some(AccountId32::new([1u8; 32])));
+        format.metadata.route = vec![2];
+        let receive = || {
+            XbiPortal::receive(
+                Origin::signed(SIBLING_ACCOUNT_OFFSET + 2),
+                VersionedMessage::Request(format.clone().into()),
+            )
+        };
+
+        assert_err!(receive(), Error::<Test>::NotRelayPeer);
+        relay_between(&[2]);
+        assert_ok!(receive());
+    });
+}
+
+#[test]
+fn local_origins_cannot_receive_messages_unless_allowed() {
+    new_test_ext().execute_with(|| {
+        relay_between(&[1, 4]);
+        let msg = || VersionedMessage::Request(routed_request(vec![3333]).into());
+
+        assert_err!(
+            XbiPortal::receive(Origin::signed(1), msg()),
+            sp_runtime::DispatchError::BadOrigin
+        );
+        assert_err!(
+            XbiPortal::receive(Origin::root(), msg()),
+            sp_runtime::DispatchError::BadOrigin
+        );
+        assert_eq!(get_len!(), 0);
+
+        AllowLocalReceive::set(true);
+        assert_ok!(XbiPortal::receive(Origin::signed(1), msg()));
+        assert_eq!(get_len!(), 1);
+    });
+}